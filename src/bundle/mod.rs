@@ -0,0 +1,260 @@
+use std::{collections::{BTreeMap, HashMap}, fs, path::Path, sync::Mutex};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use rand::{rngs::OsRng, RngCore};
+
+use crate::{config::{self, get_basedir, get_manifest_file, Manifest}, error::{Error, Result}, file};
+
+lazy_static! {
+    // 已校验的包，key: 包的路径，同一个包只计算一次sha256
+    static ref PACKAGES: Mutex<HashMap<String, std::result::Result<(), String>>> = Mutex::new(HashMap::new());
+}
+
+// 清单签名文件：<manifest-file>.sig，内容为 base64 编码的 ed25519 签名
+pub fn signature_file(manifest_file: &str) -> String {
+    format!("{}.sig", manifest_file)
+}
+
+// 密钥文件内容为 base64 编码的32字节
+fn read_key(file: &str) -> Result<[u8; 32]> {
+    let contents = fs::read_to_string(file).map_err(|e| Error::Verify(format!("{}: {}", file, e)))?;
+    let bytes = STANDARD.decode(contents.trim()).map_err(|e| Error::Verify(format!("{}: {}", file, e)))?;
+    bytes.try_into().map_err(|_| Error::Verify(format!("{}: not an ed25519 key", file)))
+}
+
+fn digest(path: &Path) -> Result<String> {
+    if !path.exists() {
+        return Err(Error::NotFound(path.display().to_string()));
+    }
+    sha256::try_digest(path).map_err(|e| Error::Verify(format!("{}: {}", path.display(), e)))
+}
+
+// 校验清单签名：指定 --trusted-key 时清单必须由该密钥签名，且列出每个包及文件的sha256
+// 未指定时，清单已签名或包含sha256则拒绝执行，无法确认清单未被篡改
+pub fn verify_manifest() -> Result<()> {
+    let manifest_file = get_manifest_file();
+    let sig_file = signature_file(&manifest_file);
+    let key_file = match config::get_trusted_key() {
+        Some(k) => k,
+        None => {
+            if Path::new(&sig_file).exists() {
+                return Err(Error::Verify(format!("Manifest {} is signed ({}), specify --trusted-key to verify it", manifest_file, sig_file)));
+            }
+            let checksummed = config::METADATA.ds.values().chain(config::METADATA.dt.values())
+                .any(|m| m.sha256.is_some() || !m.checksums.is_empty());
+            if checksummed {
+                return Err(Error::Verify(format!("Manifest {} lists sha256 but is not signed, sign it with monica sign and specify --trusted-key", manifest_file)));
+            }
+            warn!("Manifest {} is not signed, --trusted-key not specified", manifest_file);
+            return Ok(());
+        }
+    };
+
+    let key = VerifyingKey::from_bytes(&read_key(&key_file)?).map_err(|e| Error::Verify(format!("{}: {}", key_file, e)))?;
+    let contents = fs::read(&manifest_file)?;
+    let sig = fs::read_to_string(&sig_file).map_err(|e| Error::Verify(format!("Manifest {} is not signed, {}: {}", manifest_file, sig_file, e)))?;
+    if !verify_signature(&key, &contents, &sig, &sig_file)? {
+        error!("Manifest {} signature verification failed <<<", manifest_file);
+        return Err(Error::Verify(format!("Manifest {} signature does not match {}", manifest_file, key_file)));
+    }
+    info!("Manifest {} signature verified with {}", manifest_file, key_file);
+
+    check_checksums(config::METADATA.ds.values().chain(config::METADATA.dt.values()))
+}
+
+// 对清单内容签名，返回 base64 编码的签名
+fn sign(key: &SigningKey, contents: &[u8]) -> String {
+    STANDARD.encode(key.sign(contents).to_bytes())
+}
+
+// 校验 base64 编码的签名，签名文件格式错误时返回 Err，签名不匹配时返回 false
+fn verify_signature(key: &VerifyingKey, contents: &[u8], sig: &str, sig_file: &str) -> Result<bool> {
+    let sig = STANDARD.decode(sig.trim()).map_err(|e| Error::Verify(format!("{}: {}", sig_file, e)))?;
+    let sig = Signature::from_slice(&sig).map_err(|e| Error::Verify(format!("{}: {}", sig_file, e)))?;
+    Ok(key.verify_strict(contents, &sig).is_ok())
+}
+
+// 签名的清单必须包含完整的sha256
+fn check_checksums<'a>(manifests: impl Iterator<Item = &'a Manifest>) -> Result<()> {
+    for m in manifests {
+        if m.sha256.is_none() {
+            return Err(Error::Verify(format!("Manifest {} has no sha256 for package {}", m.key, m.package)));
+        }
+        if let Some(f) = m.file.iter().find(|f| !m.checksums.contains_key(*f)) {
+            return Err(Error::Verify(format!("Manifest {} has no sha256 for file {}", m.key, f)));
+        }
+    }
+    Ok(())
+}
+
+// 解压前校验包的sha256
+pub fn verify_package(manifest: &Manifest, rid: usize) -> Result<()> {
+    let expected = match &manifest.sha256 {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+    let path = Path::new(&get_basedir()).join(&manifest.package);
+    let key = path.display().to_string();
+
+    let mut packages = PACKAGES.lock().unwrap();
+    let result = match packages.get(&key) {
+        Some(r) => r.clone(),
+        None => {
+            let r = match digest(&path) {
+                Ok(actual) if &actual == expected => Ok(()),
+                Ok(actual) => Err(format!("Package {} sha256 mismatch, expected {}, actual {}", key, expected, actual)),
+                Err(e) => Err(e.to_string()),
+            };
+            packages.insert(key.clone(), r.clone());
+            r
+        },
+    };
+    match result {
+        Ok(()) => {
+            info!("xlsx:Line: {:<2} Package {}, sha256 verified", rid, key);
+            Ok(())
+        },
+        Err(cause) => {
+            error!("xlsx:Line: {:<2} {} <<<", rid, cause);
+            Err(Error::Verify(cause))
+        },
+    }
+}
+
+// 解压后校验各文件的sha256
+pub fn verify_files(manifest: &Manifest, rid: usize) -> Result<()> {
+    for f in manifest.file.iter() {
+        let expected = match manifest.checksums.get(f) {
+            Some(s) => s,
+            None => continue,
+        };
+        let local_file = Path::new(&get_basedir()).join(&manifest.dir).join(f);
+        let actual = digest(&local_file)?;
+        if &actual != expected {
+            error!("xlsx:Line: {:<2} File {}, sha256 mismatch <<<", rid, local_file.display());
+            return Err(Error::Verify(format!("File {} sha256 mismatch, expected {}, actual {}", local_file.display(), expected, actual)));
+        }
+    }
+    Ok(())
+}
+
+// 生成密钥对：私钥写入 key，公钥写入 <key>.pub
+fn generate_key(key_file: &str) -> Result<SigningKey> {
+    if Path::new(key_file).exists() {
+        return Err(Error::Verify(format!("{} already exists", key_file)));
+    }
+    let mut seed = [0u8; 32];
+    OsRng.fill_bytes(&mut seed);
+    let key = SigningKey::from_bytes(&seed);
+    fs::write(key_file, STANDARD.encode(seed))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(key_file, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::write(format!("{}.pub", key_file), STANDARD.encode(key.verifying_key().as_bytes()))?;
+    Ok(key)
+}
+
+// monica sign：解压清单中的每个包，写入包及文件的sha256，再对清单签名
+pub fn handle_command_sign(key_file: &str, generate: bool) -> Result<()> {
+    let key = if generate {
+        generate_key(key_file)?
+    } else {
+        SigningKey::from_bytes(&read_key(key_file)?)
+    };
+
+    let basedir = get_basedir();
+    let manifest_file = get_manifest_file();
+    let json = fs::read_to_string(&manifest_file)?;
+    let mut meta: serde_json::Value = serde_json::from_str(&json).map_err(|e| Error::Verify(format!("{}: {}", manifest_file, e)))?;
+
+    let mut packages: HashMap<String, String> = HashMap::new();
+    for section in ["ds", "dt"] {
+        let entries = match meta.get_mut(section).and_then(|v| v.as_object_mut()) {
+            Some(e) => e,
+            None => continue,
+        };
+        for (name, value) in entries.iter_mut() {
+            let m: Manifest = serde_json::from_value(value.clone()).map_err(|e| Error::Verify(format!("{}: {}", name, e)))?;
+
+            let sha256 = match packages.get(&m.package) {
+                Some(s) => s.clone(),
+                None => {
+                    let s = digest(&Path::new(&basedir).join(&m.package))?;
+                    file::extract_compressed_files(&basedir, &m.package, &m.dir, 0)?;
+                    packages.insert(m.package.clone(), s.clone());
+                    s
+                },
+            };
+            let mut checksums = BTreeMap::new();
+            for f in m.file.iter() {
+                checksums.insert(f.clone(), digest(&Path::new(&basedir).join(&m.dir).join(f))?);
+            }
+            value["sha256"] = serde_json::Value::from(sha256);
+            value["checksums"] = serde_json::to_value(checksums).unwrap();
+            info!("Manifest {}, {} files", name, m.file.len());
+        }
+    }
+
+    let contents = format!("{}\n", serde_json::to_string_pretty(&meta).unwrap());
+    fs::write(&manifest_file, &contents)?;
+    let sig_file = signature_file(&manifest_file);
+    fs::write(&sig_file, sign(&key, contents.as_bytes()))?;
+
+    println!("Manifest file: {}", manifest_file);
+    println!("Signature file: {}", sig_file);
+    println!("Public key: {}", STANDARD.encode(key.verifying_key().as_bytes()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"{"package": "pkg/ds.tar.gz", "dir": "ds", "file": ["bin/pmon", "lib/libdbps.so"]}"#;
+
+    fn manifest(sha256: Option<&str>, checksums: &[&str]) -> Manifest {
+        let mut m: Manifest = serde_json::from_str(MANIFEST).unwrap();
+        m.key = String::from("ORACLE_19.3.0.0.0.Linux.x86_64");
+        m.sha256 = sha256.map(String::from);
+        m.checksums = checksums.iter().map(|f| (f.to_string(), String::from("0"))).collect();
+        m
+    }
+
+    #[test]
+    fn signature_round_trip() {
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let contents = b"{\"ds\": {}}\n";
+        let sig = sign(&key, contents);
+        assert!(verify_signature(&key.verifying_key(), contents, &format!("{}\n", sig), "m.sig").unwrap());
+    }
+
+    #[test]
+    fn signature_rejects_tampered_manifest_or_other_key() {
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let other = SigningKey::from_bytes(&[8u8; 32]);
+        let sig = sign(&key, b"{\"ds\": {}}\n");
+        assert!(!verify_signature(&key.verifying_key(), b"{\"ds\": {\"x\": 1}}\n", &sig, "m.sig").unwrap());
+        assert!(!verify_signature(&other.verifying_key(), b"{\"ds\": {}}\n", &sig, "m.sig").unwrap());
+        assert!(verify_signature(&key.verifying_key(), b"", "not base64!", "m.sig").is_err());
+        assert!(verify_signature(&key.verifying_key(), b"", &STANDARD.encode([0u8; 10]), "m.sig").is_err());
+    }
+
+    #[test]
+    fn signed_manifest_lists_every_sha256() {
+        let complete = manifest(Some("abc"), &["bin/pmon", "lib/libdbps.so"]);
+        assert!(check_checksums([&complete].into_iter()).is_ok());
+
+        let no_package = manifest(None, &["bin/pmon", "lib/libdbps.so"]);
+        let cause = check_checksums([&complete, &no_package].into_iter()).unwrap_err().to_string();
+        assert!(cause.contains("package pkg/ds.tar.gz"), "{}", cause);
+
+        let no_file = manifest(Some("abc"), &["bin/pmon"]);
+        let cause = check_checksums([&no_file].into_iter()).unwrap_err().to_string();
+        assert!(cause.contains("file lib/libdbps.so"), "{}", cause);
+    }
+}
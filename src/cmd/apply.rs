@@ -1,16 +1,16 @@
-use std::{path::Path, sync::{Arc, Mutex}};
+use std::{collections::HashMap, path::Path, sync::{Arc, Mutex}};
 
 use log::info;
 use tokio::runtime;
-use crate::{cmd, config::{self, Manifest, Server}, db, file, ssh};
+use crate::{bundle, cmd, config::{self, Manifest, Server}, db, error::{Error, Result}, file::{self, path_join}, report, ssh};
 
-use super::clean_monica_cache_file;
+use super::{batch, clean_monica_cache_file, health, plan::Plan, registry, summary::Summary, wave};
 
 
 // 升级事件处理
-pub async fn handle_command_xpatch(worker_threads: usize) {
+pub async fn handle_command_xpatch(worker_threads: usize, servers: &[&'static Server]) -> Result<Summary> {
 
-    let dbc = db::Client::new(&config::get_db_info().unwrap()).await;
+    let dbc = db::Client::new(&config::get_db_info().unwrap()).await?;
     // 创建线程池
     let rt = runtime::Builder::new_multi_thread()
             .worker_threads(worker_threads)
@@ -20,60 +20,128 @@ pub async fn handle_command_xpatch(worker_threads: usize) {
             .build()
             .unwrap();
 
-    let counter = Arc::new(Mutex::new(config::GLOBAL_CONFIG.servers.len()));
-    let xlsx_checksum = file::sha256sum(Path::new(&config::get_input_file()).to_path_buf());
-    let mut handles = vec![];
-    for server in config::GLOBAL_CONFIG.servers.iter() {
-        let counter: Arc<Mutex<usize>> = Arc::clone(&counter);
-        let _dbc = dbc.clone();
-        let checksum = xlsx_checksum.clone();
-        let handle = rt.spawn(async move {
-            start_xpatch_worker(&checksum, &_dbc, counter, server).await;
-        });
-        handles.push(handle);
-    }
+    let size = servers.len();
+    let summary = Arc::new(Summary::new("Patch"));
+    let counter = Arc::new(Mutex::new(size));
+    let xlsx_checksum = batch::batch_id();
+
+    // 分批升级，上一批完成并检查通过后再开始下一批
+    let rollout = config::get_rollout();
+    let waves = wave::split(servers, &rollout);
+    let count = waves.len();
+    let mut stopped = false;
+    for (index, servers) in waves.iter().enumerate() {
+        if stopped {
+            // 停止后剩余的行不再执行
+            for server in servers.iter().copied() {
+                summary.record(server, Err(Error::Aborted));
+            }
+            continue;
+        }
+        if count > 1 {
+            info!("Wave {}/{}: rows {}", index + 1, count, servers.iter().map(|s| s.rid.to_string()).collect::<Vec<String>>().join(","));
+        }
+
+        let mut handles = vec![];
+        for server in servers.iter().copied() {
+            let counter: Arc<Mutex<usize>> = Arc::clone(&counter);
+            let summary = Arc::clone(&summary);
+            let _dbc = dbc.clone();
+            let checksum = xlsx_checksum.clone();
+            let handle = rt.spawn(async move {
+                let mut started = Vec::new();
+                if summary.aborted() {
+                    summary.record(server, Err(Error::Aborted));
+                    return (server.rid, started);
+                }
+                let result = start_xpatch_worker(&checksum, &_dbc, counter, size, server, &mut started).await;
+                summary.record(server, result);
+                (server.rid, started)
+            });
+            handles.push(handle);
+        }
+
+        // 本批各行已发出启动命令的目录
+        let mut started = HashMap::new();
+        for handle in handles {
+            let (rid, homes) = handle.await.unwrap();
+            started.insert(rid, homes);
+        }
 
-    for handle in handles {
-        handle.await.unwrap();
+        stopped = !wave::settle(servers, &started, index + 1, count, &rollout, &summary).await;
     }
 
     rt.shutdown_background();
 
-    // 写入本地清单文件
+    let summary = Arc::into_inner(summary).unwrap();
+    summary.print();
+    if config::is_dry_run() {
+        info!("Patch dry run completed, no changes were made.");
+        println!();
+        return Ok(summary);
+    }
+
+    // 写入本地清单文件，部分行失败时也需要记录，以便回退已升级的行
     file::write_local_inventory(&xlsx_checksum);
 
-    info!("Patch applied. Great!");
+    if summary.is_ok() {
+        info!("Patch applied. Great!");
+    }
     println!("");
 
+    Ok(summary)
 }
 
-// 启动任务
-async fn start_xpatch_worker(xlsx_checksum: &str, c: &db::Client, c0: Arc<Mutex<usize>>, s: &Server) {
+// 启动任务，已发出启动命令的 (角色, 目录) 记录到 started
+async fn start_xpatch_worker(xlsx_checksum: &str, c: &db::Client, c0: Arc<Mutex<usize>>, size: usize, s: &Server, started: &mut Vec<(usize, String)>) -> Result<()> {
     // 连接到复制机，需考虑异机部署
-    let mut ssh = ssh::Client::new(s);
+    let mut ssh = ssh::Client::new(s)?;
     // 打印进度
-    cmd::print_counter(c0);
+    cmd::print_counter(c0, size);
+
+    if config::is_target_role(config::ROLE_DS) {
+        let mut e = report::Entry::new("patch", s, config::ROLE_DS);
+        let result = start_ds_worker(&mut ssh, c, s, xlsx_checksum, &mut e).await;
+        started.extend(e.started_home());
+        report::push(e, &result);
+        result?;
+    }
 
-    start_ds_worker(&mut ssh, c, s, xlsx_checksum).await;
-    start_dt_worker(&mut ssh, s, xlsx_checksum);
-    start_jddm_worker(&mut ssh, s, xlsx_checksum);
+    if config::is_target_role(config::ROLE_DT) {
+        let mut e = report::Entry::new("patch", s, config::ROLE_DT);
+        let result = start_dt_worker(&mut ssh, s, xlsx_checksum, &mut e);
+        started.extend(e.started_home());
+        report::push(e, &result);
+        result?;
+    }
+
+    if config::is_target_role(config::ROLE_JDDM) {
+        let mut e = report::Entry::new("patch", s, config::ROLE_JDDM);
+        let result = start_jddm_worker(&mut ssh, s, xlsx_checksum, &mut e);
+        started.extend(e.started_home());
+        report::push(e, &result);
+        result?;
+    }
 
     info!("xlsx:Line: {:<2} Host: {}, Service: {}, Patch completed", &s.rid, &s.hostname, &s.service_name);
+    Ok(())
 }
 
 
-fn start_dt_worker(ssh: &mut ssh::Client, s: &Server, xlsx_checksum: &str){
+fn start_dt_worker(ssh: &mut ssh::Client, s: &Server, xlsx_checksum: &str, e: &mut report::Entry) -> Result<()> {
 
     let input = match &s.dst_type {
         Some(s) => s,
-        None => return
+        None => return Ok(())
     };
+    e.begin();
 
     if let Some(ckp) = file::read_checkpoint(s, config::ROLE_DT, xlsx_checksum) {
         // 查询到检查点
         cmd::log(s, &ckp.split(":").nth(0).unwrap(), &format!("Patch applied on {}", cmd::get_last_datetime(&ckp)));
         if !config::is_force() {
-            return;
+            e.set_status(report::STATUS_SKIPPED, &format!("Patch applied on {}", cmd::get_last_datetime(&ckp)));
+            return Ok(());
         }
         cmd::log(s, &ckp.split(":").nth(0).unwrap(), "Covering applied patch");
     }
@@ -82,52 +150,88 @@ fn start_dt_worker(ssh: &mut ssh::Client, s: &Server, xlsx_checksum: &str){
         Some(s) => s,
         None => {
             cmd::error(s, "<NONE>", "No such directory <<<");
-            return;
+            e.fail("No such directory");
+            return Err(Error::NotFound(path_join(&s.service_base_path, &s.service_name)));
         }
     };
+    e.dbps_home = Some(dbps_home.clone());
+    batch::migrate_remote_backupset(ssh, s, &dbps_home, xlsx_checksum);
+
+    if config::is_dry_run() {
+        let manifest = config::get_dt_manifest(input, ssh.get_ss_version(input, &dbps_home));
+        e.set_manifest(manifest);
+        e.set_status(report::STATUS_PLANNED, "");
+        print_patch_plan(ssh, s, config::ROLE_DT, &dbps_home, manifest, xlsx_checksum, None);
+        return Ok(());
+    }
 
     // 停止程序
-    let (starting, starting2) = ssh.kill_ps(&format!("{}/bin/", dbps_home));
+    let (starting, starting2) = cmd::stop(s, config::ROLE_DT, &dbps_home, ssh);
     cmd::log(s, &dbps_home, &format!("B-Start:{}, A-Start:{}", starting, starting2));
+    e.started_before = Some(starting);
+    e.started_after_stop = Some(starting2);
 
-    match config::get_dt_manifest(input, ssh.get_ss_version(&input, &dbps_home)) {
-        Some(manifest) => patch_remote_files(config::ROLE_DT, manifest, &dbps_home, ssh, &s, xlsx_checksum),
-        None => cmd::error(s, &dbps_home, "Oracle version read failed <<<")
+    // 升级失败时仍需启动任务，最后再返回错误
+    let manifest = config::get_dt_manifest(input, ssh.get_ss_version(&input, &dbps_home));
+    let result = match manifest {
+        Some(manifest) => patch_remote_files(config::ROLE_DT, manifest, &dbps_home, ssh, &s, xlsx_checksum, e),
+        None => {
+            cmd::error(s, &dbps_home, "Oracle version read failed <<<");
+            e.fail("Oracle version read failed");
+            Err(Error::Version(input.clone()))
+        }
+    };
+
+    // 上传或校验失败时文件已从临时备份集恢复，不再重置任务，仅按停止前的状态启动
+    if let Err(err) = result {
+        e.startup_issued = Some(starting);
+        restart_unpatched(config::ROLE_DT, &dbps_home, ssh, s, starting);
+        return Err(err);
     }
 
     // 文件上传完成后重置任务
     cmd::clean_dt(s, &dbps_home, &ssh);
 
-    if starting {
-        cmd::startup(s, &dbps_home, &ssh);
+    e.startup_issued = Some(starting);
+    let health = if starting {
+        health::startup_and_check(ssh, s, config::ROLE_DT, &dbps_home, manifest, e)
     } else {
         cmd::log(s, &dbps_home, "Non-Start, Skip start");
         // 清理垃圾文件
         clean_monica_cache_file(&dbps_home, &ssh);
-    }
+        Ok(())
+    };
+
+    restore_unhealthy(config::ROLE_DT, &dbps_home, ssh, s, xlsx_checksum, health, e)?;
+
+    // 记录补丁级别
+    registry::append(ssh, s, &dbps_home, registry::ACTION_APPLY, manifest, e);
 
     // 写入检查点文件
     file::write_checkpoint(&dbps_home, s, config::ROLE_DT, xlsx_checksum);
+    Ok(())
 
 }
 
-fn start_jddm_worker(ssh: &mut ssh::Client, s: &Server, xlsx_checksum: &str){
+fn start_jddm_worker(ssh: &mut ssh::Client, s: &Server, xlsx_checksum: &str, e: &mut report::Entry) -> Result<()> {
 
     let input = match &s.dst_type {
         Some(s) => s,
-        None => return
+        None => return Ok(())
     };
 
     // kafka类型
     if !input.starts_with(config::KFK_TYPE) {
-        return ;
+        return Ok(());
     }
+    e.begin();
 
     if let Some(ckp) = file::read_checkpoint(s, config::ROLE_JDDM, xlsx_checksum) {
         // 查询到检查点
         cmd::log(s, &ckp.split(":").nth(0).unwrap(), &format!("Patch applied on {}", cmd::get_last_datetime(&ckp)));
         if !config::is_force() {
-            return;
+            e.set_status(report::STATUS_SKIPPED, &format!("Patch applied on {}", cmd::get_last_datetime(&ckp)));
+            return Ok(());
         }
         cmd::log(s, &ckp.split(":").nth(0).unwrap(), "Covering applied patch");
     }
@@ -136,46 +240,76 @@ fn start_jddm_worker(ssh: &mut ssh::Client, s: &Server, xlsx_checksum: &str){
         Some(s) => s,
         None => {
             cmd::error(s, "<NONE>", "No such directory <<<");
-            return;
+            e.fail("No such directory");
+            return Err(Error::NotFound(path_join(&s.service_base_path, &s.service_name)));
         }
     };
+    e.dbps_home = Some(dbps_home.clone());
+    batch::migrate_remote_backupset(ssh, s, &dbps_home, xlsx_checksum);
+
+    if config::is_dry_run() {
+        let manifest = config::get_jddm_manifest(input);
+        e.set_manifest(Some(manifest));
+        e.set_status(report::STATUS_PLANNED, "");
+        print_patch_plan(ssh, s, config::ROLE_JDDM, &dbps_home, Some(manifest), xlsx_checksum, None);
+        return Ok(());
+    }
 
     // 停止程序
-    let (starting, starting2) = ssh.kill_ps(&format!("DPath={} ", dbps_home));
+    let (starting, starting2) = cmd::stop(s, config::ROLE_JDDM, &dbps_home, ssh);
     cmd::log(s, &dbps_home, &format!("B-Start: {}, A-Start: {}", starting, starting2));
+    e.started_before = Some(starting);
+    e.started_after_stop = Some(starting2);
 
     let manifest = config::get_jddm_manifest(input);
-    patch_remote_files(config::ROLE_JDDM, manifest, &dbps_home, ssh, &s, xlsx_checksum);
+    let result = patch_remote_files(config::ROLE_JDDM, manifest, &dbps_home, ssh, &s, xlsx_checksum, e);
 
     
+    // 上传或校验失败时文件已从临时备份集恢复，不再重置任务，仅按停止前的状态启动
+    if let Err(err) = result {
+        e.startup_issued = Some(starting);
+        restart_unpatched(config::ROLE_JDDM, &dbps_home, ssh, s, starting);
+        return Err(err);
+    }
+
     // 文件上传完成后重置任务
     cmd::clean_jddm(s, &dbps_home, &ssh);
 
-    if starting {
-        cmd::startup_jddm(s, &dbps_home, &ssh);
+    e.startup_issued = Some(starting);
+    let health = if starting {
+        health::startup_and_check(ssh, s, config::ROLE_JDDM, &dbps_home, Some(manifest), e)
     } else {
         cmd::log(s, &dbps_home, "Non-Start, Skip start");
         clean_monica_cache_file(&dbps_home, &ssh);
-    }
+        Ok(())
+    };
+
+    restore_unhealthy(config::ROLE_JDDM, &dbps_home, ssh, s, xlsx_checksum, health, e)?;
+
+    // 记录补丁级别
+    registry::append(ssh, s, &dbps_home, registry::ACTION_APPLY, Some(manifest), e);
 
     // 写入检查点文件
     file::write_checkpoint(&dbps_home, s, config::ROLE_JDDM, xlsx_checksum);
+    Ok(())
 
 
 }
 
-async fn start_ds_worker(ssh: &mut ssh::Client, c: &db::Client, s: &Server, xlsx_checksum: &str){
+async fn start_ds_worker(ssh: &mut ssh::Client, c: &db::Client, s: &Server, xlsx_checksum: &str, e: &mut report::Entry) -> Result<()> {
 
     let input = match &s.src_type {
         Some(s) => s,
-        None => return
+        None => return Ok(())
     };
-    
+    e.begin();
+
     if let Some(ckp) = file::read_checkpoint(s, config::ROLE_DS, xlsx_checksum) {
         // 查询到检查点
         cmd::log(s, &ckp.split(":").nth(0).unwrap(), &format!("Patch applied on {}", cmd::get_last_datetime(&ckp)));
         if !config::is_force() {
-            return;
+            e.set_status(report::STATUS_SKIPPED, &format!("Patch applied on {}", cmd::get_last_datetime(&ckp)));
+            return Ok(());
         }
         cmd::log(s, &ckp.split(":").nth(0).unwrap(), "Covering applied patch");
     }
@@ -184,52 +318,114 @@ async fn start_ds_worker(ssh: &mut ssh::Client, c: &db::Client, s: &Server, xlsx
         Some(s) => s,
         None => {
             cmd::error(s, "<NONE>", "No such directory <<<");
-            return;
+            e.fail("No such directory");
+            return Err(Error::NotFound(path_join(&s.service_base_path, &s.service_name)));
         }
     };
+    e.dbps_home = Some(dbps_home.clone());
+    batch::migrate_remote_backupset(ssh, s, &dbps_home, xlsx_checksum);
 
     // 从远端文件中获取位点信息
     let valid_log_pos;
     let yrba_dat;
     if cmd::current_log_position() {
-        (valid_log_pos, yrba_dat) = cmd::query_log_position(s, c.clone()).await;
+        (valid_log_pos, yrba_dat) = cmd::query_log_position(s, c.clone()).await?;
     } else {
         (valid_log_pos, yrba_dat) = cmd::read_log_position(&ssh, &dbps_home, s);
     }
         
+    if config::is_dry_run() {
+        let manifest = config::get_ds_manifest(input, ssh.get_ss_version(input, &dbps_home));
+        let yrba = if valid_log_pos { Some(yrba_dat.as_str()) } else { None };
+        e.set_manifest(manifest);
+        e.set_status(report::STATUS_PLANNED, "");
+        print_patch_plan(ssh, s, config::ROLE_DS, &dbps_home, manifest, xlsx_checksum, yrba);
+        return Ok(());
+    }
+
     // 停止程序
-    let (starting, starting2) = ssh.kill_ps(&format!("{}/bin/", dbps_home));
+    let (starting, starting2) = cmd::stop(s, config::ROLE_DS, &dbps_home, ssh);
     cmd::log(s, &dbps_home, &format!("B-Start:{}, A-Start:{}", starting, starting2));
+    e.started_before = Some(starting);
+    e.started_after_stop = Some(starting2);
 
-    match config::get_ds_manifest(input, ssh.get_ss_version(&input, &dbps_home)) {
-        Some(manifest) => patch_remote_files(config::ROLE_DS, manifest, &dbps_home, ssh, &s, xlsx_checksum),
-        None => cmd::error(s, &dbps_home, "Oracle version read failed <<<")
+    // 升级失败时仍需启动任务，最后再返回错误
+    let manifest = config::get_ds_manifest(input, ssh.get_ss_version(&input, &dbps_home));
+    let result = match manifest {
+        Some(manifest) => patch_remote_files(config::ROLE_DS, manifest, &dbps_home, ssh, &s, xlsx_checksum, e),
+        None => {
+            cmd::error(s, &dbps_home, "Oracle version read failed <<<");
+            e.fail("Oracle version read failed");
+            Err(Error::Version(input.clone()))
+        }
+    };
+
+    // 上传或校验失败时文件已从临时备份集恢复，不再重置任务，仅按停止前的状态启动
+    if let Err(err) = result {
+        e.startup_issued = Some(starting);
+        restart_unpatched(config::ROLE_DS, &dbps_home, ssh, s, starting);
+        return Err(err);
     }
 
     // 文件上传完成后重置任务
     cmd::clean_ds(s, &dbps_home, &ssh);
 
+    e.yrba_before = ssh.get_yrba(&dbps_home);
     if valid_log_pos {
         // 写入yrba文件
         cmd::update_yrba_file(s, &dbps_home, &yrba_dat, &ssh);
     }
+    e.yrba_after = ssh.get_yrba(&dbps_home);
 
-    if starting {
-        cmd::startup(s, &dbps_home, &ssh);
+    e.startup_issued = Some(starting);
+    let health = if starting {
+        health::startup_and_check(ssh, s, config::ROLE_DS, &dbps_home, manifest, e)
     } else {
         cmd::log(s, &dbps_home, "Non-Start, Skip start");
         clean_monica_cache_file(&dbps_home, &ssh);
-    }
+        Ok(())
+    };
+
+    restore_unhealthy(config::ROLE_DS, &dbps_home, ssh, s, xlsx_checksum, health, e)?;
+
+    // 记录补丁级别
+    registry::append(ssh, s, &dbps_home, registry::ACTION_APPLY, manifest, e);
 
     // 写入检查点文件
     file::write_checkpoint(&dbps_home, s, config::ROLE_DS, xlsx_checksum);
+    Ok(())
         
 }
 
 
+// 演练：输出升级计划
+fn print_patch_plan(ssh: &ssh::Client, s: &Server, role: usize, dbps_home: &str, manifest: Option<&Manifest>, 
+    xlsx_checksum: &str, yrba: Option<&str>) {
+
+    let mut plan = Plan::new(s, role, dbps_home, manifest);
+    let starting = plan.stop(ssh, s, role, dbps_home);
+    match manifest {
+        Some(manifest) => {
+            plan.upload(ssh, dbps_home, manifest);
+            plan.verify(ssh, dbps_home, xlsx_checksum);
+        },
+        None => plan.step("Read manifest", "Oracle version read failed <<<"),
+    }
+    plan.restart(s, role, starting, yrba);
+    plan.print();
+}
+
 // 升级文件：上传文件
 // 本地生成sha256sum.txt文件
-fn patch_remote_files(role: usize, manifest: &Manifest, dbps_home: &str, ssh: &mut ssh::Client, s: &Server, xlsx_checksum: &str){
+fn patch_remote_files(role: usize, manifest: &Manifest, dbps_home: &str, ssh: &mut ssh::Client, s: &Server, xlsx_checksum: &str, e: &mut report::Entry) -> Result<()> {
+
+    e.set_manifest(Some(manifest));
+
+    // 上传前再次校验文件的sha256，--skip-check 时也不跳过
+    if let Err(cause) = bundle::verify_files(manifest, s.rid) {
+        e.fail(&cause.to_string());
+        return Err(cause);
+    }
 
     // 里面记录了文件上传的断点信息
     ssh.remove_sha256sum_file(dbps_home);
@@ -244,15 +440,20 @@ fn patch_remote_files(role: usize, manifest: &Manifest, dbps_home: &str, ssh: &m
         let current = index+1;
 
         // 判断文件是否已经上传
-        if let Some(_) = file::file_checkpoint(s, role, xlsx_checksum, &s_local_file) {
+        if file::file_checkpoint(s, role, xlsx_checksum, &s_local_file).is_some() {
             // 文件已上传
             cmd::log(s, dbps_home, &format!("Upload [{}/{}] \"{}\" completed (disk cache)", current, counter, local_file_path));
             continue;
         }
 
         cmd::log(s, dbps_home, &format!("Upload [{}/{}] \"{}\"", current, counter, local_file_path));
-        if ssh.scp_send(local_file, remote_file, current, counter) {
+        if ssh.send_cached(local_file, remote_file, current, counter) {
             cmd::log(s, dbps_home, &format!("Upload [{}/{}] \"{}\" completed", current, counter, local_file_path));
+        } else {
+            // 上传失败，恢复远端文件
+            cmd::error(s, dbps_home, &format!("Upload [{}/{}] \"{}\" failed <<<", current, counter, local_file_path));
+            let cause = restore_remote_files(role, dbps_home, ssh, s, xlsx_checksum, &format!("Upload {} failed", local_file_path), e);
+            return Err(Error::Remote(cause));
         }
 
         // 写入断点文件
@@ -264,28 +465,96 @@ fn patch_remote_files(role: usize, manifest: &Manifest, dbps_home: &str, ssh: &m
     // f7dac4ade9ab40000593bbc7fde9f12f7350d6447e1f275d240333313a178570 bin/aaaa.monica   
     // xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx bin/bbbb.monica   
     // 校验通过后，可以将备份文件挪出到.monica目录中并写入 backupset.index 文件
-    if ssh.verify_sha256sum_file(dbps_home)  {
+    let verified = ssh.verify_sha256sum_file(dbps_home);
+    e.record_files(ssh, dbps_home, manifest, true);
+    if verified {
         cmd::log(s, dbps_home, "File check passed");
+        e.set_status(report::STATUS_SUCCESS, "");
 
         // 检查文件是否存在
         let (exists, backupset_file_name) = ssh.exists_backupset(xlsx_checksum, dbps_home);
         if exists {
             // 已经存在了，则不用再次mv，且不用写入备份的检查文件
             cmd::log(s, dbps_home, &format!("Generated backupset {}", backupset_file_name));
-            return;
+            e.backupset = Some(backupset_file_name);
+            return Ok(());
         }
 
         // 上传成功
         match ssh.gen_backupset(xlsx_checksum, dbps_home) {
             Ok(backupset_file_name) => {
                 cmd::log(s, dbps_home, &format!("Generated backupset {}", backupset_file_name));
+                e.backupset = Some(backupset_file_name);
             },
-            Err(e) => config::abnormal_exit_patch(&e)
+            Err(cause) => {
+                e.fail(&cause);
+                return Err(Error::Remote(cause));
+            }
         }
     } else {
         cmd::log(s, dbps_home, "File check failed");
+        let cause = restore_remote_files(role, dbps_home, ssh, s, xlsx_checksum, "File check failed", e);
+        return Err(Error::Verify(cause));
     }
 
     // ssh.remove_sha256sum_file(dbps_home);
+    Ok(())
+
+}
 
+// 升级后启动检查失败：--rollback-on-health-failure 时停止任务，通过备份集恢复远端文件后重新启动
+fn restore_unhealthy(role: usize, dbps_home: &str, ssh: &ssh::Client, s: &Server, xlsx_checksum: &str, health: Result<()>, e: &mut report::Entry) -> Result<()> {
+    let cause = match health {
+        Ok(_) => return Ok(()),
+        Err(Error::Health(cause)) => cause,
+        Err(other) => return Err(other),
+    };
+    if !config::get_health_check().is_some_and(|h| h.rollback) {
+        return Err(Error::Health(cause));
+    }
+
+    // jddm 的启动参数在启动后已被清理，需从运行中的进程重新获取
+    if role == config::ROLE_JDDM && ssh.write_jddm_starts_with(dbps_home) {
+        cmd::log(s, dbps_home, &format!("Jddm_starts_with written to {}", cmd::JDDM_START_WITH_FILE));
+    }
+    cmd::stop(s, role, dbps_home, ssh);
+
+    let cause = restore_remote_files(role, dbps_home, ssh, s, xlsx_checksum, &cause, e);
+    match role {
+        config::ROLE_JDDM => cmd::startup_jddm(s, dbps_home, ssh),
+        _ => cmd::startup(s, dbps_home, ssh),
+    }
+    Err(Error::Health(cause))
+}
+
+// 升级失败且文件已恢复：停止前运行中的任务重新启动，不做重置
+fn restart_unpatched(role: usize, dbps_home: &str, ssh: &ssh::Client, s: &Server, starting: bool) {
+    if starting {
+        match role {
+            config::ROLE_JDDM => cmd::startup_jddm(s, dbps_home, ssh),
+            _ => cmd::startup(s, dbps_home, ssh),
+        }
+    } else {
+        cmd::log(s, dbps_home, "Non-Start, Skip start");
+        clean_monica_cache_file(dbps_home, ssh);
+    }
+}
+
+// 升级失败：通过临时备份集恢复远端文件，并删除文件上传的断点，返回失败原因
+// 是否启动任务由调用方按停止前的状态决定
+fn restore_remote_files(role: usize, dbps_home: &str, ssh: &ssh::Client, s: &Server, xlsx_checksum: &str, cause: &str, e: &mut report::Entry) -> String {
+
+    file::remove_file_checkpoint(s, role, xlsx_checksum);
+
+    let cause = if ssh.exec_restore_backupset(dbps_home, xlsx_checksum) {
+        cmd::log(s, dbps_home, "Restored from temporary backupset");
+        e.restored = Some(true);
+        format!("{}, restored from temporary backupset", cause)
+    } else {
+        cmd::error(s, dbps_home, "Restore from temporary backupset failed <<<");
+        e.restored = Some(false);
+        format!("{}, restore from temporary backupset failed", cause)
+    };
+    e.fail(&cause);
+    cause
 }
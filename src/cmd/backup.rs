@@ -1,17 +1,17 @@
-use std::{path::Path, sync::{Arc, Mutex}};
+use std::sync::{Arc, Mutex};
 
 use log::info;
 use structopt::StructOpt;
 use tokio::runtime;
-use crate::{cmd::query_log_position, config::{self, Command, Manifest, Opt, Server}, db, file, ssh};
+use crate::{cmd::query_log_position, config::{self, Command, Manifest, Opt, Server}, db, error::{Error, Result}, file::{self, path_join}, report, ssh};
 
-use super::{error, get_last_datetime, log, print_counter, JDDM_START_WITH_FILE};
+use super::{batch, error, get_last_datetime, log, plan::Plan, print_counter, summary::Summary, JDDM_START_WITH_FILE};
 
 
 // 备份事件处理
-pub async fn handle_command_backup(worker_threads: usize){
+pub async fn handle_command_backup(worker_threads: usize, servers: &[&'static Server]) -> Result<Summary> {
 
-    let dbc = db::Client::new(&config::get_db_info().unwrap()).await;
+    let dbc = db::Client::new(&config::get_db_info().unwrap()).await?;
     // 创建线程池
     let rt = runtime::Builder::new_multi_thread()
             .worker_threads(worker_threads)
@@ -21,15 +21,23 @@ pub async fn handle_command_backup(worker_threads: usize){
             .build()
             .unwrap();
 
-    let counter = Arc::new(Mutex::new(config::GLOBAL_CONFIG.servers.len()));
-    let xlsx_checksum = file::sha256sum(Path::new(&config::get_input_file()).to_path_buf());
+    let size = servers.len();
+    let summary = Arc::new(Summary::new("Backup"));
+    let counter = Arc::new(Mutex::new(size));
+    let xlsx_checksum = batch::batch_id();
     let mut handles = vec![];
-    for server in config::GLOBAL_CONFIG.servers.iter() {
+    for server in servers.iter().copied() {
         let counter: Arc<Mutex<usize>> = Arc::clone(&counter);
+        let summary = Arc::clone(&summary);
         let _dbc = dbc.clone();
         let checksum = xlsx_checksum.clone();
         let handle = rt.spawn(async move {
-            start_backup_worker(&checksum, &_dbc, counter, server).await;
+            if summary.aborted() {
+                summary.record(server, Err(Error::Aborted));
+                return;
+            }
+            let result = start_backup_worker(&checksum, &_dbc, counter, size, server).await;
+            summary.record(server, result);
         });
         handles.push(handle);
     }
@@ -40,10 +48,16 @@ pub async fn handle_command_backup(worker_threads: usize){
 
     rt.shutdown_background();
 
-    info!("Backup completed. Great!");
+    let summary = Arc::into_inner(summary).unwrap();
+    summary.print();
+    if config::is_dry_run() {
+        info!("Backup dry run completed, no changes were made.");
+    } else if summary.is_ok() {
+        info!("Backup completed. Great!");
+    }
     println!("");
 
-
+    Ok(summary)
 }
 
 // 备份后生成 sha256sum 
@@ -54,37 +68,56 @@ pub async fn handle_command_backup(worker_threads: usize){
 // 4, ok,              ## 非集中部署
 // 
 // 如果上传过程中失败，则需要自动回退操作
-async fn start_backup_worker(xlsx_checksum: &str, c: &db::Client, c0: Arc<Mutex<usize>>, s: &Server){
+async fn start_backup_worker(xlsx_checksum: &str, c: &db::Client, c0: Arc<Mutex<usize>>, size: usize, s: &Server) -> Result<()> {
     
     
     // if current_log_position() {
     // }
     // 连接到复制机，需考虑异机部署
-    let ssh = ssh::Client::new(s);
+    let ssh = ssh::Client::new(s)?;
     
-    print_counter(c0);
+    print_counter(c0, size);
 
-    start_ds_worker(&ssh, c, s, xlsx_checksum).await;
-    start_dt_worker(&ssh, s, xlsx_checksum);
-    start_jddm_worker(&ssh, s, xlsx_checksum);
+    if config::is_target_role(config::ROLE_DS) {
+        let mut e = report::Entry::new("backup", s, config::ROLE_DS);
+        let result = start_ds_worker(&ssh, c, s, xlsx_checksum, &mut e).await;
+        report::push(e, &result);
+        result?;
+    }
 
-    info!("xlsx:Line: {:<2} Host: {}, Service: {}, Backup completed", &s.rid, &s.hostname, &s.service_name);
+    if config::is_target_role(config::ROLE_DT) {
+        let mut e = report::Entry::new("backup", s, config::ROLE_DT);
+        let result = start_dt_worker(&ssh, s, xlsx_checksum, &mut e);
+        report::push(e, &result);
+        result?;
+    }
+
+    if config::is_target_role(config::ROLE_JDDM) {
+        let mut e = report::Entry::new("backup", s, config::ROLE_JDDM);
+        let result = start_jddm_worker(&ssh, s, xlsx_checksum, &mut e);
+        report::push(e, &result);
+        result?;
+    }
 
+    info!("xlsx:Line: {:<2} Host: {}, Service: {}, Backup completed", &s.rid, &s.hostname, &s.service_name);
+    Ok(())
 }
 
 
-fn start_dt_worker(ssh: &ssh::Client, s: &Server, xlsx_checksum: &str){
+fn start_dt_worker(ssh: &ssh::Client, s: &Server, xlsx_checksum: &str, e: &mut report::Entry) -> Result<()> {
 
     let input = match &s.dst_type {
         Some(s) => s,
-        None => return
+        None => return Ok(())
     };
+    e.begin();
 
     if let Some(ckp) = file::read_backup_checkpoint(s, config::ROLE_DT, xlsx_checksum) {
         // 查询到检查点
         log(s, &ckp.split(":").nth(0).unwrap(), &format!("Backed up on {}", get_last_datetime(&ckp)));
         if !config::is_force() {
-            return;
+            e.set_status(report::STATUS_SKIPPED, &format!("Backed up on {}", get_last_datetime(&ckp)));
+            return Ok(());
         }
         log(s, &ckp.split(":").nth(0).unwrap(), "Covering backed up");
     }
@@ -93,48 +126,66 @@ fn start_dt_worker(ssh: &ssh::Client, s: &Server, xlsx_checksum: &str){
         Some(s) => s,
         None => {
             error(s, "<NONE>", "No such directory <<<");
-            return;
+            e.fail("No such directory");
+            return Err(Error::NotFound(path_join(&s.service_base_path, &s.service_name)));
         }
     };
+    e.dbps_home = Some(dbps_home.clone());
+    batch::migrate_remote_backupset(ssh, s, &dbps_home, xlsx_checksum);
+
+    if config::is_dry_run() {
+        let manifest = config::get_dt_manifest(input, ssh.get_ss_version(input, &dbps_home));
+        e.set_manifest(manifest);
+        e.set_status(report::STATUS_PLANNED, "");
+        print_backup_plan(ssh, s, config::ROLE_DT, &dbps_home, manifest, xlsx_checksum, None);
+        return Ok(());
+    }
 
     // 判断远端是否有备份集
     let (exists, remote_backupset_file) = ssh.exists_backupset(xlsx_checksum, &dbps_home);
     if exists {
         // 备份文件已存在
         log(s, &dbps_home, &format!("BackupSet: {} exists", remote_backupset_file));
+        e.backupset = Some(remote_backupset_file.clone());
+        e.set_status(report::STATUS_SKIPPED, &format!("BackupSet: {} exists", remote_backupset_file));
     } else {
 
         if let Some(manifest) = config::get_dt_manifest(input, ssh.get_ss_version(&input, &dbps_home)) {
-            backup_remote_files(xlsx_checksum, manifest, &dbps_home, &ssh, &s, false);
+            backup_remote_files(xlsx_checksum, manifest, &dbps_home, &ssh, &s, false, e)?;
         } else {
-            error(s, &dbps_home, "Oracle version read failed <<<")
+            error(s, &dbps_home, "Oracle version read failed <<<");
+            e.fail("Oracle version read failed");
+            return Err(Error::Version(input.clone()));
         }
 
     }
 
     // 写入检查点
     file::write_backup_checkpoint(&dbps_home, s, config::ROLE_DT, xlsx_checksum);
+    Ok(())
 
 }
 
 
-fn start_jddm_worker(ssh: &ssh::Client, s: &Server, xlsx_checksum: &str){
+fn start_jddm_worker(ssh: &ssh::Client, s: &Server, xlsx_checksum: &str, e: &mut report::Entry) -> Result<()> {
 
     let input = match &s.dst_type {
         Some(s) => s,
-        None => return
+        None => return Ok(())
     };
 
     // kafka类型
     if !input.starts_with(config::KFK_TYPE) {
-        return ;
+        return Ok(());
     }
+    e.begin();
 
     if let Some(ckp) = file::read_backup_checkpoint(s, config::ROLE_JDDM, xlsx_checksum) {
         // 查询到检查点
         log(s, &ckp.split(":").nth(0).unwrap(), &format!("Backed up on {}", get_last_datetime(&ckp)));
         if !config::is_force() {
-            return;
+            e.set_status(report::STATUS_SKIPPED, &format!("Backed up on {}", get_last_datetime(&ckp)));
+            return Ok(());
         }
         log(s, &ckp.split(":").nth(0).unwrap(), "Covering backed up");
     }
@@ -143,9 +194,20 @@ fn start_jddm_worker(ssh: &ssh::Client, s: &Server, xlsx_checksum: &str){
         Some(s) => s,
         None => {
             error(s, "<NONE>", "No such directory <<<");
-            return;
+            e.fail("No such directory");
+            return Err(Error::NotFound(path_join(&s.service_base_path, &s.service_name)));
         }
     };
+    e.dbps_home = Some(dbps_home.clone());
+    batch::migrate_remote_backupset(ssh, s, &dbps_home, xlsx_checksum);
+
+    if config::is_dry_run() {
+        let manifest = config::get_jddm_manifest(input);
+        e.set_manifest(Some(manifest));
+        e.set_status(report::STATUS_PLANNED, "");
+        print_backup_plan(ssh, s, config::ROLE_JDDM, &dbps_home, Some(manifest), xlsx_checksum, None);
+        return Ok(());
+    }
 
     // 将启动参数写入到 $dbps_home/bin/monica.started 中
     // ./startJddmKafkaEngine.sh start <service_name> <jddm_state>
@@ -158,30 +220,35 @@ fn start_jddm_worker(ssh: &ssh::Client, s: &Server, xlsx_checksum: &str){
     if exists {
         // 备份文件已存在
         log(s, &dbps_home, &format!("BackupSet: {} exists", remote_backupset_file));
+        e.backupset = Some(remote_backupset_file.clone());
+        e.set_status(report::STATUS_SKIPPED, &format!("BackupSet: {} exists", remote_backupset_file));
 
     } else {
         let manifest = config::get_jddm_manifest(input);
-        backup_remote_files(xlsx_checksum, manifest, &dbps_home, &ssh, &s, false);
+        backup_remote_files(xlsx_checksum, manifest, &dbps_home, &ssh, &s, false, e)?;
     }
 
     // 写入检查点
     file::write_backup_checkpoint(&dbps_home, s, config::ROLE_JDDM, xlsx_checksum);
+    Ok(())
 
 }
 
 
-async fn start_ds_worker(ssh: &ssh::Client, c: &db::Client, s: &Server, xlsx_checksum: &str){
+async fn start_ds_worker(ssh: &ssh::Client, c: &db::Client, s: &Server, xlsx_checksum: &str, e: &mut report::Entry) -> Result<()> {
 
     let input = match &s.src_type {
         Some(s) => s,
-        None => return
+        None => return Ok(())
     };
+    e.begin();
 
     if let Some(ckp) = file::read_backup_checkpoint(s, config::ROLE_DS, xlsx_checksum) {
         // 查询到检查点
         log(s, &ckp.split(":").nth(0).unwrap(), &format!("Backed up on {}", get_last_datetime(&ckp)));
         if !config::is_force() {
-            return;
+            e.set_status(report::STATUS_SKIPPED, &format!("Backed up on {}", get_last_datetime(&ckp)));
+            return Ok(());
         }
         log(s, &ckp.split(":").nth(0).unwrap(), "Covering backed up");
     }
@@ -190,43 +257,88 @@ async fn start_ds_worker(ssh: &ssh::Client, c: &db::Client, s: &Server, xlsx_che
         Some(s) => s,
         None => {
             error(s, "<NONE>", "No such directory <<<");
-            return;
+            e.fail("No such directory");
+            return Err(Error::NotFound(path_join(&s.service_base_path, &s.service_name)));
         }
     };
+    e.dbps_home = Some(dbps_home.clone());
+    batch::migrate_remote_backupset(ssh, s, &dbps_home, xlsx_checksum);
+
+    if config::is_dry_run() {
+        let (_, yrba_dat) = query_log_position(s, c.clone()).await?;
+        let manifest = config::get_ds_manifest(input, ssh.get_ss_version(input, &dbps_home));
+        e.set_manifest(manifest);
+        e.yrba_after = Some(yrba_dat.clone());
+        e.set_status(report::STATUS_PLANNED, "");
+        print_backup_plan(ssh, s, config::ROLE_DS, &dbps_home, manifest, xlsx_checksum, Some(&yrba_dat));
+        return Ok(());
+    }
 
     // 判断远端是否有备份集
     let (exists, remote_backupset_file) = ssh.exists_backupset(xlsx_checksum, &dbps_home);
     if exists {
         // 备份文件已存在
         log(s, &dbps_home, &format!("BackupSet: {} exists", remote_backupset_file));
+        e.backupset = Some(remote_backupset_file.clone());
+        e.set_status(report::STATUS_SKIPPED, &format!("BackupSet: {} exists", remote_backupset_file));
         file::write_backup_checkpoint(&dbps_home, s, config::ROLE_DS, xlsx_checksum);
-        return;
+        return Ok(());
     }
 
     // 从数据库中查询位点信息
-    let (_, yrba_dat) = query_log_position(s, c.clone()).await;
+    let (_, yrba_dat) = query_log_position(s, c.clone()).await?;
 
     // 将位点信息写入备份目录中：$DBPS_HOME/bin/monica.yrba.dat
+    e.yrba_after = Some(yrba_dat.clone());
     match ssh.write_log_pos(&dbps_home, &yrba_dat) {
         Ok(log_pos_written) => match config::get_ds_manifest(input, ssh.get_ss_version(&input, &dbps_home)) {
             Some(manifest) => {
-                if backup_remote_files(xlsx_checksum, manifest, &dbps_home, &ssh, &s, log_pos_written) {
+                if backup_remote_files(xlsx_checksum, manifest, &dbps_home, &ssh, &s, log_pos_written, e)? {
                     // 写入检查点
                     file::write_backup_checkpoint(&dbps_home, s, config::ROLE_DS, xlsx_checksum);
                 }
+                Ok(())
             },
-            None => error(s, &dbps_home, "Oracle version read failed <<<")
+            None => {
+                error(s, &dbps_home, "Oracle version read failed <<<");
+                e.fail("Oracle version read failed");
+                Err(Error::Version(input.clone()))
+            }
         },
-        Err(e) => config::abnormal_exit_backup(&e)
+        Err(cause) => {
+            e.fail(&cause);
+            Err(Error::Remote(cause))
+        }
     }
 
 }
 
 
+// 演练：输出备份计划
+fn print_backup_plan(ssh: &ssh::Client, s: &Server, role: usize, dbps_home: &str, manifest: Option<&Manifest>, 
+    xlsx_checksum: &str, yrba: Option<&str>) {
+
+    let mut plan = Plan::new(s, role, dbps_home, manifest);
+    if role == config::ROLE_JDDM {
+        plan.step("Record jddm arguments", JDDM_START_WITH_FILE);
+    }
+
+    let (exists, remote_backupset_file) = ssh.exists_backupset(xlsx_checksum, dbps_home);
+    match manifest {
+        _ if exists => plan.step("Create backupset", &format!("BackupSet: {} exists, skip", remote_backupset_file)),
+        Some(manifest) => plan.backup(ssh, dbps_home, manifest, xlsx_checksum, yrba),
+        None => plan.step("Read manifest", "Oracle version read failed <<<"),
+    }
+    plan.print();
+}
+
 // 备份文件，计算sha256sum
 // 备份远端程序，备份时先生成临时文件 .monica/.tmp/<sha256sum>.tar，当文件上传成功后，将备份文件挪出目录.monica中，并写入backupset.index
 fn backup_remote_files(xlsx_checksum: &str, manifest: &Manifest, 
-    dbps_home: &str, ssh: &ssh::Client, s: &Server, log_pos_written: bool) -> bool {
+    dbps_home: &str, ssh: &ssh::Client, s: &Server, log_pos_written: bool, e: &mut report::Entry) -> Result<bool> {
+
+    e.set_manifest(Some(manifest));
+    e.record_files(ssh, dbps_home, manifest, false);
 
     let mut file_list = manifest.file.join(" ");
     if log_pos_written {
@@ -241,7 +353,10 @@ fn backup_remote_files(xlsx_checksum: &str, manifest: &Manifest,
             // 执行成功，并将该文件打包到备份文件中
             file_list = format!("{} {}", file_list, sha256sum_file_name);
         },
-        Err(e) => config::abnormal_exit_backup(&e)
+        Err(cause) => {
+            e.fail(&cause);
+            return Err(Error::Remote(cause));
+        }
     }
 
     let file_name = format!("bin/{}", config::BACKUPUP_SHA256SUM_FILENAME);
@@ -252,30 +367,33 @@ fn backup_remote_files(xlsx_checksum: &str, manifest: &Manifest,
 
         Ok(backupset_file_name) => {
             log(s, dbps_home, &format!("Generated remote temporary BackupSet {}", backupset_file_name));
-
-            match Opt::from_args().command {
-                // Command::Patch(_) => {
-                //     // 先上传文件再移动为正式文件
-                //     return true;
-                // },
-                Command::Backup(_) => {
-                    // 只执行备份命令
-                    match ssh.gen_backupset(xlsx_checksum, dbps_home) {
-                        Ok(backupset_file_name) => {
-                            log(s, dbps_home, &format!("Generated BackupSet {}, BackupSet record in {}", backupset_file_name, config::BACKUPUP_INDEX_FILENAME));
-                            return true;
-                        },
-                        Err(e) => config::abnormal_exit_backup(&e)
-                        
+            e.backupset = Some(format!("{}/{}", config::BACKUPUP_TMP_DIR, backupset_file_name));
+            e.set_status(report::STATUS_SUCCESS, "");
+
+            // patch命令先上传文件再移动为正式文件
+            if let Command::Backup(_) = Opt::from_args().command {
+                // 只执行备份命令
+                match ssh.gen_backupset(xlsx_checksum, dbps_home) {
+                    Ok(backupset_file_name) => {
+                        log(s, dbps_home, &format!("Generated BackupSet {}, BackupSet record in {}", backupset_file_name, config::BACKUPUP_INDEX_FILENAME));
+                        e.backupset = Some(backupset_file_name);
+                        return Ok(true);
+                    },
+                    Err(cause) => {
+                        e.fail(&cause);
+                        return Err(Error::Remote(cause));
                     }
-                },
-                _ => { },
+                    
+                }
             }
         },
 
-        Err(e) => config::abnormal_exit_backup(&e)
+        Err(cause) => {
+            e.fail(&cause);
+            return Err(Error::Remote(cause));
+        }
     }
 
-    false
+    Ok(false)
 
 }
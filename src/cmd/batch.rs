@@ -0,0 +1,242 @@
+use std::{fs, path::Path};
+
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+
+use crate::{config::{self, Server, BACKUPUP_DIR, BACKUPUP_FILE_PREFIX, BACKUPUP_INDEX_FILENAME, BACKUPUP_TMP_DIR, GLOBAL_CONFIG}, error::{Error, Result}, file, ssh};
+
+lazy_static! {
+    // 批次编号：备份集、检查点及本地清单目录的键，清单读取失败时为错误原因
+    static ref BATCH_ID: std::result::Result<String, String> = match config::get_batch_id() {
+        Some(id) => Ok(id),
+        None => read_manifest().map(|m| derive_batch_id(&GLOBAL_CONFIG.servers, &m)).map_err(|e| e.to_string()),
+    };
+    // 旧版本使用的键：输入文件的sha256
+    static ref LEGACY_ID: String = file::sha256sum(Path::new(&config::get_input_file()).to_path_buf());
+}
+
+// 清单内容，由 monica sign 写入的 sha256、checksums 不参与计算
+fn read_manifest() -> Result<serde_json::Value> {
+    let manifest_file = config::get_manifest_file();
+    let contents = fs::read_to_string(&manifest_file)?;
+    let mut manifest: serde_json::Value = serde_json::from_str(&contents).map_err(|e| Error::Verify(format!("{}: {}", manifest_file, e)))?;
+    for role in ["ds", "dt"] {
+        if let Some(entries) = manifest.get_mut(role).and_then(|m| m.as_object_mut()) {
+            for m in entries.values_mut().filter_map(|m| m.as_object_mut()) {
+                m.remove("sha256");
+                m.remove("checksums");
+            }
+        }
+    }
+    Ok(manifest)
+}
+
+// 由输入文件中的行及清单内容生成，与文件格式、列宽、密码、认证方式、标签等无关
+fn derive_batch_id(servers: &[Server], manifest: &serde_json::Value) -> String {
+    let mut contents = String::new();
+    for s in servers.iter() {
+        let fields = [
+            s.rid.to_string(), s.hostname.clone(), s.port.clone(), s.username.clone(), s.service_base_path.clone(), s.service_name.clone(),
+            s.src_type.clone().unwrap_or_default(), s.dst_type.clone().unwrap_or_default(),
+        ];
+        let fields: Vec<&str> = fields.iter().map(|f| f.trim()).collect();
+        contents.push_str(&fields.join("\t"));
+        contents.push('\n');
+    }
+    // serde_json 的对象按键排序，与清单文件的格式无关
+    contents.push_str(&manifest.to_string());
+
+    sha256::digest(contents)
+}
+
+// 检查批次编号能否生成，由 main 在使用前调用
+pub fn check_batch_id() -> Result<String> {
+    BATCH_ID.clone().map_err(Error::Verify)
+}
+
+// 生成失败时 main 已退出
+pub fn batch_id() -> String {
+    BATCH_ID.clone().unwrap_or_default()
+}
+
+// 本地清单中的 BackupSet ID
+pub fn short_id(id: &str) -> &str {
+    &id[..id.len().min(12)]
+}
+
+// 旧版本的本地清单目录名为输入文件的sha256
+fn is_legacy_key(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+// 本地清单由输入文件的sha256改为批次编号：inventory/<sha256>/input.json 中的行与当前输入文件的行生成相同的批次编号时，
+// 重命名为 inventory/<批次编号>，并更新 backupset.index；输入文件重新保存过时，可能有多个旧目录
+pub fn migrate_local_inventory() {
+    let manifest = match read_manifest() {
+        Ok(m) => m,
+        Err(e) => {
+            error!("Migrate local inventory failed, cause: {}", e);
+            return;
+        }
+    };
+    let current = derive_batch_id(&GLOBAL_CONFIG.servers, &manifest);
+    migrate_inventory_dir(&config::get_local_inventory_dir(), &batch_id(), &current, &manifest);
+}
+
+// 迁移 dir 下与当前行(current)匹配的旧目录到 dir/<id>
+fn migrate_inventory_dir(dir: &str, id: &str, current: &str, manifest: &serde_json::Value) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    let id_dir = format!("{}/{}", dir, id);
+    for entry in entries.flatten() {
+        let legacy = entry.file_name().to_string_lossy().to_string();
+        if legacy == id || !is_legacy_key(&legacy) {
+            continue;
+        }
+        let servers: Vec<Server> = match fs::read_to_string(entry.path().join("input.json")).ok().and_then(|c| serde_json::from_str(&c).ok()) {
+            Some(servers) => servers,
+            None => continue,
+        };
+        // 已按批次编号保存，或是其他输入文件的清单
+        let derived = derive_batch_id(&servers, manifest);
+        if derived == legacy || derived != current {
+            continue;
+        }
+
+        let legacy_dir = format!("{}/{}", dir, legacy);
+        if Path::new(&id_dir).exists() {
+            warn!("Local inventory {} not migrated, {} already exists", legacy_dir, id_dir);
+            continue;
+        }
+        if let Err(e) = fs::rename(&legacy_dir, &id_dir) {
+            error!("Migrate local inventory {} failed, cause: {}", legacy_dir, e);
+            continue;
+        }
+
+        // 格式：<BackupSet ID>:<checksum>:<行数>:<日期>
+        let index_file = format!("{}/backupset.index", dir);
+        let contents = fs::read_to_string(&index_file).unwrap_or_default();
+        let lines: Vec<String> = contents.lines()
+            .map(|l| l.replacen(&format!("{}:{}:", short_id(&legacy), legacy), &format!("{}:{}:", short_id(id), id), 1))
+            .collect();
+        match fs::write(&index_file, lines.join("\n") + "\n") {
+            Ok(_) => info!("Migrate local inventory {} to batch {}", legacy_dir, id),
+            Err(e) => error!("File {} write failed, cause: {}", index_file, e),
+        }
+    }
+}
+
+// 远端备份集由输入文件的sha256改为批次编号：重命名 .monica/backupset-<sha256>.tar 及临时备份集，并更新 backupset.index
+// key 不是当前批次时不处理，如：回退到其他输入文件的备份集；演练时不处理
+pub fn migrate_remote_backupset(ssh: &ssh::Client, s: &Server, dbps_home: &str, key: &str) {
+    let id = batch_id();
+    let legacy = LEGACY_ID.as_str();
+    if legacy == id || key != id || config::is_dry_run() {
+        return;
+    }
+    let legacy_file = format!("{}-{}.tar", BACKUPUP_FILE_PREFIX, legacy);
+    let id_file = format!("{}-{}.tar", BACKUPUP_FILE_PREFIX, id);
+
+    let mut cmd = format!("cd {} && ", dbps_home);
+    cmd = format!("{} if [ -e {}/{} ] && [ ! -e {}/{} ]; then ", cmd, BACKUPUP_DIR, legacy_file, BACKUPUP_DIR, id_file);
+    cmd = format!("{} mv {}/{} {}/{} && sed -i 's/{}/{}/' {}/{} && echo migrated; fi; ", cmd, BACKUPUP_DIR, legacy_file, BACKUPUP_DIR, id_file, legacy_file, id_file, BACKUPUP_DIR, BACKUPUP_INDEX_FILENAME);
+    cmd = format!("{} if [ -e {}/{} ] && [ ! -e {}/{} ]; then mv {}/{} {}/{}; fi", cmd, BACKUPUP_TMP_DIR, legacy_file, BACKUPUP_TMP_DIR, id_file, BACKUPUP_TMP_DIR, legacy_file, BACKUPUP_TMP_DIR, id_file);
+    let (status, stdout, stderr) = ssh.exec_cmd_with_status(&cmd);
+    if status != 0 {
+        error!("xlsx:Line: {:<2} Host: {}, Service: {}, DBPS_HOME: {}, Migrate backupset {} failed, cause: {}", s.rid, s.hostname, s.service_name, dbps_home, legacy_file, stderr);
+    } else if stdout.trim() == "migrated" {
+        info!("xlsx:Line: {:<2} Host: {}, Service: {}, DBPS_HOME: {}, Migrate backupset {} to {}", s.rid, s.hostname, s.service_name, dbps_home, legacy_file, id_file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(rid: usize, service_name: &str) -> Server {
+        Server {
+            rid,
+            hostname: String::from("192.168.6.12"),
+            port: String::from("22"),
+            username: String::from("dsg"),
+            service_base_path: String::from("/data/dataxone/sync"),
+            service_name: service_name.to_string(),
+            src_type: Some(String::from("ORACLE")),
+            ..Default::default()
+        }
+    }
+
+    fn manifest() -> serde_json::Value {
+        serde_json::json!({"ds": {"ORACLE_19": {"package": "ds.tar.gz", "dir": "ds", "file": ["bin/pmon"]}}, "dt": {}})
+    }
+
+    #[test]
+    fn batch_id_ignores_credentials_and_formatting() {
+        let servers = vec![server(2, "s1"), server(3, "s2")];
+        let id = derive_batch_id(&servers, &manifest());
+
+        let mut changed = servers.clone();
+        changed[0].password = Some(String::from("secret"));
+        changed[0].auth_method = Some(String::from("publickey"));
+        changed[0].tags = Some(String::from("canary"));
+        changed[1].service_name = String::from(" s2 ");
+        assert_eq!(derive_batch_id(&changed, &manifest()), id);
+
+        // 与清单文件中键的顺序无关
+        let reordered: serde_json::Value = serde_json::from_str(r#"{"dt": {}, "ds": {"ORACLE_19": {"file": ["bin/pmon"], "dir": "ds", "package": "ds.tar.gz"}}}"#).unwrap();
+        assert_eq!(derive_batch_id(&servers, &reordered), id);
+    }
+
+    #[test]
+    fn batch_id_follows_rows_and_manifest() {
+        let servers = vec![server(2, "s1"), server(3, "s2")];
+        let id = derive_batch_id(&servers, &manifest());
+        assert_eq!(id.len(), 64);
+        assert_ne!(derive_batch_id(&servers[..1], &manifest()), id);
+        assert_ne!(derive_batch_id(&[server(2, "s1"), server(4, "s2")], &manifest()), id);
+
+        let mut other = manifest();
+        other["ds"]["ORACLE_19"]["file"] = serde_json::json!(["bin/pmon", "bin/dbps"]);
+        assert_ne!(derive_batch_id(&servers, &other), id);
+    }
+
+    #[test]
+    fn legacy_keys() {
+        assert!(is_legacy_key(&"0123456789abcdef".repeat(4)));
+        assert!(!is_legacy_key(&"0123456789ABCDEF".repeat(4)));
+        assert!(!is_legacy_key(&"0123456789abcdef".repeat(3)));
+        assert!(!is_legacy_key("my-batch"));
+    }
+
+    #[test]
+    fn matching_legacy_inventories_are_migrated() {
+        let dir = std::env::temp_dir().join(format!("monica-inventory-{}", std::process::id()));
+        let dir_str = dir.to_string_lossy().to_string();
+        let servers = vec![server(2, "s1")];
+        let current = derive_batch_id(&servers, &manifest());
+        let id = "release-42";
+        let legacy = "a".repeat(64);
+        let unrelated = "b".repeat(64);
+
+        for (key, rows) in [(&legacy, vec![server(2, "s1")]), (&unrelated, vec![server(2, "s9")])] {
+            fs::create_dir_all(dir.join(key)).unwrap();
+            fs::write(dir.join(key).join("input.json"), serde_json::to_string(&rows).unwrap()).unwrap();
+        }
+        fs::write(dir.join("backupset.index"), format!("{}:{}:1:2024-06-01\n{}:{}:1:2024-06-02\n",
+            short_id(&legacy), legacy, short_id(&unrelated), unrelated)).unwrap();
+
+        migrate_inventory_dir(&dir_str, id, &current, &manifest());
+
+        let index = fs::read_to_string(dir.join("backupset.index")).unwrap();
+        let migrated = dir.join(id).join("input.json").exists();
+        let legacy_left = dir.join(&legacy).exists();
+        let unrelated_left = dir.join(&unrelated).exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(migrated && !legacy_left && unrelated_left);
+        assert_eq!(index, format!("{}:{}:1:2024-06-01\n{}:{}:1:2024-06-02\n", id, id, short_id(&unrelated), unrelated));
+    }
+}
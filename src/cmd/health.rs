@@ -0,0 +1,127 @@
+use std::{path::Path, thread, time::{Duration, Instant}};
+
+use crate::{config::{self, Manifest, Server, ROLE_JDDM}, error::{Error, Result}, file::path_join, ps::{self, Process}, report, ssh};
+
+use super::{error, log, startup, startup_jddm};
+
+// 轮询间隔
+const HEALTH_CHECK_INTERVAL: u64 = 5;
+
+// 启动后的检查参数
+#[derive(Debug, Default)]
+pub struct HealthCheck {
+    pub timeout: u64,
+    pub log_file: Option<String>,
+    pub pattern: Option<String>,
+    pub fail_pattern: Option<String>,
+    pub rollback: bool,
+}
+
+// 启动任务，并等待任务正常运行，未开启检查时只发出启动命令
+pub fn startup_and_check(ssh: &ssh::Client, s: &Server, role: usize, dbps_home: &str, manifest: Option<&Manifest>, e: &mut report::Entry) -> Result<()> {
+    let health = config::get_health_check();
+
+    // 启动前记录日志文件的大小，只检查启动后新写入的内容
+    let offset = match &health {
+        Some(HealthCheck { log_file: Some(f), .. }) => log_size(ssh, dbps_home, f),
+        _ => 0,
+    };
+
+    match role {
+        ROLE_JDDM => startup_jddm(s, dbps_home, ssh),
+        _ => startup(s, dbps_home, ssh),
+    }
+
+    let health = match health {
+        Some(h) => h,
+        None => return Ok(()),
+    };
+    let result = wait_healthy(ssh, role, dbps_home, manifest, &health, offset);
+    match &result {
+        Ok(_) => log(s, dbps_home, "Health check passed"),
+        Err(cause) => error(s, dbps_home, &format!("Health check failed, cause: {} <<<", cause)),
+    }
+    e.healthy = Some(result.is_ok());
+    result
+}
+
+// 启动后需要运行的进程，清单中未指定 process 时：
+// ds/dt 检查 bin/pmon，jddm 检查 bin 目录下的 jar 包，没有 jar 包时返回空
+fn expected_processes(role: usize, manifest: Option<&Manifest>) -> Vec<String> {
+    if let Some(m) = manifest {
+        if !m.process.is_empty() {
+            return m.process.clone();
+        }
+    }
+    match role {
+        ROLE_JDDM => manifest.map(|m| m.file.iter()
+                .filter(|f| f.starts_with("bin/") && f.ends_with(".jar"))
+                .cloned()
+                .collect())
+            .unwrap_or_default(),
+        _ => vec![String::from("bin/pmon")],
+    }
+}
+
+fn wait_healthy(ssh: &ssh::Client, role: usize, dbps_home: &str, manifest: Option<&Manifest>, health: &HealthCheck, offset: u64) -> Result<()> {
+    let processes = expected_processes(role, manifest);
+    // 没有可检查的进程时无法确认任务已运行，按检查失败处理
+    if processes.is_empty() {
+        return Err(Error::Health(String::from("No process to check, set process in the manifest")));
+    }
+    let deadline = Instant::now() + Duration::from_secs(health.timeout);
+
+    loop {
+        // 日志中出现失败标识，直接失败
+        if let (Some(f), Some(p)) = (&health.log_file, &health.fail_pattern) {
+            if log_contains(ssh, dbps_home, f, offset, p) {
+                return Err(Error::Health(format!("Found \"{}\" in {}", p, f)));
+            }
+        }
+
+        let running = ssh.list_ps(role, dbps_home);
+        let missing: Vec<&String> = processes.iter().filter(|p| !is_running(&running, role, dbps_home, p)).collect();
+        let logged = match (&health.log_file, &health.pattern) {
+            (Some(f), Some(p)) => log_contains(ssh, dbps_home, f, offset, p),
+            _ => true,
+        };
+        if missing.is_empty() && logged {
+            return Ok(());
+        }
+
+        if Instant::now() >= deadline {
+            if !missing.is_empty() {
+                let missing: Vec<&str> = missing.iter().map(|p| p.as_str()).collect();
+                return Err(Error::Health(format!("Not running after {}s: {}", health.timeout, missing.join(", "))));
+            }
+            return Err(Error::Health(format!("\"{}\" not found in {} after {}s",
+                health.pattern.as_deref().unwrap_or_default(), health.log_file.as_deref().unwrap_or_default(), health.timeout)));
+        }
+        thread::sleep(Duration::from_secs(HEALTH_CHECK_INTERVAL));
+    }
+}
+
+// jddm 通过 jar 包名称识别，ds/dt 通过程序的完整路径识别
+fn is_running(running: &[Process], role: usize, dbps_home: &str, process: &str) -> bool {
+    match role {
+        ROLE_JDDM => {
+            let name = Path::new(process).file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+            running.iter().any(|p| p.has_file(&name))
+        },
+        _ => {
+            let exe = ps::normalize(&path_join(dbps_home, process));
+            running.iter().any(|p| p.exe == exe)
+        },
+    }
+}
+
+fn log_size(ssh: &ssh::Client, dbps_home: &str, log_file: &str) -> u64 {
+    let stdout = ssh.exec_cmd(&format!("stat -c %s {} 2>/dev/null", path_join(dbps_home, log_file)));
+    stdout.trim().parse().unwrap_or(0)
+}
+
+fn log_contains(ssh: &ssh::Client, dbps_home: &str, log_file: &str, offset: u64, pattern: &str) -> bool {
+    let cmd = format!("tail -c +{} {} 2>/dev/null | grep -E -m1 -e '{}'", offset + 1, path_join(dbps_home, log_file), pattern.replace('\'', "'\\''"));
+    let (status, _, _) = ssh.exec_cmd_with_status(&cmd);
+    status == 0
+}
@@ -1,7 +1,61 @@
-use crate::cmd::print_local_inventory_tab;
+use std::fs;
+
+use comfy_table::Table;
+
+use crate::{cmd::{get_last_datetime, print_local_inventory_tab}, config::{self, get_role_name, Server, KFK_TYPE, ROLE_DS, ROLE_DT, ROLE_JDDM}, file::{self, read_local_inventory_index}};
+
+// 检查点中最后一次的日期，不存在时为空
+fn last_checkpoint(ckp: Option<String>) -> String {
+    ckp.and_then(|c| c.lines().last().map(get_last_datetime)).unwrap_or_default()
+}
+
+// 指定 --rows --host --service --role --exclude 时，列出每个备份集中这些行、角色的备份及升级检查点
+fn print_local_inventory_rows() -> String {
+    let target = config::get_target();
+    let mut table = Table::new();
+    table.set_header(vec!["BackupSet ID", "Line", "Host", "Service", "Role", "Backup", "Patch"]);
+
+    let dir = config::get_local_inventory_dir();
+    for line in read_local_inventory_index().lines() {
+        let mut arr = line.split(":");
+        let (id, checksum) = match (arr.next(), arr.next()) {
+            (Some(id), Some(checksum)) => (id, checksum),
+            _ => continue,
+        };
+        // .monica/inventory/<checksum>/input.json
+        let servers: Vec<Server> = fs::read_to_string(format!("{}/{}/input.json", dir, checksum)).ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default();
+
+        for s in servers.iter().filter(|s| target.contains(s)) {
+            let mut roles = Vec::new();
+            if s.src_type.is_some() {
+                roles.push(ROLE_DS);
+            }
+            if let Some(input) = &s.dst_type {
+                roles.push(ROLE_DT);
+                if input.starts_with(KFK_TYPE) {
+                    roles.push(ROLE_JDDM);
+                }
+            }
+            for role in roles.into_iter().filter(|r| target.contains_role(*r)) {
+                table.add_row(vec![
+                    id.to_string(), s.rid.to_string(), s.hostname.clone(), s.service_name.clone(), get_role_name(role).to_string(),
+                    last_checkpoint(file::read_backup_checkpoint(s, role, checksum)),
+                    last_checkpoint(file::read_checkpoint(s, role, checksum)),
+                ]);
+            }
+        }
+    }
+    table.to_string()
+}
 
 // 查看备份集事件处理
 pub fn handle_command_lsinventory(_: usize){
     println!("{}", print_local_inventory_tab());
     println!("");
-}
\ No newline at end of file
+    if !config::get_target().is_all() {
+        println!("{}", print_local_inventory_rows());
+        println!();
+    }
+}
@@ -3,15 +3,27 @@ use std::sync::{Arc, Mutex};
 use comfy_table::Table;
 use log::{error, info};
 
-use crate::{config::{self, current_log_position, Server, YRBA_FILENAME}, db, file::read_local_inventory_index, ssh};
+use crate::{config::{self, current_log_position, Manifest, Server, KFK_TYPE, ROLE_DS, ROLE_DT, ROLE_JDDM, STOP_KILL, STOP_SCRIPT, YRBA_FILENAME}, db, error::Result, file::{self, read_local_inventory_index}, ssh};
 
 pub mod apply;
+pub mod batch;
 pub mod rollback;
 pub mod precheck;
 pub mod lsinventory;
 pub mod backup;
+pub mod plan;
+pub mod prune;
+pub mod health;
+pub mod summary;
+pub mod trust;
+pub mod version;
+pub mod registry;
+pub mod status;
+pub mod verify;
+pub mod wave;
 
 pub const START_SERVICE_SCRIPT: &str = "start_flow.sh";
+pub const STOP_SERVICE_SCRIPT: &str = "stop_flow.sh";
 pub const START_JDDM_M_SCRIPT: &str = "startMonitorJddmEngine.sh";
 pub const START_JDDM_SCRIPT: &str = "startJddmKafkaEngine.sh";
 pub const JDDM_START_WITH_FILE: &str = "bin/monica.started";
@@ -29,6 +41,68 @@ pub const JDDM_START_WITH_FILE: &str = "bin/monica.started";
 //     fn do_work(&self, xlsx_checksum: &str, role: usize, manifest: &Manifest, dbps_home: &str, ssh: &mut ssh::Client, server: &Server);
 // }
 
+// 停止任务：停止脚本 -> SIGTERM -> SIGKILL，每一步后等待 --stop-grace 秒，角色的停止方式决定从哪一步开始
+// 返回 (停止前是否运行, 停止后是否仍在运行)
+pub fn stop(s: &Server, role: usize, dbps_home: &str, ssh: &ssh::Client) -> (bool, bool) {
+    if !ssh.check_valid_ps(role, dbps_home) {
+        return (false, false);
+    }
+    let (strategy, grace) = config::get_stop_strategy(role);
+
+    if strategy == STOP_SCRIPT && stop_by_script(s, role, dbps_home, ssh) {
+        if ssh.wait_ps_exit(role, dbps_home, grace) {
+            log(s, dbps_home, "Stopped by stop script");
+            return (true, false);
+        }
+        log(s, dbps_home, &format!("Still running {}s after stop script", grace));
+    }
+
+    if strategy != STOP_KILL {
+        ssh.signal_ps(role, dbps_home, "TERM");
+        log(s, dbps_home, "SIGTERM has been sent");
+        if ssh.wait_ps_exit(role, dbps_home, grace) {
+            log(s, dbps_home, "Stopped by SIGTERM");
+            return (true, false);
+        }
+        log(s, dbps_home, &format!("Still running {}s after SIGTERM", grace));
+    }
+
+    let (_, starting2) = ssh.kill_ps(role, dbps_home);
+    log(s, dbps_home, "SIGKILL has been sent");
+    (true, starting2)
+}
+
+// 执行停止脚本，脚本不存在或执行失败时返回 false
+// ds/dt: scripts/stop_flow.sh
+// jddm: ./startMonitorJddmEngine.sh stop <service_name>，./startJddmKafkaEngine.sh stop <service_name>
+fn stop_by_script(s: &Server, role: usize, dbps_home: &str, ssh: &ssh::Client) -> bool {
+    let cmd = match role {
+        ROLE_JDDM => {
+            let stdout = ssh.exec_cmd("env | grep JAVA_HOME");
+            let java_home = stdout.trim_end_matches("\n");
+            format!("export {} && export DBPS_HOME={} && cd $DBPS_HOME && ./{} stop {} >/dev/null 2>&1 && ./{} stop {} >/dev/null 2>&1",
+                java_home, dbps_home, START_JDDM_M_SCRIPT, s.service_name, START_JDDM_SCRIPT, s.service_name)
+        },
+        _ => {
+            let (status, _, _) = ssh.exec_cmd_with_status(&format!("test -f {}/scripts/{}", dbps_home, STOP_SERVICE_SCRIPT));
+            if status != 0 {
+                log(s, dbps_home, &format!("No such file: scripts/{}, Skip stop script", STOP_SERVICE_SCRIPT));
+                return false;
+            }
+            format!("export DBPS_HOME={} && cd $DBPS_HOME/scripts && sh ./{}", dbps_home, STOP_SERVICE_SCRIPT)
+        },
+    };
+
+    let (status, _, stderr) = ssh.exec_cmd_with_status(&cmd);
+    if status == 0 {
+        log(s, dbps_home, "Stop command has been issued");
+        true
+    } else {
+        error(s, dbps_home, &format!("Stop command issuance failed, cause: {}", stderr));
+        false
+    }
+}
+
 // 通用步骤
 pub fn clean_ds(s: &Server, dbps_home: &str, ssh: &ssh::Client){
     clean(s, dbps_home, "ds_clean", ssh);
@@ -125,6 +199,45 @@ pub fn update_yrba_file(s: &Server, dbps_home: &str, yrba_dat: &str, ssh: &ssh::
     }
 }
 
+// 角色的目录及清单，目录不存在时为 Err
+pub type RoleHome = std::result::Result<(String, Option<&'static Manifest>), String>;
+
+// 行中各角色的目录及清单：ds、dt，dst_type 为 KAFKA 时还有 jddm，只包含 --role 中的角色
+pub fn role_homes(ssh: &ssh::Client, s: &'static Server) -> Vec<(usize, RoleHome)> {
+    let mut homes = Vec::new();
+    let not_found = || Err(format!("No such directory: {}", file::path_join(&s.service_base_path, &s.service_name)));
+
+    let target = config::get_target();
+    if let Some(input) = &s.src_type {
+        if target.contains_role(ROLE_DS) {
+            let home = match ssh.ds_dbps_home(s) {
+                Some(home) => Ok((home.clone(), config::get_ds_manifest(input, ssh.get_ss_version(input, &home)))),
+                None => not_found(),
+            };
+            homes.push((ROLE_DS, home));
+        }
+    }
+
+    if let Some(input) = &s.dst_type {
+        if target.contains_role(ROLE_DT) {
+            let home = match ssh.dt_dbps_home(s) {
+                Some(home) => Ok((home.clone(), config::get_dt_manifest(input, ssh.get_ss_version(input, &home)))),
+                None => not_found(),
+            };
+            homes.push((ROLE_DT, home));
+        }
+
+        if input.starts_with(KFK_TYPE) && target.contains_role(ROLE_JDDM) {
+            let home = match ssh.jddm_home(s) {
+                Some(home) => Ok((home, Some(config::get_jddm_manifest(input)))),
+                None => not_found(),
+            };
+            homes.push((ROLE_JDDM, home));
+        }
+    }
+    homes
+}
+
 pub fn log(s: &Server, dbps_home: &str, msg: &str){
     info!("xlsx:Line: {:<2} Host: {}, Service: {}, DBPS_HOME: {}, {}", &s.rid, &s.hostname, &s.service_name, dbps_home, msg);
 }
@@ -134,8 +247,7 @@ pub fn error(s: &Server, dbps_home: &str, msg: &str){
 }
 
 // 打印计数器
-pub fn print_counter(c: Arc<Mutex<usize>>){
-    let size = config::GLOBAL_CONFIG.servers.len();
+pub fn print_counter(c: Arc<Mutex<usize>>, size: usize){
     let mut lock = c.lock().unwrap();
     *lock -= 1;
     let remain_count: usize = lock.to_string().parse().unwrap();
@@ -166,11 +278,11 @@ pub fn read_log_position(ssh: &ssh::Client, dbps_home: &str, s: &Server) -> (boo
 
 
 // 从数据库中获取位点信息
-async fn query_log_position(s: &Server, c: db::Client) -> (bool, String) {
+async fn query_log_position(s: &Server, c: db::Client) -> Result<(bool, String)> {
 
     // 从数据库中获取位点信息
     let mut yrba_dat: String = String::new();
-    let valid_log_pos = match c.query_log_pos(&s).await {
+    let valid_log_pos = match c.query_log_pos(&s).await? {
         Some(yrba) => {
             yrba_dat = yrba;
             info!("xlsx:Line: {:<2} Host: {}, Service: {}, Query YRBA(log position): {}", &s.rid, &s.hostname, &s.service_name, yrba_dat);
@@ -182,7 +294,7 @@ async fn query_log_position(s: &Server, c: db::Client) -> (bool, String) {
         }
     };
 
-    (valid_log_pos, yrba_dat)
+    Ok((valid_log_pos, yrba_dat))
 
 }
 
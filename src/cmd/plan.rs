@@ -0,0 +1,161 @@
+use std::path::Path;
+
+use comfy_table::Table;
+use structopt::StructOpt;
+
+use crate::{config::{self, get_role_name, Command, Manifest, Opt, Server, BACKUPUP_DIR, BACKUPUP_FILE_PREFIX, BACKUPUP_INDEX_FILENAME, BACKUPUP_RECYCLE_BIN_DIR, BACKUPUP_SHA256SUM_FILENAME, BACKUPUP_TMP_DIR, ROLE_DS, ROLE_JDDM, STOP_KILL, STOP_SCRIPT, YRBA_FILENAME}, file, ssh};
+
+use super::{JDDM_START_WITH_FILE, START_JDDM_M_SCRIPT, START_JDDM_SCRIPT, START_SERVICE_SCRIPT, STOP_SERVICE_SCRIPT};
+
+// 演练计划：只读取远端信息，输出每行将要执行的操作，不对远端做任何变更
+pub struct Plan {
+    header: String,
+    steps: Table,
+    files: Option<Table>,
+    count: usize,
+}
+
+impl Plan {
+
+    pub fn new(s: &Server, role: usize, dbps_home: &str, manifest: Option<&Manifest>) -> Self {
+        let mut header = format!("Plan: xlsx:Line: {:<2} Host: {}, Service: {}, Role: {}\n", s.rid, s.hostname, s.service_name, get_role_name(role));
+        header = format!("{}  DBPS_HOME : {}\n", header, dbps_home);
+        header = format!("{}  Manifest  : {}\n", header, match manifest {
+            Some(m) => m.key.as_str(),
+            None => "<NONE>",
+        });
+
+        let mut steps = Table::new();
+        steps.set_header(vec!["#", "Action", "Detail"]);
+        Plan { header, steps, files: None, count: 0 }
+    }
+
+    pub fn step(&mut self, action: &str, detail: &str) {
+        self.count += 1;
+        self.steps.add_row(vec![self.count.to_string(), action.to_string(), detail.to_string()]);
+    }
+
+    // 需要停止的进程，及按角色的停止方式执行的每一步，返回进程是否在运行
+    pub fn stop(&mut self, ssh: &ssh::Client, s: &Server, role: usize, dbps_home: &str) -> bool {
+        let ps = ssh.list_ps(role, dbps_home);
+        if ps.is_empty() {
+            self.step("Stop processes", "Not running");
+            return false;
+        }
+        self.step("Stop processes", &ps.iter().map(|p| p.to_string()).collect::<Vec<_>>().join("\n"));
+
+        let (strategy, grace) = config::get_stop_strategy(role);
+        if strategy == STOP_SCRIPT {
+            match role {
+                ROLE_JDDM => self.step("Run stop script", &format!("./{} stop {}\n./{} stop {}\nwait {}s", START_JDDM_M_SCRIPT, s.service_name, START_JDDM_SCRIPT, s.service_name, grace)),
+                _ => self.step("Run stop script", &format!("scripts/{}, wait {}s", STOP_SERVICE_SCRIPT, grace)),
+            }
+        }
+        if strategy != STOP_KILL {
+            self.step("Send SIGTERM", &format!("kill -TERM, wait {}s", grace));
+        }
+        self.step("Send SIGKILL", "kill -9, if still running");
+        true
+    }
+
+    // 需要上传的文件，及本地、远端的sha256sum
+    pub fn upload(&mut self, ssh: &ssh::Client, dbps_home: &str, manifest: &Manifest) {
+        let remote_sums = ssh.sha256sum_files(dbps_home, &manifest.file);
+
+        let mut table = Table::new();
+        table.set_header(vec!["Local file", "Remote file", "Local SHA-256", "Remote SHA-256"]);
+        for f in manifest.file.iter() {
+            let local_file = Path::new(&config::get_basedir()).join(&manifest.dir).join(f);
+            let local_sum = if local_file.exists() {
+                file::sha256sum(local_file.clone())
+            } else {
+                String::from("<missing>")
+            };
+            let remote_sum = remote_sums.get(f).cloned().unwrap_or(String::from("<missing>"));
+            table.add_row(vec![local_file.display().to_string(), file::path_join(dbps_home, f), local_sum, remote_sum]);
+        }
+        self.step("Upload files", &format!("{} file(s), see the file list below", manifest.file.len()));
+        self.files = Some(table);
+    }
+
+    // 备份远端文件
+    pub fn backup(&mut self, ssh: &ssh::Client, dbps_home: &str, manifest: &Manifest, xlsx_checksum: &str, yrba: Option<&str>) {
+        let mut file_list = manifest.file.join(" ");
+        if let Some(yrba) = yrba {
+            self.step("Write log position", &format!("bin/{}: {}", config::get_yrba_file_name(), yrba));
+            file_list = format!("{} bin/{}", file_list, config::get_yrba_file_name());
+        }
+        self.step("Generate checksum file", &format!("sha256sum {} > bin/{}", file_list, BACKUPUP_SHA256SUM_FILENAME));
+
+        let backupset_file_name = format!("{}-{}.tar", BACKUPUP_FILE_PREFIX, xlsx_checksum);
+        self.step("Create temporary backupset", &format!("{}/{}", BACKUPUP_TMP_DIR, backupset_file_name));
+        if let Command::Backup(_) = Opt::from_args().command {
+            self.step("Create backupset", &format!("{}/{}, record in {}/{}", BACKUPUP_DIR, backupset_file_name, BACKUPUP_DIR, BACKUPUP_INDEX_FILENAME));
+        }
+
+        let remote_sums = ssh.sha256sum_files(dbps_home, &manifest.file);
+        let mut table = Table::new();
+        table.set_header(vec!["Remote file", "Remote SHA-256"]);
+        for f in manifest.file.iter() {
+            table.add_row(vec![file::path_join(dbps_home, f), remote_sums.get(f).cloned().unwrap_or(String::from("<missing>"))]);
+        }
+        self.files = Some(table);
+    }
+
+    // 上传后校验文件，并生成正式备份集
+    pub fn verify(&mut self, ssh: &ssh::Client, dbps_home: &str, xlsx_checksum: &str) {
+        self.step("Verify files", &format!("sha256sum -c bin/{}.tmp", BACKUPUP_SHA256SUM_FILENAME));
+        let (exists, backupset_file_name) = ssh.exists_backupset(xlsx_checksum, dbps_home);
+        if exists {
+            self.step("Create backupset", &format!("{} exists", backupset_file_name));
+        } else {
+            self.step("Create backupset", &format!("{}/{}-{}.tar, record in {}/{}", BACKUPUP_DIR, BACKUPUP_FILE_PREFIX, xlsx_checksum, BACKUPUP_DIR, BACKUPUP_INDEX_FILENAME));
+        }
+    }
+
+    // 通过备份集恢复远端文件
+    pub fn restore(&mut self, xlsx_checksum: &str) {
+        let backupset_file = format!("{}/{}-{}.tar", BACKUPUP_DIR, BACKUPUP_FILE_PREFIX, xlsx_checksum);
+        self.step("Restore backupset", &format!("tar -xf {}", backupset_file));
+        self.step("Verify files", &format!("sha256sum -c bin/{}", BACKUPUP_SHA256SUM_FILENAME));
+        self.step("Move backupset", &format!("{} to {}", backupset_file, BACKUPUP_RECYCLE_BIN_DIR));
+    }
+
+    // 清理任务、写入位点、启动任务
+    pub fn restart(&mut self, s: &Server, role: usize, starting: bool, yrba: Option<&str>) {
+        match role {
+            ROLE_JDDM => self.step("Run cleanup", "rm -rf $DBPS_HOME/table/* $DBPS_HOME/cache/*"),
+            _ => self.step("Run cleanup", &format!("scripts/{}_clean.sh", get_role_name(role))),
+        }
+
+        if role == ROLE_DS {
+            if let Some(yrba) = yrba {
+                self.step("Write log position", &format!("rmp/{}: {}", YRBA_FILENAME, yrba));
+            }
+        }
+
+        if !starting {
+            self.step("Run startup", "Non-Start, Skip start");
+            return;
+        }
+        match role {
+            ROLE_JDDM => {
+                self.step("Run startup", &format!("./{} start {} <{}>", START_JDDM_M_SCRIPT, s.service_name, JDDM_START_WITH_FILE));
+                self.step("Run startup", &format!("./{} start {} <{}>", START_JDDM_SCRIPT, s.service_name, JDDM_START_WITH_FILE));
+            },
+            _ => self.step("Run startup", &format!("scripts/{}", START_SERVICE_SCRIPT)),
+        }
+        if let Some(health) = config::get_health_check() {
+            self.step("Check health", &format!("Wait up to {}s for the processes to come back up", health.timeout));
+        }
+    }
+
+    pub fn print(&self) {
+        let mut out = format!("\n{}{}\n", self.header, self.steps);
+        if let Some(files) = &self.files {
+            out = format!("{}{}\n", out, files);
+        }
+        println!("{}", out);
+    }
+
+}
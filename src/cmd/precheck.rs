@@ -3,19 +3,23 @@ use std::{path::Path, sync::{Arc, Mutex}};
 use log::info;
 use tokio::runtime;
 
-use crate::{cmd::print_counter, config::{self, get_basedir, Manifest, Server, KFK_TYPE}, file::{self, path_join}, ssh};
+use crate::{bundle, cmd::print_counter, config::{self, get_basedir, Manifest, Server, KFK_TYPE}, error::{Error, Result}, file::{self, path_join}, report, ssh};
 
-use super::{error, log};
+use super::{error, log, summary::Summary, version};
 
 
 // 预检查事件处理
-pub async fn handle_command_precheck(worker_threads: usize){
+pub async fn handle_command_precheck(worker_threads: usize, servers: &[&'static Server]) -> Result<Summary> {
 
-    let size = config::GLOBAL_CONFIG.servers.len();
+    // 校验清单签名，不一致时不再检查
+    bundle::verify_manifest()?;
+
+    let size = servers.len();
+    let summary = Arc::new(Summary::new("PreChecks"));
 
     if size == 0 {
         println!("\nWarning: The xlsx file has no valid lines\n");
-        return ;
+        return Ok(Arc::into_inner(summary).unwrap());
     }
 
     // 创建线程池
@@ -32,10 +36,16 @@ pub async fn handle_command_precheck(worker_threads: usize){
 
     // 预检查
     let mut handles = vec![];
-    for server in config::GLOBAL_CONFIG.servers.iter() {
+    for server in servers.iter().copied() {
         let counter: Arc<Mutex<usize>> = Arc::clone(&counter);
+        let summary = Arc::clone(&summary);
         let handle: tokio::task::JoinHandle<()> = rt.spawn(async move {
-            start_precheck_worker(counter, server).await;
+            if summary.aborted() {
+                summary.record(server, Err(Error::Aborted));
+                return;
+            }
+            let result = start_precheck_worker(counter, size, server).await;
+            summary.record(server, result);
         });
         handles.push(handle);
     }
@@ -46,106 +56,137 @@ pub async fn handle_command_precheck(worker_threads: usize){
 
     rt.shutdown_background();
 
-    info!("PreChecks passed. Great!");
-    println!("");
+    let summary = Arc::into_inner(summary).unwrap();
+    summary.print();
+    if summary.is_ok() {
+        info!("PreChecks passed. Great!");
+        println!("");
+    }
 
+    Ok(summary)
 }
 
 // 预检查
-pub async fn start_precheck_worker(c: Arc<Mutex<usize>>, s: &Server){
+pub async fn start_precheck_worker(c: Arc<Mutex<usize>>, size: usize, s: &Server) -> Result<()> {
 
-    print_counter(c);
+    print_counter(c, size);
 
     // 连接到复制机，需考虑异机部署
-    let ssh = ssh::Client::new(s);
-    start_ds_worker(&ssh, s);
-    start_dt_worker(&ssh, s);
-    start_jddm_worker(&ssh, s);
+    let ssh = ssh::Client::new(s)?;
+
+    if config::is_target_role(config::ROLE_DS) {
+        let mut e = report::Entry::new("precheck", s, config::ROLE_DS);
+        let result = start_ds_worker(&ssh, s, &mut e);
+        report::push(e, &result);
+        result?;
+    }
+
+    if config::is_target_role(config::ROLE_DT) {
+        let mut e = report::Entry::new("precheck", s, config::ROLE_DT);
+        let result = start_dt_worker(&ssh, s, &mut e);
+        report::push(e, &result);
+        result?;
+    }
+
+    if config::is_target_role(config::ROLE_JDDM) {
+        let mut e = report::Entry::new("precheck", s, config::ROLE_JDDM);
+        let result = start_jddm_worker(&ssh, s, &mut e);
+        report::push(e, &result);
+        result?;
+    }
 
     info!("xlsx:Line: {:<2} Host: {}, Service: {}, PreChecks passed", &s.rid, &s.hostname, &s.service_name);
-    
+    Ok(())
 }
 
 
-fn start_dt_worker(ssh: &ssh::Client, s: &Server){
+fn start_dt_worker(ssh: &ssh::Client, s: &Server, e: &mut report::Entry) -> Result<()> {
 
     let input = match &s.dst_type {
         Some(s) => s,
-        None => return
+        None => return Ok(())
     };
+    e.begin();
 
     let dbps_home = match ssh.dt_dbps_home(s) {
         Some(s) => s,
         None => {
             error(s, "<NONE>", "No such directory <<<");
-            abnormal_exit_not_found();
-            return;
+            e.fail("No such directory");
+            return Err(Error::NotFound(path_join(&s.service_base_path, &s.service_name)));
         }
     };
+    e.dbps_home = Some(dbps_home.clone());
 
     log(s, &dbps_home, "Found");
     match config::get_dt_manifest(input, ssh.get_ss_version(&input, &dbps_home)) {
-        Some(manifest) => do_precheck_files(s, &dbps_home, manifest, &ssh),
+        Some(manifest) => do_precheck_files(s, config::ROLE_DT, &dbps_home, manifest, &ssh, e),
         None => {
             error(s, &dbps_home, "Oracle version read failed");
-            config::abnormal_exit_precheck("Oracle version read failed");
+            e.fail("Oracle version read failed");
+            Err(Error::Version(input.clone()))
         }
     }
         
 }
 
 
-fn start_jddm_worker(ssh: &ssh::Client, s: &Server){
+fn start_jddm_worker(ssh: &ssh::Client, s: &Server, e: &mut report::Entry) -> Result<()> {
 
     let input = match &s.dst_type {
         Some(s) => s,
-        None => return
+        None => return Ok(())
     };
 
     // kafka类型
     if !input.starts_with(KFK_TYPE) {
-        return ;
+        return Ok(());
     }
+    e.begin();
 
     let dbps_home = match ssh.jddm_home(s) {
         Some(s) => s,
         None => {
             error(s, "<NONE>", "No such directory <<<");
-            abnormal_exit_not_found();
-            return;
+            e.fail("No such directory");
+            return Err(Error::NotFound(path_join(&s.service_base_path, &s.service_name)));
         }
     };
+    e.dbps_home = Some(dbps_home.clone());
 
     log(s, &dbps_home, "Found");
     let manifest = config::get_jddm_manifest(input);
-    do_precheck_files(s, &dbps_home, manifest, &ssh);
+    do_precheck_files(s, config::ROLE_JDDM, &dbps_home, manifest, &ssh, e)
         
 }
 
 
 
-fn start_ds_worker(ssh: &ssh::Client, s: &Server){
+fn start_ds_worker(ssh: &ssh::Client, s: &Server, e: &mut report::Entry) -> Result<()> {
 
     let input = match &s.src_type {
         Some(s) => s,
-        None => return
+        None => return Ok(())
     };
+    e.begin();
 
     let dbps_home = match ssh.ds_dbps_home(s) {
         Some(s) => s,
         None => {
             error(s, "<NONE>", "No such directory <<<");
-            abnormal_exit_not_found();
-            return;
+            e.fail("No such directory");
+            return Err(Error::NotFound(path_join(&s.service_base_path, &s.service_name)));
         }
     };
+    e.dbps_home = Some(dbps_home.clone());
 
     log(s, &dbps_home, "Found");
     match config::get_ds_manifest(input, ssh.get_ss_version(&input, &dbps_home)) {
-        Some(manifest) => do_precheck_files(s, &dbps_home, manifest, &ssh),
+        Some(manifest) => do_precheck_files(s, config::ROLE_DS, &dbps_home, manifest, &ssh, e),
         None => {
             error(s, &dbps_home, "Oracle version read failed");
-            config::abnormal_exit_precheck("Oracle version read failed");
+            e.fail("Oracle version read failed");
+            Err(Error::Version(input.clone()))
         }
     }
         
@@ -153,29 +194,46 @@ fn start_ds_worker(ssh: &ssh::Client, s: &Server){
 
 
 // 预检查：本地文件检查 和 远程文件
-fn do_precheck_files(s: &Server, dbps_home: &str, manifest: &Manifest, ssh: &ssh::Client){
+fn do_precheck_files(s: &Server, role: usize, dbps_home: &str, manifest: &Manifest, ssh: &ssh::Client, e: &mut report::Entry) -> Result<()> {
+    e.set_manifest(Some(manifest));
+
+    // 已安装版本需在清单允许的范围内
+    version::check_build(ssh, s, role, dbps_home, manifest, e)?;
+
+    // 校验包及解压后文件的sha256，防止补丁介质被篡改或未拷贝完整
+    if let Err(cause) = bundle::verify_package(manifest, s.rid) {
+        e.fail(&cause.to_string());
+        return Err(cause);
+    }
 
     // 解压本地文件 
-    file::extract_compressed_files(&get_basedir(), &manifest.package, &manifest.dir, s.rid);
+    if let Err(cause) = file::extract_compressed_files(&get_basedir(), &manifest.package, &manifest.dir, s.rid) {
+        e.fail(&cause.to_string());
+        return Err(cause);
+    }
+    if let Err(cause) = bundle::verify_files(manifest, s.rid) {
+        e.fail(&cause.to_string());
+        return Err(cause);
+    }
     for f in manifest.file.iter() {
         let local_file = Path::new(&get_basedir()).join(&manifest.dir).join(f);
         if !local_file.exists() {
             error!("xlsx:Line: {:<2} File {}, No Found <<<", &s.rid, local_file.display());
-            abnormal_exit_not_found();
+            e.fail(&format!("File {} not found", local_file.display()));
+            return Err(Error::NotFound(local_file.display().to_string()));
         }
         info!("xlsx:Line: {:<2} File {}, Found", &s.rid, local_file.display());
 
         let remote_file = &path_join(dbps_home, f);
         if !ssh.is_file(remote_file) {
             error!("xlsx:Line: {:<2} Remote File {}, No Found <<<", &s.rid, remote_file);
-            abnormal_exit_not_found();
+            e.fail(&format!("Remote file {} not found", remote_file));
+            return Err(Error::NotFound(remote_file.to_string()));
         }
         info!("xlsx:Line: {:<2} Remote File {}, Found", &s.rid, remote_file);
     }
-    
-}
 
-// 
-fn abnormal_exit_not_found(){
-    config::abnormal_exit_precheck("No such file or directory");
-}
\ No newline at end of file
+    e.record_files(ssh, dbps_home, manifest, true);
+    e.set_status(report::STATUS_SUCCESS, "");
+    Ok(())
+}
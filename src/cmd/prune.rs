@@ -0,0 +1,320 @@
+use std::{collections::BTreeSet, time::{SystemTime, UNIX_EPOCH}};
+
+use comfy_table::Table;
+use log::{error, info, warn};
+
+use crate::{config::{self, get_role_name, Server, BACKUPUP_DIR, BACKUPUP_FILE_PREFIX, BACKUPUP_INDEX_FILENAME, BACKUPUP_RECYCLE_BIN_DIR, BACKUPUP_TMP_DIR}, file, ssh};
+
+use super::{log, registry::{self, Record, ACTION_APPLY}, role_homes};
+
+// 备份集保留策略
+#[derive(Debug, Default)]
+pub struct Retention {
+    pub keep: Option<usize>, // 保留最新的N个备份集
+    pub older_than: Option<u64>, // 只删除D天之前的备份集
+    pub recyclebin_only: bool, // 只清理回收站及临时备份集
+}
+
+// 远端的备份集文件
+struct Backupset {
+    path: String, // 相对 $DBPS_HOME，如：.monica/backupset-<batch>.tar
+    size: u64,
+    mtime: u64,
+}
+
+impl Backupset {
+
+    // 备份集的批次编号
+    fn key(&self) -> &str {
+        let name = self.path.rsplit('/').next().unwrap_or_default();
+        name.strip_prefix(&format!("{}-", BACKUPUP_FILE_PREFIX)).and_then(|n| n.strip_suffix(".tar")).unwrap_or(name)
+    }
+
+}
+
+// 每个目录的备份集及清理结果
+struct Usage {
+    active: Vec<Backupset>,
+    recyclebin: Vec<Backupset>,
+    tmp: Vec<Backupset>,
+    pruned: Vec<Backupset>,
+    protected: Option<String>, // 超出保留策略，但当前补丁级别依赖而保留的备份集
+}
+
+fn kib(backupsets: &[Backupset]) -> String {
+    format!("{:.0}KiB", backupsets.iter().map(|b| b.size).sum::<u64>() as f64 / 1024.0)
+}
+
+// 列出 .monica、.monica/.recyclebin、.monica/.tmp 中的备份集：<大小> <修改时间> <文件>
+fn list_backupsets(ssh: &ssh::Client, dbps_home: &str) -> Vec<Backupset> {
+    let mut cmd = format!("cd {} && for f in ", dbps_home);
+    for dir in [BACKUPUP_DIR, BACKUPUP_RECYCLE_BIN_DIR, BACKUPUP_TMP_DIR] {
+        cmd = format!("{} {}/{}-*.tar", cmd, dir, BACKUPUP_FILE_PREFIX);
+    }
+    cmd = format!("{}; do [ ! -f \"$f\" ] || stat -c '%s %Y %n' \"$f\"; done", cmd);
+    ssh.exec_cmd(&cmd).lines()
+        .filter_map(|l| {
+            let mut arr = l.splitn(3, ' ');
+            Some(Backupset { size: arr.next()?.parse().ok()?, mtime: arr.next()?.parse().ok()?, path: arr.next()?.to_string() })
+        })
+        .collect()
+}
+
+// 按保留策略选出需要删除的备份集，当前补丁级别依赖的备份集不删除
+fn select(ssh: &ssh::Client, s: &Server, dbps_home: &str, retention: &Retention) -> Usage {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let backupsets = list_backupsets(ssh, dbps_home);
+    let (index, protected) = if retention.recyclebin_only {
+        (Vec::new(), None)
+    } else {
+        let index = ssh.list_remote_backupset(dbps_home);
+        let protected = protected(registry::last(ssh, dbps_home), &index);
+        (index, protected)
+    };
+
+    let usage = plan(backupsets, &index, protected.as_deref(), retention, now);
+    if let Some(path) = &usage.protected {
+        warn!("xlsx:Line: {:<2} Host: {}, Service: {}, DBPS_HOME: {}, Keep {}, the current patch level depends on it", s.rid, s.hostname, s.service_name, dbps_home, path);
+    }
+    usage
+}
+
+// 当前补丁级别依赖的备份集：最后一条为升级记录时为其备份集，回退后为空
+// 没有升级记录时(如：旧版本升级的目录)，为 backupset.index 中最新的备份集
+fn protected(last: Option<Record>, index: &[String]) -> Option<String> {
+    match last {
+        Some(r) if r.action == ACTION_APPLY => r.backupset,
+        Some(_) => None,
+        None => index.last().cloned(),
+    }
+}
+
+// 保留策略：回收站及临时备份集按 --older-than 删除，其余的按 backupset.index 中的顺序保留最新的 --keep 个
+fn plan(backupsets: Vec<Backupset>, index: &[String], protected: Option<&str>, retention: &Retention, now: u64) -> Usage {
+    let expired = |b: &Backupset| retention.older_than.is_none_or(|days| b.mtime + days * 86400 < now);
+
+    let (recyclebin, rest): (Vec<Backupset>, Vec<Backupset>) = backupsets.into_iter()
+        .partition(|b| b.path.starts_with(&format!("{}/", BACKUPUP_RECYCLE_BIN_DIR)));
+    let (tmp, mut active): (Vec<Backupset>, Vec<Backupset>) = rest.into_iter()
+        .partition(|b| b.path.starts_with(&format!("{}/", BACKUPUP_TMP_DIR)));
+
+    // 回收站及临时备份集
+    let (mut pruned, recyclebin): (Vec<Backupset>, Vec<Backupset>) = recyclebin.into_iter().partition(|b| expired(b));
+    let (pruned_tmp, tmp): (Vec<Backupset>, Vec<Backupset>) = tmp.into_iter().partition(|b| expired(b));
+    pruned.extend(pruned_tmp);
+    if retention.recyclebin_only {
+        return Usage { active, recyclebin, tmp, pruned, protected: None };
+    }
+
+    // 按 backupset.index 中的顺序排列，最新的在最后；不在索引中的视为最旧
+    active.sort_by_key(|b| (index.iter().position(|l| *l == b.path), b.mtime));
+
+    let count = active.len().saturating_sub(retention.keep.unwrap_or(0));
+    let mut kept = Vec::new();
+    let mut kept_protected = None;
+    for (i, b) in active.into_iter().enumerate() {
+        if i >= count || !expired(&b) {
+            kept.push(b);
+        } else if protected == Some(b.path.as_str()) {
+            kept_protected = Some(b.path.clone());
+            kept.push(b);
+        } else {
+            pruned.push(b);
+        }
+    }
+    Usage { active: kept, recyclebin, tmp, pruned, protected: kept_protected }
+}
+
+// 删除备份集，并从 backupset.index、.recyclebin/backupset.index 中删除对应的行
+fn delete(ssh: &ssh::Client, dbps_home: &str, pruned: &[Backupset]) -> Result<(), String> {
+    let files: Vec<&str> = pruned.iter().map(|b| b.path.as_str()).collect();
+    let mut cmd = format!("cd {} && rm -f {}", dbps_home, files.join(" "));
+
+    // 回收站的索引中记录的是原备份集的路径
+    let recyclebin = format!("{}/", BACKUPUP_RECYCLE_BIN_DIR);
+    for (index_file, prefix) in [(format!("{}/{}", BACKUPUP_DIR, BACKUPUP_INDEX_FILENAME), None), (format!("{}/{}", BACKUPUP_RECYCLE_BIN_DIR, BACKUPUP_INDEX_FILENAME), Some(recyclebin.as_str()))] {
+        let lines: Vec<String> = pruned.iter()
+            .filter_map(|b| match prefix {
+                Some(p) => b.path.strip_prefix(p).map(|name| format!("{}/{}", BACKUPUP_DIR, name)),
+                None if !b.path.starts_with(&format!("{}/.", BACKUPUP_DIR)) => Some(b.path.clone()),
+                None => None,
+            })
+            .map(|l| format!("-e '\\#^{}$#d'", l.replace('.', "\\.")))
+            .collect();
+        if !lines.is_empty() {
+            cmd = format!("{} && if [ -f {} ]; then sed -i {} {}; fi", cmd, index_file, lines.join(" "), index_file);
+        }
+    }
+    let (status, _, stderr) = ssh.exec_cmd_with_status(&cmd);
+    if status != 0 {
+        return Err(stderr.trim_end().to_string());
+    }
+    Ok(())
+}
+
+// 本地清单：所有行都已删除的备份集，从 inventory 中删除
+fn sync_local_inventory(removed: &BTreeSet<String>, remaining: &BTreeSet<String>) {
+    for key in removed.difference(remaining) {
+        let contents = file::read_local_inventory_index();
+        if contents.lines().any(|l| l.contains(&format!(":{}:", key))) {
+            file::clean_local_inventory(key, &contents);
+        }
+    }
+}
+
+// 连接每一行，按保留策略清理各目录中的备份集、回收站及临时备份集，输出磁盘占用
+// 返回是否全部成功
+pub fn handle_command_prune(servers: &[&'static Server]) -> bool {
+    let retention = config::get_retention();
+    if retention.keep.is_none() && retention.older_than.is_none() && !retention.recyclebin_only {
+        error!("Prune needs --keep, --older-than or --recyclebin-only");
+        return false;
+    }
+    let dry_run = config::is_dry_run();
+
+    let mut table = Table::new();
+    table.set_header(vec!["Line", "Host", "Role", "DBPS_HOME", "Backupsets", "Size", "Recycle bin", "Temporary", "Pruned", "Freed"]);
+
+    let mut ok = true;
+    let (mut removed, mut remaining) = (BTreeSet::new(), BTreeSet::new());
+    for s in servers.iter().copied() {
+        let ssh = match ssh::Client::new(s) {
+            Ok(ssh) => ssh,
+            Err(e) => {
+                error!("xlsx:Line: {:<2} Host: {}, Prune failed, cause: {}", s.rid, s.hostname, e);
+                table.add_row(vec![s.rid.to_string(), s.hostname.clone(), String::new(), String::new(), String::new(), String::new(), String::new(), String::new(), String::new(), format!("failed, {}", e)]);
+                ok = false;
+                continue;
+            }
+        };
+        for (role, home) in role_homes(&ssh, s) {
+            let dbps_home = match home {
+                Ok((dbps_home, _)) => dbps_home,
+                Err(e) => {
+                    error!("xlsx:Line: {:<2} Host: {}, Prune failed, cause: {}", s.rid, s.hostname, e);
+                    table.add_row(vec![s.rid.to_string(), s.hostname.clone(), get_role_name(role).to_string(), String::new(), String::new(), String::new(), String::new(), String::new(), String::new(), format!("failed, {}", e)]);
+                    ok = false;
+                    continue;
+                },
+            };
+
+            let usage = select(&ssh, s, &dbps_home, &retention);
+            let mut freed = kib(&usage.pruned);
+            for b in usage.pruned.iter() {
+                log(s, &dbps_home, &format!("{} {} {}", if dry_run { "Would prune" } else { "Prune" }, b.path, kib(std::slice::from_ref(b))));
+            }
+            if !dry_run && !usage.pruned.is_empty() {
+                if let Err(e) = delete(&ssh, &dbps_home, &usage.pruned) {
+                    error!("xlsx:Line: {:<2} Host: {}, Service: {}, DBPS_HOME: {}, Prune failed, cause: {}", s.rid, s.hostname, s.service_name, dbps_home, e);
+                    freed = format!("failed, {}", e);
+                    ok = false;
+                }
+            }
+
+            removed.extend(usage.pruned.iter().filter(|b| !b.path.starts_with(&format!("{}/.", BACKUPUP_DIR))).map(|b| b.key().to_string()));
+            remaining.extend(usage.active.iter().map(|b| b.key().to_string()));
+            table.add_row(vec![
+                s.rid.to_string(), s.hostname.clone(), get_role_name(role).to_string(), dbps_home,
+                usage.active.len().to_string(), kib(&usage.active), kib(&usage.recyclebin), kib(&usage.tmp), usage.pruned.len().to_string(), freed,
+            ]);
+        }
+    }
+
+    // 只处理了部分行，或有行失败时，无法确认备份集已从所有行删除，保留本地清单
+    if !dry_run && ok && config::get_target().is_all() {
+        sync_local_inventory(&removed, &remaining);
+    }
+
+    println!("\nPrune summary:\n{}\n", table);
+    if dry_run {
+        info!("Prune dry run completed, no changes were made.");
+    }
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::registry::ACTION_ROLLBACK;
+
+    const DAY: u64 = 86400;
+    const NOW: u64 = 100 * DAY;
+
+    fn backupset(path: &str, age_days: u64) -> Backupset {
+        Backupset { path: path.to_string(), size: 1024, mtime: NOW - age_days * DAY }
+    }
+
+    fn active(key: &str, age_days: u64) -> Backupset {
+        backupset(&format!("{}/{}-{}.tar", BACKUPUP_DIR, BACKUPUP_FILE_PREFIX, key), age_days)
+    }
+
+    fn index(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|k| format!("{}/{}-{}.tar", BACKUPUP_DIR, BACKUPUP_FILE_PREFIX, k)).collect()
+    }
+
+    fn keys(backupsets: &[Backupset]) -> Vec<&str> {
+        backupsets.iter().map(|b| b.key()).collect()
+    }
+
+    fn record(action: &str, backupset: Option<&str>) -> Record {
+        Record {
+            action: action.to_string(), patch_id: None, manifest: None, build: None,
+            backupset: backupset.map(String::from), files: Default::default(), time: String::new(), operator: String::new(),
+        }
+    }
+
+    #[test]
+    fn keep_newest_in_index_order() {
+        // 索引顺序 b1 < b2 < b3，不在索引中的 b0 视为最旧
+        let backupsets = vec![active("b3", 1), active("b1", 3), active("b0", 0), active("b2", 2)];
+        let retention = Retention { keep: Some(2), ..Default::default() };
+        let usage = plan(backupsets, &index(&["b1", "b2", "b3"]), None, &retention, NOW);
+        assert_eq!(keys(&usage.active), vec!["b2", "b3"]);
+        assert_eq!(keys(&usage.pruned), vec!["b0", "b1"]);
+    }
+
+    #[test]
+    fn older_than_keeps_recent_backupsets() {
+        let backupsets = vec![active("b1", 40), active("b2", 20), active("b3", 1)];
+        let retention = Retention { keep: Some(1), older_than: Some(30), ..Default::default() };
+        let usage = plan(backupsets, &index(&["b1", "b2", "b3"]), None, &retention, NOW);
+        assert_eq!(keys(&usage.active), vec!["b2", "b3"]);
+        assert_eq!(keys(&usage.pruned), vec!["b1"]);
+    }
+
+    #[test]
+    fn protected_backupset_is_kept() {
+        let backupsets = vec![active("b1", 3), active("b2", 2), active("b3", 1)];
+        let retention = Retention { keep: Some(1), ..Default::default() };
+        let protected = index(&["b1"]).pop();
+        let usage = plan(backupsets, &index(&["b1", "b2", "b3"]), protected.as_deref(), &retention, NOW);
+        assert_eq!(keys(&usage.active), vec!["b1", "b3"]);
+        assert_eq!(keys(&usage.pruned), vec!["b2"]);
+        assert_eq!(usage.protected, protected);
+    }
+
+    #[test]
+    fn recyclebin_and_temporary_backupsets() {
+        let backupsets = vec![
+            active("b1", 50),
+            backupset(&format!("{}/{}-r1.tar", BACKUPUP_RECYCLE_BIN_DIR, BACKUPUP_FILE_PREFIX), 40),
+            backupset(&format!("{}/{}-r2.tar", BACKUPUP_RECYCLE_BIN_DIR, BACKUPUP_FILE_PREFIX), 10),
+            backupset(&format!("{}/{}-t1.tar", BACKUPUP_TMP_DIR, BACKUPUP_FILE_PREFIX), 40),
+        ];
+        let retention = Retention { older_than: Some(30), recyclebin_only: true, ..Default::default() };
+        let usage = plan(backupsets, &[], None, &retention, NOW);
+        assert_eq!(keys(&usage.active), vec!["b1"]);
+        assert_eq!(keys(&usage.recyclebin), vec!["r2"]);
+        assert!(usage.tmp.is_empty());
+        assert_eq!(keys(&usage.pruned), vec!["r1", "t1"]);
+    }
+
+    #[test]
+    fn current_patch_level_backupset() {
+        let index = index(&["b1", "b2"]);
+        assert_eq!(protected(Some(record(ACTION_APPLY, Some("x.tar"))), &index).as_deref(), Some("x.tar"));
+        assert_eq!(protected(Some(record(ACTION_ROLLBACK, Some("x.tar"))), &index), None);
+        assert_eq!(protected(None, &index), index.last().cloned());
+        assert_eq!(protected(None, &[]), None);
+    }
+}
@@ -0,0 +1,75 @@
+use std::{collections::BTreeMap, env, path::Path};
+
+use chrono::Local;
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::{self, Manifest, Server}, file, report, ssh};
+
+use super::log;
+
+// 补丁记录文件，每行一条 JSON 记录，最后一条为当前的补丁级别
+pub const REGISTRY_FILE: &str = ".monica/registry.jsonl";
+
+pub const ACTION_APPLY: &str = "apply";
+pub const ACTION_ROLLBACK: &str = "rollback";
+
+// 补丁记录
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Record {
+    pub action: String,
+    pub patch_id: Option<String>, // 回退时为空
+    pub manifest: Option<String>,
+    pub build: Option<String>,
+    pub backupset: Option<String>,
+    pub files: BTreeMap<String, String>, // 相对 $DBPS_HOME 的文件 -> sha256
+    pub time: String,
+    pub operator: String,
+}
+
+// 补丁编号：清单中的 build，未指定时为清单文件sha256的前12位
+pub fn patch_id(manifest: &Manifest) -> String {
+    match &manifest.build {
+        Some(b) => b.clone(),
+        None => file::sha256sum(Path::new(&config::get_manifest_file()).to_path_buf())[0..12].to_string(),
+    }
+}
+
+// 执行 monica 的本地用户
+fn operator() -> String {
+    env::var("USER").or_else(|_| env::var("USERNAME")).unwrap_or_else(|_| String::from("unknown"))
+}
+
+// 升级或回退成功后追加记录，文件及sha256取自运行报告
+pub fn append(ssh: &ssh::Client, s: &Server, dbps_home: &str, action: &str, manifest: Option<&Manifest>, e: &report::Entry) {
+    let prefix = format!("{}/", dbps_home);
+    let record = Record {
+        action: action.to_string(),
+        patch_id: manifest.filter(|_| action == ACTION_APPLY).map(patch_id),
+        manifest: manifest.map(|m| m.key.clone()),
+        build: manifest.and_then(|m| m.build.clone()),
+        backupset: e.backupset.clone(),
+        files: e.files.iter()
+            .filter_map(|f| f.remote_sha256.as_ref().map(|sum| (f.file.trim_start_matches(&prefix).to_string(), sum.clone())))
+            .collect(),
+        time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        operator: operator(),
+    };
+    let line = serde_json::to_string(&record).unwrap();
+    let (status, _, stderr) = ssh.exec_cmd_with_status(&format!("mkdir -p {}/{} && printf '%s\\n' '{}' >> {}/{}",
+        dbps_home, config::BACKUPUP_DIR, line.replace('\'', "'\\''"), dbps_home, REGISTRY_FILE));
+    if status != 0 {
+        error!("xlsx:Line: {:<2} Host: {}, Registry {}/{} write failed, cause: {}", s.rid, s.hostname, dbps_home, REGISTRY_FILE, stderr.trim_end());
+        return;
+    }
+    log(s, dbps_home, &format!("Registry {} {}", action, record.patch_id.as_deref().unwrap_or("<NONE>")));
+}
+
+// 当前的补丁级别：最后一条有效记录
+pub fn last(ssh: &ssh::Client, dbps_home: &str) -> Option<Record> {
+    let (status, stdout, _) = ssh.exec_cmd_with_status(&format!("cat {}/{}", dbps_home, REGISTRY_FILE));
+    if status != 0 {
+        return None;
+    }
+    stdout.lines().rev().find_map(|l| serde_json::from_str(l).ok())
+}
@@ -1,15 +1,72 @@
-use std::{io, process::exit, sync::{Arc, Mutex}};
+use std::{fs, io::{self, IsTerminal}, process::exit, sync::{Arc, Mutex}};
 
 use dialoguer::{theme::ColorfulTheme, Select};
 use log::info;
 use tokio::runtime;
 
-use crate::{cmd::{clean_ds, clean_dt, clean_jddm, error, get_last_datetime, log, query_log_position, startup, startup_jddm, update_yrba_file}, config::{self, current_log_position, get_db_info, Server, KFK_TYPE}, db, file::{clean_local_inventory, read_local_inventory_index}, ssh};
+use crate::{cmd::{clean_ds, clean_dt, clean_jddm, error, get_last_datetime, log, query_log_position, stop, update_yrba_file}, config::{self, current_log_position, get_db_info, Server, KFK_TYPE}, db, error::{Error, Result}, file::{clean_local_inventory, path_join, read_local_inventory_index}, report, ssh};
 
-use super::{clean_monica_cache_file, print_counter, read_log_position, JDDM_START_WITH_FILE};
+use super::{batch, clean_monica_cache_file, health, plan::Plan, print_counter, read_log_position, registry, summary::Summary, JDDM_START_WITH_FILE};
+
+// 非交互回退：指定备份集，跳过确认
+#[derive(Debug, Default)]
+pub struct Selection {
+    pub backupset: Option<String>, // BackupSet ID 或 xlsx checksum
+    pub latest: bool,
+    pub yes: bool,
+}
+
+// 按 --backupset、--latest 选择备份集，都未指定时显示菜单
+fn choose_backupset(selection: &Selection, lines: &[&str], options: &[String]) -> Result<usize> {
+    if let Some(backupset) = &selection.backupset {
+        // 格式：<BackupSet ID>:<checksum>:<行数>:<日期>
+        return lines.iter()
+            .position(|l| {
+                let mut arr = l.split(':');
+                let (id, checksum) = (arr.next(), arr.next());
+                id == Some(backupset.as_str()) || checksum == Some(backupset.as_str())
+            })
+            .ok_or_else(|| Error::NotFound(format!("BackupSet {} in {}/backupset.index", backupset, config::get_local_inventory_dir())));
+    }
+    if selection.latest {
+        return Ok(options.len() - 1);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(Error::Io(io::Error::new(io::ErrorKind::Unsupported, "stdin is not a terminal, use --backupset or --latest")));
+    }
+
+    println!("Choose BackupSet for rollback");
+    println!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
+    println!("  BackupSet ID   Date Time             BackupSet                                                          Valid Line ");
+    println!(" -------------- --------------------- ------------------------------------------------------------------ ------------");
+
+    // 创建Select实例
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .default(options.len() - 1)
+        .items(options)
+        .interact()
+        .unwrap();
+    Ok(selection)
+}
+
+// 只回退部分行时，检查这些行都在备份集的清单(input.json)中
+fn check_inventory(xlsx_checksum: &str, servers: &[&'static Server]) -> Result<()> {
+    if config::get_target().is_all() {
+        return Ok(());
+    }
+    let input_file = format!("{}/{}/input.json", config::get_local_inventory_dir(), xlsx_checksum);
+    let contents = fs::read_to_string(&input_file).map_err(|_| Error::NotFound(input_file.clone()))?;
+    let inventory: Vec<Server> = serde_json::from_str(&contents)
+        .map_err(|e| Error::Io(io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", input_file, e))))?;
+
+    match servers.iter().find(|s| !inventory.iter().any(|i| i.rid == s.rid)) {
+        Some(s) => Err(Error::NotFound(format!("Row {} in {}", s.rid, input_file))),
+        None => Ok(()),
+    }
+}
 
 // 回退操作
-pub async fn handle_command_rollback(worker_threads: usize) {
+pub async fn handle_command_rollback(worker_threads: usize, servers: &[&'static Server]) -> Result<Summary> {
 
     let contents: String = read_local_inventory_index();
     println!("");
@@ -18,60 +75,56 @@ pub async fn handle_command_rollback(worker_threads: usize) {
     if contents.is_empty() {
         println!("There are no Interim patches applied in this inventory home.");
         println!("");
-        return ;
+        return Ok(Summary::new("Rollback"));
     }
     
+    let mut lines = Vec::new();
     let mut options = Vec::new();
     for line in contents.lines(){
         if line.is_empty() {
             continue;
         }
         let mut arr = line.split(":");
-        options.push(format!("{}   {}   {}   {:>10}", arr.next().unwrap(), get_last_datetime(&line), arr.next().unwrap(), arr.next().unwrap()))
+        options.push(format!("{}   {}   {}   {:>10}", arr.next().unwrap(), get_last_datetime(&line), arr.next().unwrap(), arr.next().unwrap()));
+        lines.push(line);
     }
 
     // 无有效的备份
     if options.len() == 0 {
         println!("There are no Interim patches applied in this inventory home.");
         println!("");
-        return ;
+        return Ok(Summary::new("Rollback"));
     }
 
-    println!("Choose BackupSet for rollback");
-    println!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
-    println!("  BackupSet ID   Date Time             BackupSet                                                          Valid Line ");
-    println!(" -------------- --------------------- ------------------------------------------------------------------ ------------");
-
-        
-    // 创建Select实例
-    let selection = Select::with_theme(&ColorfulTheme::default())
-        .default(options.len() - 1)
-        .items(&options[..])
-        .interact()
-        .unwrap();
-
-    println!("* {}", options[selection]);
-    
+    // 回退前检查备份集、行，未通过时不做任何修改
+    let selection = config::get_rollback_selection();
+    let index = choose_backupset(&selection, &lines, &options)?;
+    println!("* {}", options[index]);
 
+    let xlsx_checksum = String::from(options[index].split_whitespace().nth(3).unwrap());
+    check_inventory(&xlsx_checksum, servers)?;
     println!("");
     // 回退
-    for i in 0..3 {
-        let mut input = String::new();
-        println!("Do you want to continue rollback change? ");
-        println!("WARNING: There is no UNDO for this change. [y|n] ");
-        io::stdin().read_line(&mut input).unwrap();
-        if input.starts_with("y") {
-            break;
+    if !config::is_dry_run() && !selection.yes {
+        if !io::stdin().is_terminal() {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::Unsupported, "stdin is not a terminal, use --yes")));
+        }
+        for i in 0..3 {
+            let mut input = String::new();
+            println!("Do you want to continue rollback change? ");
+            println!("WARNING: There is no UNDO for this change. [y|n] ");
+            io::stdin().read_line(&mut input).unwrap();
+            if input.starts_with("y") {
+                break;
+            }
+            if i == 2 || input.starts_with("n") {
+                println!("Bye.");
+                exit(-1);
+            } 
         }
-        if i == 2 || input.starts_with("n") {
-            println!("Bye.");
-            exit(-1);
-        } 
     }
 
-    let xlsx_checksum = String::from(options[selection].split_whitespace().nth(3).unwrap());
-
-    let dbc = db::Client::new(&get_db_info().unwrap()).await;
+    let dbc = db::Client::new(&get_db_info().unwrap()).await?;
 
     // 创建线程池
     let rt = runtime::Builder::new_multi_thread()
@@ -82,14 +135,22 @@ pub async fn handle_command_rollback(worker_threads: usize) {
             .build()
             .unwrap();
 
-    let counter = Arc::new(Mutex::new(config::GLOBAL_CONFIG.servers.len()));
+    let size = servers.len();
+    let summary = Arc::new(Summary::new("Rollback"));
+    let counter = Arc::new(Mutex::new(size));
     let mut handles = vec![];
-    for server in config::GLOBAL_CONFIG.servers.iter() {
+    for server in servers.iter().copied() {
         let counter: Arc<Mutex<usize>> = Arc::clone(&counter);
+        let summary = Arc::clone(&summary);
         let _dbc = dbc.clone();
         let checksum = xlsx_checksum.clone();
         let handle = rt.spawn(async move {
-            start_rollback_worker(&checksum, &_dbc, counter, server).await;
+            if summary.aborted() {
+                summary.record(server, Err(Error::Aborted));
+                return;
+            }
+            let result = start_rollback_worker(&checksum, &_dbc, counter, size, server).await;
+            summary.record(server, result);
         });
         handles.push(handle);
     }
@@ -100,49 +161,88 @@ pub async fn handle_command_rollback(worker_threads: usize) {
 
     rt.shutdown_background();
 
+    let summary = Arc::into_inner(summary).unwrap();
+    summary.print();
+    if config::is_dry_run() {
+        info!("Rollback dry run completed, no changes were made.");
+        println!();
+        return Ok(summary);
+    }
+
+    // 部分行失败，或只回退了部分行、角色时保留本地清单，以便再次回退
+    if !summary.is_ok() || !config::get_target().is_all() {
+        println!();
+        return Ok(summary);
+    }
+
     // 删除目录
     clean_local_inventory(&xlsx_checksum, &contents);
 
     info!("Rollback completed. Great!");
     println!("");
 
+    Ok(summary)
 }
 
 
 // 回退操作
-async fn start_rollback_worker(checksum: &str, c: &db::Client, c0: Arc<Mutex<usize>>, s: &Server) {
+async fn start_rollback_worker(checksum: &str, c: &db::Client, c0: Arc<Mutex<usize>>, size: usize, s: &Server) -> Result<()> {
     // 连接到复制机，需考虑异机部署
-    let ssh = ssh::Client::new(s);
+    let ssh = ssh::Client::new(s)?;
     // 打印进度条
-    print_counter(c0);
+    print_counter(c0, size);
 
-    start_ds_worker(&ssh, c, s, &checksum).await;
-    start_dt_worker(&ssh, s, &checksum);
-    start_jddm_worker(&ssh, s, &checksum);
+    if config::is_target_role(config::ROLE_DS) {
+        let mut e = report::Entry::new("rollback", s, config::ROLE_DS);
+        let result = start_ds_worker(&ssh, c, s, &checksum, &mut e).await;
+        report::push(e, &result);
+        result?;
+    }
+
+    if config::is_target_role(config::ROLE_DT) {
+        let mut e = report::Entry::new("rollback", s, config::ROLE_DT);
+        let result = start_dt_worker(&ssh, s, &checksum, &mut e);
+        report::push(e, &result);
+        result?;
+    }
+
+    if config::is_target_role(config::ROLE_JDDM) {
+        let mut e = report::Entry::new("rollback", s, config::ROLE_JDDM);
+        let result = start_jddm_worker(&ssh, s, &checksum, &mut e);
+        report::push(e, &result);
+        result?;
+    }
 
     info!("xlsx:Line: {:<2} Host: {}, Service: {}, Rollback completed", &s.rid, &s.hostname, &s.service_name);
+    Ok(())
 }
 
 
-fn start_dt_worker(ssh: &ssh::Client, s: &Server, xlsx_checksum: &str){
+fn start_dt_worker(ssh: &ssh::Client, s: &Server, xlsx_checksum: &str, e: &mut report::Entry) -> Result<()> {
 
-    if s.dst_type.is_none() {
-        return;
-    }
+    let input = match &s.dst_type {
+        Some(s) => s,
+        None => return Ok(())
+    };
+    e.begin();
 
     let dbps_home = match ssh.dt_dbps_home(s) {
         Some(s) => s,
         None => {
             error(s, "<NONE>", "No such directory <<<");
-            return;
+            e.fail("No such directory");
+            return Err(Error::NotFound(path_join(&s.service_base_path, &s.service_name)));
         }
     };
+    e.dbps_home = Some(dbps_home.clone());
+    batch::migrate_remote_backupset(ssh, s, &dbps_home, xlsx_checksum);
 
     // 先判断远端是否有备份
     let ls = ssh.list_remote_backupset(&dbps_home);
     if ls.len() == 0 {
         log(s, &dbps_home, "There are no Interim patches installed in this dbps home");
-        return;
+        e.set_status(report::STATUS_SKIPPED, "There are no Interim patches installed in this dbps home");
+        return Ok(());
     }
 
     // 检查文件是否存在
@@ -150,55 +250,90 @@ fn start_dt_worker(ssh: &ssh::Client, s: &Server, xlsx_checksum: &str){
     if !exists {
         // 备份文件已存在
         error(s, &dbps_home, &format!("BackupSet: {} not exists", remote_backupset_file));
-        return;
+        e.fail(&format!("BackupSet: {} not exists", remote_backupset_file));
+        return Err(Error::NotFound(remote_backupset_file));
+    }
+    e.backupset = Some(remote_backupset_file);
+
+    if config::is_dry_run() {
+        e.set_status(report::STATUS_PLANNED, "");
+        print_rollback_plan(ssh, s, config::ROLE_DT, &dbps_home, xlsx_checksum, None);
+        return Ok(());
     }
 
     // 停止程序
-    let (starting, starting2) = ssh.kill_ps(&format!("{}/bin/", dbps_home));
+    let (starting, starting2) = stop(s, config::ROLE_DT, &dbps_home, ssh);
     log(s, &dbps_home, &format!("B-Start: {}, A-Start: {}", starting, starting2));
+    e.started_before = Some(starting);
+    e.started_after_stop = Some(starting2);
 
-    // 回退远端文件
-    rollback_remote_files(&dbps_home, &ssh, xlsx_checksum);
+    // 回退远端文件，失败时仍需重置、启动任务，最后再返回错误
+    let result = rollback_remote_files(&dbps_home, &ssh, xlsx_checksum, e);
 
     // 文件上传完成后重置任务
     clean_dt(s, &dbps_home, &ssh);
 
-    if starting {
-        startup(s, &dbps_home, &ssh);
+    e.startup_issued = Some(starting);
+    let health = if starting {
+        let manifest = config::get_dt_manifest(input, ssh.get_ss_version(input, &dbps_home));
+        health::startup_and_check(ssh, s, config::ROLE_DT, &dbps_home, manifest, e)
     } else {
         log(s, &dbps_home, "Non-Start, Skip start");
         // 清理垃圾文件
         clean_monica_cache_file(&dbps_home, &ssh);
-    }
+        Ok(())
+    };
 
+    result?;
+    // 记录回退
+    registry::append(ssh, s, &dbps_home, registry::ACTION_ROLLBACK, None, e);
+    health
 }
 
 // 回退JDDM程序
-fn start_jddm_worker(ssh: &ssh::Client, s: &Server, xlsx_checksum: &str){
+fn start_jddm_worker(ssh: &ssh::Client, s: &Server, xlsx_checksum: &str, e: &mut report::Entry) -> Result<()> {
 
     let input = match &s.dst_type {
         Some(s) => s,
-        None => return
+        None => return Ok(())
     };
 
     // kafka类型
     if !input.starts_with(KFK_TYPE) {
-        return ;
+        return Ok(());
     }
+    e.begin();
 
     let dbps_home = match ssh.jddm_home(s) {
         Some(s) => s,
         None => {
             error(s, "<NONE>", "No such directory <<<");
-            return;
+            e.fail("No such directory");
+            return Err(Error::NotFound(path_join(&s.service_base_path, &s.service_name)));
         }
     };
+    e.dbps_home = Some(dbps_home.clone());
+    batch::migrate_remote_backupset(ssh, s, &dbps_home, xlsx_checksum);
 
     // 先判断远端是否有备份
     let ls = ssh.list_remote_backupset(&dbps_home);
     if ls.len() == 0 {
         log(s, &dbps_home, "There are no Interim patches installed in this dbps home");
-        return;
+        e.set_status(report::STATUS_SKIPPED, "There are no Interim patches installed in this dbps home");
+        return Ok(());
+    }
+
+    if config::is_dry_run() {
+        let (exists, remote_backupset_file) = ssh.exists_backupset(xlsx_checksum, &dbps_home);
+        if !exists {
+            error(s, &dbps_home, &format!("BackupSet: {} not exists", remote_backupset_file));
+            e.fail(&format!("BackupSet: {} not exists", remote_backupset_file));
+            return Err(Error::NotFound(remote_backupset_file));
+        }
+        e.backupset = Some(remote_backupset_file);
+        e.set_status(report::STATUS_PLANNED, "");
+        print_rollback_plan(ssh, s, config::ROLE_JDDM, &dbps_home, xlsx_checksum, None);
+        return Ok(());
     }
 
     // 将启动参数写入到 $dbps_home/bin/monica.started 中
@@ -212,48 +347,65 @@ fn start_jddm_worker(ssh: &ssh::Client, s: &Server, xlsx_checksum: &str){
     if !exists {
         // 备份文件已存在
         error(s, &dbps_home, &format!("BackupSet: {} not exists", remote_backupset_file));
-        return;
+        e.fail(&format!("BackupSet: {} not exists", remote_backupset_file));
+        return Err(Error::NotFound(remote_backupset_file));
     }
+    e.backupset = Some(remote_backupset_file);
 
     // 停止程序
     // ./startJddmKafkaEngine.sh start <service_name> <jddm_state>
-    let (starting, starting2) = ssh.kill_ps(&format!("DPath={} ", dbps_home));
+    let (starting, starting2) = stop(s, config::ROLE_JDDM, &dbps_home, ssh);
     log(s, &dbps_home, &format!("B-Start: {}, A-Start: {}", starting, starting2));
+    e.started_before = Some(starting);
+    e.started_after_stop = Some(starting2);
 
-    // 回退远端文件
-    rollback_remote_files(&dbps_home, &ssh, xlsx_checksum);
+    // 回退远端文件，失败时仍需重置、启动任务，最后再返回错误
+    let result = rollback_remote_files(&dbps_home, &ssh, xlsx_checksum, e);
 
     // 文件上传完成后重置任务
     clean_jddm(s, &dbps_home, &ssh);
 
-    if starting {
-        startup_jddm(s, &dbps_home, &ssh);
+    e.startup_issued = Some(starting);
+    let health = if starting {
+        let manifest = Some(config::get_jddm_manifest(input));
+        health::startup_and_check(ssh, s, config::ROLE_JDDM, &dbps_home, manifest, e)
     } else {
         log(s, &dbps_home, "Non-Start, Skip start");
         clean_monica_cache_file(&dbps_home, &ssh);
-    }
-        
+        Ok(())
+    };
+
+    result?;
+    // 记录回退
+    registry::append(ssh, s, &dbps_home, registry::ACTION_ROLLBACK, None, e);
+    health
 }
 
-async fn start_ds_worker(ssh: &ssh::Client, c: &db::Client, s: &Server, xlsx_checksum: &str){
+async fn start_ds_worker(ssh: &ssh::Client, c: &db::Client, s: &Server, xlsx_checksum: &str, e: &mut report::Entry) -> Result<()> {
 
-    if s.src_type.is_none() {
-        return;
-    }
+    let input = match &s.src_type {
+        Some(s) => s,
+        None => return Ok(())
+    };
+    e.begin();
     
     let dbps_home = match ssh.ds_dbps_home(s) {
         Some(s) => s,
         None => {
             error(s, "<NONE>", "No such directory <<<");
-            return;
+            e.fail("No such directory");
+            return Err(Error::NotFound(path_join(&s.service_base_path, &s.service_name)));
         }
     };
+    e.dbps_home = Some(dbps_home.clone());
+    batch::migrate_remote_backupset(ssh, s, &dbps_home, xlsx_checksum);
 
     // 先判断远端是否有备份
     let ls = ssh.list_remote_backupset(&dbps_home);
     if ls.len() == 0 {
         log(s, &dbps_home, "There are no Interim patches installed in this dbps home");
-        return;
+        e.set_status(report::STATUS_SKIPPED, "There are no Interim patches installed in this dbps home");
+        return Ok(());
     }
 
     // 检查文件是否存在
@@ -261,15 +413,33 @@ async fn start_ds_worker(ssh: &ssh::Client, c: &db::Client, s: &Server, xlsx_che
     if !exists {
         // 备份文件已存在
         error(s, &dbps_home, &format!("BackupSet: {} not exists", remote_backupset_file));
-        return;
+        e.fail(&format!("BackupSet: {} not exists", remote_backupset_file));
+        return Err(Error::NotFound(remote_backupset_file));
+    }
+    e.backupset = Some(remote_backupset_file);
+
+    if config::is_dry_run() {
+        // 位点信息：从数据库中获取，或从备份集中的 bin/monica.yrba.dat 获取
+        let yrba_dat = if current_log_position() {
+            let (valid_log_pos, yrba_dat) = query_log_position(s, c.clone()).await?;
+            if valid_log_pos { Some(yrba_dat) } else { None }
+        } else {
+            ssh.read_backupset_file(&dbps_home, xlsx_checksum, &format!("bin/{}", config::get_yrba_file_name()))
+        };
+        e.set_status(report::STATUS_PLANNED, "");
+        e.yrba_after = yrba_dat.clone();
+        print_rollback_plan(ssh, s, config::ROLE_DS, &dbps_home, xlsx_checksum, yrba_dat.as_deref());
+        return Ok(());
     }
 
     // 停止程序
-    let (starting, starting2) = ssh.kill_ps(&format!("{}/bin/", dbps_home));
+    let (starting, starting2) = stop(s, config::ROLE_DS, &dbps_home, ssh);
     log(s, &dbps_home, &format!("B-Start: {}, A-Start: {}", starting, starting2));
+    e.started_before = Some(starting);
+    e.started_after_stop = Some(starting2);
 
-    // 回退备份文件
-    rollback_remote_files(&dbps_home, &ssh, xlsx_checksum);
+    // 回退备份文件，失败时仍需重置、启动任务，最后再返回错误
+    let result = rollback_remote_files(&dbps_home, &ssh, xlsx_checksum, e);
 
     // 文件上传完成后重置任务
     clean_ds(s, &dbps_home, &ssh);
@@ -278,27 +448,48 @@ async fn start_ds_worker(ssh: &ssh::Client, c: &db::Client, s: &Server, xlsx_che
     let valid_log_pos;
     let yrba_dat;
     if current_log_position() {
-        (valid_log_pos, yrba_dat) = query_log_position(s, c.clone()).await;
+        (valid_log_pos, yrba_dat) = query_log_position(s, c.clone()).await?;
     } else {
         (valid_log_pos, yrba_dat) = read_log_position(&ssh, &dbps_home, s);
     }
 
+    e.yrba_before = ssh.get_yrba(&dbps_home);
     if valid_log_pos {
         // 写入yrba文件
         update_yrba_file(s, &dbps_home, &yrba_dat, &ssh);
     }
+    e.yrba_after = ssh.get_yrba(&dbps_home);
 
-    if starting {
-        startup(s, &dbps_home, &ssh);
+    e.startup_issued = Some(starting);
+    let health = if starting {
+        let manifest = config::get_ds_manifest(input, ssh.get_ss_version(input, &dbps_home));
+        health::startup_and_check(ssh, s, config::ROLE_DS, &dbps_home, manifest, e)
     } else {
         log(s, &dbps_home, "Non-Start, Skip start");
         clean_monica_cache_file(&dbps_home, &ssh);
-    }
-        
+        Ok(())
+    };
+
+    result?;
+    // 记录回退
+    registry::append(ssh, s, &dbps_home, registry::ACTION_ROLLBACK, None, e);
+    health
 }
 
 
-fn rollback_remote_files(dbps_home: &str, ssh: &ssh::Client, xlsx_checksum: &str){
+// 演练：输出回退计划
+fn print_rollback_plan(ssh: &ssh::Client, s: &Server, role: usize, dbps_home: &str, xlsx_checksum: &str, yrba: Option<&str>) {
+    let mut plan = Plan::new(s, role, dbps_home, None);
+    if role == config::ROLE_JDDM {
+        plan.step("Record jddm arguments", JDDM_START_WITH_FILE);
+    }
+    let starting = plan.stop(ssh, s, role, dbps_home);
+    plan.restore(xlsx_checksum);
+    plan.restart(s, role, starting, yrba);
+    plan.print();
+}
+
+fn rollback_remote_files(dbps_home: &str, ssh: &ssh::Client, xlsx_checksum: &str, e: &mut report::Entry) -> Result<()> {
 
     // 通过备份文件恢复远端程序
     // 通过备份目录中的 sha256sum.txt 检查文件是否有效
@@ -306,8 +497,12 @@ fn rollback_remote_files(dbps_home: &str, ssh: &ssh::Client, xlsx_checksum: &str
     // sha256sum -c bin/monica.sha256sum.txt
     if !ssh.exec_rollback_backupset(dbps_home, xlsx_checksum) {
         // 回退失败
-        abnormal_exit_rollback("Some files sha256sum did not pass");
+        e.fail("Some files sha256sum did not pass");
+        return Err(Error::Verify(String::from("Some files sha256sum did not pass")));
     }
+    e.record_sha256sum_file(dbps_home, &ssh.exec_cmd(&format!("cat {}/bin/{}", dbps_home, config::BACKUPUP_SHA256SUM_FILENAME)));
+    e.set_status(report::STATUS_SUCCESS, "");
+    Ok(())
 }
 
 
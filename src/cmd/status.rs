@@ -0,0 +1,103 @@
+use std::{collections::BTreeMap, path::Path};
+
+use comfy_table::Table;
+use log::{error, info, warn};
+
+use crate::{config::{self, get_role_name, Manifest, Server}, file, ssh};
+
+use super::{registry::{self, ACTION_ROLLBACK}, role_homes};
+
+const STATUS_OK: &str = "OK";
+const STATUS_NOT_APPLIED: &str = "NOT APPLIED";
+const STATUS_ROLLED_BACK: &str = "ROLLED BACK";
+const STATUS_DRIFT: &str = "DRIFT";
+const STATUS_MODIFIED: &str = "MODIFIED";
+
+// 每个 ds/dt/jddm 目录的补丁级别
+struct Level {
+    dbps_home: String,
+    installed: String,
+    applied_on: String,
+    expected: String,
+    status: String,
+}
+
+// 清单中文件的sha256：优先使用签名清单中的记录，否则计算本地已解压的文件
+fn expected_files(manifest: &Manifest) -> BTreeMap<String, String> {
+    if !manifest.checksums.is_empty() {
+        return manifest.checksums.clone();
+    }
+    manifest.file.iter().filter_map(|f| {
+        let local_file = Path::new(&config::get_basedir()).join(&manifest.dir).join(f);
+        if local_file.exists() { Some((f.clone(), file::sha256sum(local_file))) } else { None }
+    }).collect()
+}
+
+// 对比远端记录、远端文件与当前清单
+fn check_level(ssh: &ssh::Client, dbps_home: String, manifest: Option<&Manifest>) -> Level {
+    let expected = manifest.map(registry::patch_id).unwrap_or_else(|| String::from("<NONE>"));
+    let record = registry::last(ssh, &dbps_home);
+    let (installed, applied_on) = match &record {
+        Some(r) if r.action == ACTION_ROLLBACK => (String::from("<ROLLED BACK>"), r.time.clone()),
+        Some(r) => (r.patch_id.clone().unwrap_or_default(), r.time.clone()),
+        None => (String::from("<NONE>"), String::new()),
+    };
+
+    let status = match (&record, manifest) {
+        (None, _) => STATUS_NOT_APPLIED,
+        (Some(r), _) if r.action == ACTION_ROLLBACK => STATUS_ROLLED_BACK,
+        (Some(r), Some(m)) if r.patch_id.as_deref() != Some(expected.as_str()) || r.manifest.as_deref() != Some(m.key.as_str()) => STATUS_DRIFT,
+        (Some(r), Some(m)) => {
+            // 记录之后远端文件被修改，或与当前清单的文件不一致
+            let remote = ssh.sha256sum_files(&dbps_home, &m.file);
+            let expected_files = expected_files(m);
+            let modified = m.file.iter().any(|f| {
+                remote.get(f) != r.files.get(f) || expected_files.get(f).is_some_and(|sum| remote.get(f) != Some(sum))
+            });
+            if modified { STATUS_MODIFIED } else { STATUS_OK }
+        },
+        (Some(_), None) => STATUS_DRIFT,
+    };
+
+    Level { dbps_home, installed, applied_on, expected, status: status.to_string() }
+}
+
+// 连接每一行，输出各目录的补丁级别，与当前清单不一致的标记出来
+// 返回是否全部一致
+pub fn handle_command_status(servers: &[&'static Server]) -> bool {
+    let mut table = Table::new();
+    table.set_header(vec!["Line", "Host", "Service", "Role", "DBPS_HOME", "Installed", "Applied on", "Expected", "Status"]);
+
+    let mut ok = true;
+    for s in servers.iter().copied() {
+        let levels: Vec<(&str, Result<Level, String>)> = match ssh::Client::new(s) {
+            Ok(ssh) => role_homes(&ssh, s).into_iter()
+                .map(|(role, home)| (get_role_name(role), home.map(|(home, manifest)| check_level(&ssh, home, manifest))))
+                .collect(),
+            Err(e) => vec![("", Err(e.to_string()))],
+        };
+        for (role, level) in levels {
+            let mut row = vec![s.rid.to_string(), s.hostname.clone(), s.service_name.clone(), role.to_string()];
+            match level {
+                Ok(l) => {
+                    if l.status == STATUS_OK {
+                        info!("xlsx:Line: {:<2} Host: {}, DBPS_HOME: {}, Patch level {}", s.rid, s.hostname, l.dbps_home, l.installed);
+                    } else {
+                        warn!("xlsx:Line: {:<2} Host: {}, DBPS_HOME: {}, {}, installed {}, expected {}", s.rid, s.hostname, l.dbps_home, l.status, l.installed, l.expected);
+                        ok = false;
+                    }
+                    row.extend([l.dbps_home, l.installed, l.applied_on, l.expected, l.status]);
+                },
+                Err(e) => {
+                    error!("xlsx:Line: {:<2} Host: {}, Status failed, cause: {}", s.rid, s.hostname, e);
+                    row.extend([String::new(), String::new(), String::new(), String::new(), format!("FAILED, {}", e)]);
+                    ok = false;
+                },
+            }
+            table.add_row(row);
+        }
+    }
+
+    println!("\nStatus summary:\n{}\n", table);
+    ok
+}
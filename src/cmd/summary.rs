@@ -0,0 +1,104 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Mutex};
+
+use comfy_table::Table;
+use log::error;
+
+use crate::{config::{self, Server}, error::{Error, Result}};
+
+// 每行的执行结果
+struct Row {
+    server: &'static Server,
+    result: Result<()>,
+}
+
+// 命令执行汇总：记录每行的成功或失败
+// --fail-fast 时，有行失败后不再启动新的行，已启动的行继续执行完成
+pub struct Summary {
+    phase: &'static str,
+    abort: AtomicBool,
+    rows: Mutex<Vec<Row>>,
+}
+
+impl Summary {
+
+    pub fn new(phase: &'static str) -> Self {
+        Summary { phase, abort: AtomicBool::new(false), rows: Mutex::new(Vec::new()) }
+    }
+
+    // 是否已终止
+    pub fn aborted(&self) -> bool {
+        self.abort.load(Ordering::SeqCst)
+    }
+
+    pub fn record(&self, s: &'static Server, result: Result<()>) {
+        if let Err(e) = &result {
+            if !matches!(e, Error::Aborted) {
+                error!("xlsx:Line: {:<2} Host: {}, Service: {}, {} failed, cause: {}", s.rid, s.hostname, s.service_name, self.phase, e);
+                if !config::is_continue_on_error() {
+                    self.abort.store(true, Ordering::SeqCst);
+                }
+            }
+        }
+        self.rows.lock().unwrap().push(Row { server: s, result });
+    }
+
+    // 已记录为成功的行改为失败，如：分批升级后任务未运行
+    pub fn fail(&self, s: &'static Server, e: Error) {
+        error!("xlsx:Line: {:<2} Host: {}, Service: {}, {} failed, cause: {}", s.rid, s.hostname, s.service_name, self.phase, e);
+        if let Some(row) = self.rows.lock().unwrap().iter_mut().find(|r| r.server.rid == s.rid) {
+            row.result = Err(e);
+        }
+        if !config::is_continue_on_error() {
+            self.abort.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // 指定行中失败的行数，未执行的行不计入
+    pub fn failed_count(&self, servers: &[&'static Server]) -> usize {
+        self.rows.lock().unwrap().iter()
+            .filter(|r| servers.iter().any(|s| s.rid == r.server.rid))
+            .filter(|r| matches!(r.result, Err(ref e) if !matches!(e, Error::Aborted)))
+            .count()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.rows.lock().unwrap().iter().all(|r| r.result.is_ok())
+    }
+
+    // 执行成功的行，按行号排序
+    pub fn passed(&self) -> Vec<&'static Server> {
+        let mut servers: Vec<&'static Server> = self.rows.lock().unwrap().iter()
+            .filter(|r| r.result.is_ok())
+            .map(|r| r.server)
+            .collect();
+        servers.sort_by_key(|s| s.rid);
+        servers
+    }
+
+    // 第一个失败的原因
+    pub fn cause(&self) -> Option<String> {
+        let rows = self.rows.lock().unwrap();
+        let mut failed: Vec<&Row> = rows.iter().filter(|r| matches!(r.result, Err(ref e) if !matches!(e, Error::Aborted))).collect();
+        failed.sort_by_key(|r| r.server.rid);
+        failed.first().map(|r| format!("Row {}: {}", r.server.rid, r.result.as_ref().unwrap_err()))
+    }
+
+    // 打印每行的执行结果
+    pub fn print(&self) {
+        let mut rows = self.rows.lock().unwrap();
+        rows.sort_by_key(|r| r.server.rid);
+
+        let mut table = Table::new();
+        table.set_header(vec!["Line", "Host", "Service", "Result", "Cause"]);
+        for r in rows.iter() {
+            let (result, cause) = match &r.result {
+                Ok(_) => ("OK", String::new()),
+                Err(Error::Aborted) => ("SKIPPED", Error::Aborted.to_string()),
+                Err(e) => ("FAILED", e.to_string()),
+            };
+            table.add_row(vec![r.server.rid.to_string(), r.server.hostname.clone(), r.server.service_name.clone(), result.to_string(), cause]);
+        }
+        println!("\n{} summary:\n{}\n", self.phase, table);
+    }
+
+}
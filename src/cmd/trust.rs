@@ -0,0 +1,35 @@
+use std::collections::HashSet;
+
+use comfy_table::Table;
+use log::{error, info};
+
+use crate::{config::{self, Server}, ssh};
+
+// 收集并固定每一行的主机密钥，同一主机、端口只处理一次
+// 返回是否全部成功
+pub fn handle_command_trust(servers: &[&'static Server]) -> bool {
+    let mut table = Table::new();
+    table.set_header(vec!["Line", "Host", "Port", "Key type", "Fingerprint", "Result"]);
+
+    let mut seen = HashSet::new();
+    let mut ok = true;
+    for s in servers {
+        if !seen.insert(format!("{}:{}", s.hostname, s.port)) {
+            continue;
+        }
+        match ssh::trust_host(s) {
+            Ok((key_type, fingerprint, status)) => {
+                info!("xlsx:Line: {:<2} Host: {}:{}, {} {} {}", s.rid, s.hostname, s.port, key_type, fingerprint, status);
+                table.add_row(vec![s.rid.to_string(), s.hostname.clone(), s.port.clone(), key_type, fingerprint, status.to_string()]);
+            },
+            Err(e) => {
+                error!("xlsx:Line: {:<2} Host: {}:{}, Host key collect failed, cause: {}", s.rid, s.hostname, s.port, e);
+                table.add_row(vec![s.rid.to_string(), s.hostname.clone(), s.port.clone(), String::new(), String::new(), format!("failed, {}", e)]);
+                ok = false;
+            },
+        }
+    }
+
+    println!("\nTrust summary:\n{}\nKnown hosts file  : {}\n", table, config::get_known_hosts_file());
+    ok
+}
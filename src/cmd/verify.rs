@@ -0,0 +1,109 @@
+use std::{collections::HashMap, path::Path};
+
+use comfy_table::Table;
+use log::{error, info, warn};
+
+use crate::{config::{self, get_role_name, Manifest, Server, BACKUPUP_FILE_PREFIX, BACKUPUP_SHA256SUM_FILENAME}, file, ssh};
+
+use super::{batch, role_homes};
+
+const FILE_PATCHED: &str = "patched";
+const FILE_ORIGINAL: &str = "original";
+const FILE_UNKNOWN: &str = "unknown";
+const FILE_MISSING: &str = "missing";
+
+// 升级前的sha256：当前输入文件的备份集，不存在时使用最近的备份集
+// 返回 (备份集, <相对路径, sha256sum>)
+fn original_sums(ssh: &ssh::Client, dbps_home: &str, xlsx_checksum: &str) -> Option<(String, HashMap<String, String>)> {
+    let mut checksums = vec![xlsx_checksum.to_string()];
+    if let Some(last) = ssh.list_remote_backupset(dbps_home).last() {
+        // .monica/backupset-<checksum>.tar
+        if let Some(c) = last.rsplit('/').next().and_then(|n| n.strip_prefix(&format!("{}-", BACKUPUP_FILE_PREFIX))).and_then(|n| n.strip_suffix(".tar")) {
+            checksums.push(c.to_string());
+        }
+    }
+    checksums.into_iter().find_map(|c| {
+        let contents = ssh.read_backupset_file(dbps_home, &c, &format!("bin/{}", BACKUPUP_SHA256SUM_FILENAME))?;
+        let sums = contents.lines()
+            .filter_map(|l| l.split_once(' '))
+            .map(|(sum, f)| (f.trim_start_matches([' ', '*']).to_string(), sum.to_string()))
+            .collect();
+        Some((format!("{}-{}.tar", BACKUPUP_FILE_PREFIX, c), sums))
+    })
+}
+
+// 升级后的sha256：本地解压的文件，不存在时使用签名清单中的记录
+fn patched_sum(manifest: &Manifest, f: &str) -> Option<String> {
+    let local_file = Path::new(&config::get_basedir()).join(&manifest.dir).join(f);
+    if local_file.exists() {
+        return Some(file::sha256sum(local_file));
+    }
+    manifest.checksums.get(f).cloned()
+}
+
+// 每个文件的状态：patched 与本地升级包一致，original 与备份集一致，否则为 unknown
+fn verify_home(ssh: &ssh::Client, s: &Server, role: usize, dbps_home: &str, manifest: &Manifest, xlsx_checksum: &str, table: &mut Table) -> bool {
+    let remote = ssh.sha256sum_files(dbps_home, &manifest.file);
+    let original = original_sums(ssh, dbps_home, xlsx_checksum);
+    let backupset = original.as_ref().map(|(b, _)| b.clone()).unwrap_or_else(|| String::from("<NONE>"));
+
+    let mut ok = true;
+    for f in manifest.file.iter() {
+        let sum = remote.get(f);
+        let result = match sum {
+            None => FILE_MISSING,
+            Some(sum) if patched_sum(manifest, f).as_ref() == Some(sum) => FILE_PATCHED,
+            Some(sum) if original.as_ref().is_some_and(|(_, o)| o.get(f) == Some(sum)) => FILE_ORIGINAL,
+            Some(_) => FILE_UNKNOWN,
+        };
+        let remote_file = file::path_join(dbps_home, f);
+        if result == FILE_UNKNOWN || result == FILE_MISSING {
+            warn!("xlsx:Line: {:<2} Host: {}, File {}, {} <<<", s.rid, s.hostname, remote_file, result);
+            ok = false;
+        } else {
+            info!("xlsx:Line: {:<2} Host: {}, File {}, {}", s.rid, s.hostname, remote_file, result);
+        }
+        table.add_row(vec![
+            s.rid.to_string(), s.hostname.clone(), get_role_name(role).to_string(), remote_file,
+            sum.map(|s| s[0..12].to_string()).unwrap_or_default(), backupset.clone(), result.to_string(),
+        ]);
+    }
+    ok
+}
+
+// 连接每一行，比较清单中的远端文件与本地升级包、备份集，找出被手工替换的文件
+// 返回是否没有 unknown 或 missing 的文件
+pub fn handle_command_verify(servers: &[&'static Server]) -> bool {
+    let xlsx_checksum = batch::batch_id();
+    let mut table = Table::new();
+    table.set_header(vec!["Line", "Host", "Role", "File", "SHA-256", "Backupset", "Result"]);
+
+    let mut ok = true;
+    for s in servers.iter().copied() {
+        let ssh = match ssh::Client::new(s) {
+            Ok(ssh) => ssh,
+            Err(e) => {
+                error!("xlsx:Line: {:<2} Host: {}, Verify failed, cause: {}", s.rid, s.hostname, e);
+                table.add_row(vec![s.rid.to_string(), s.hostname.clone(), String::new(), String::new(), String::new(), String::new(), format!("failed, {}", e)]);
+                ok = false;
+                continue;
+            }
+        };
+        for (role, home) in role_homes(&ssh, s) {
+            let cause = match home {
+                Ok((dbps_home, Some(manifest))) => {
+                    ok &= verify_home(&ssh, s, role, &dbps_home, manifest, &xlsx_checksum, &mut table);
+                    continue;
+                },
+                Ok((dbps_home, None)) => format!("no manifest for {}", dbps_home),
+                Err(e) => e,
+            };
+            error!("xlsx:Line: {:<2} Host: {}, Verify failed, cause: {}", s.rid, s.hostname, cause);
+            table.add_row(vec![s.rid.to_string(), s.hostname.clone(), get_role_name(role).to_string(), String::new(), String::new(), String::new(), format!("failed, {}", cause)]);
+            ok = false;
+        }
+    }
+
+    println!("\nVerify summary:\n{}\n", table);
+    ok
+}
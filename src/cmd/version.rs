@@ -0,0 +1,73 @@
+use log::warn;
+
+use crate::{config::{self, Manifest, Server, ROLE_DS, VERSION_CHECK_OFF, VERSION_CHECK_WARN}, error::{Error, Result}, report, ssh};
+
+use super::{error, log};
+
+// 版本匹配，* 结尾时按前缀匹配
+fn build_matches(pattern: &str, version: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => version.starts_with(prefix),
+        None => pattern == version,
+    }
+}
+
+// 检查已安装版本：已是升级后的版本，或在清单的 from 中时通过
+// 清单未指定 from 时不检查，不匹配时按 --version-check 拒绝或告警
+pub fn check_build(ssh: &ssh::Client, s: &Server, role: usize, dbps_home: &str, manifest: &Manifest, e: &mut report::Entry) -> Result<()> {
+    let mode = config::get_version_check();
+    if mode == VERSION_CHECK_OFF || (manifest.from.is_empty() && manifest.build.is_none()) {
+        return Ok(());
+    }
+
+    let sd_type = if role == ROLE_DS { &s.src_type } else { &s.dst_type };
+    let installed = ssh.get_build_version(role, sd_type.as_deref().unwrap_or_default(), dbps_home, manifest);
+    e.installed_build = installed.clone();
+    let target = manifest.build.as_deref().unwrap_or("<NONE>");
+
+    let cause = match &installed {
+        Some(v) if manifest.build.as_deref() == Some(v.as_str()) => {
+            log(s, dbps_home, &format!("Installed build {}, already the target build", v));
+            return Ok(());
+        },
+        Some(v) if manifest.from.is_empty() || manifest.from.iter().any(|p| build_matches(p, v)) => {
+            log(s, dbps_home, &format!("Installed build {}, target build {}", v, target));
+            return Ok(());
+        },
+        Some(v) => format!("installed build {} not in [{}] of manifest {}", v, manifest.from.join(", "), manifest.key),
+        None if manifest.from.is_empty() => {
+            log(s, dbps_home, &format!("Installed build unknown, target build {}", target));
+            return Ok(());
+        },
+        None => format!("installed build unknown, manifest {} requires [{}]", manifest.key, manifest.from.join(", ")),
+    };
+
+    if mode == VERSION_CHECK_WARN {
+        warn!("xlsx:Line: {:<2} Host: {}, Path: {}, Build mismatch, {}", s.rid, s.hostname, dbps_home, cause);
+        return Ok(());
+    }
+    error(s, dbps_home, &format!("Build mismatch, {} <<<", cause));
+    e.fail(&format!("Build mismatch, {}", cause));
+    Err(Error::Build(cause))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_build() {
+        assert!(build_matches("5.2.1.20240601", "5.2.1.20240601"));
+        assert!(!build_matches("5.2.1.20240601", "5.2.1.20240602"));
+        assert!(!build_matches("5.2.1", "5.2.1.20240601"));
+    }
+
+    #[test]
+    fn wildcard_build() {
+        assert!(build_matches("5.2.*", "5.2.1.20240601"));
+        assert!(build_matches("5.2.*", "5.2."));
+        assert!(!build_matches("5.2.*", "5.20.1"));
+        assert!(!build_matches("5.2.*", "5.3.0"));
+        assert!(build_matches("*", "anything"));
+    }
+}
@@ -0,0 +1,127 @@
+use std::{collections::HashMap, io, time::Duration};
+
+use log::{info, warn};
+
+use crate::{config::{self, Server}, error::Error, ssh};
+
+use super::summary::Summary;
+
+// 标记为金丝雀的行，输入文件N列
+pub const CANARY_TAG: &str = "canary";
+
+// 每批的行数
+#[derive(Debug, Clone, Copy)]
+pub enum WaveSize {
+    Rows(usize),
+    Percent(usize),
+}
+
+// 分批升级参数
+#[derive(Debug, Default)]
+pub struct Rollout {
+    pub canary: Option<usize>,
+    pub size: Option<WaveSize>,
+    pub soak: u64,
+    pub pause: bool,
+    pub max_failure: usize, // 失败率超过该百分比时停止
+}
+
+fn is_canary(s: &Server) -> bool {
+    s.tags.as_deref().is_some_and(|t| t.split(',').any(|t| t.trim().eq_ignore_ascii_case(CANARY_TAG)))
+}
+
+// 分批：金丝雀(标记为 canary 的行，或 --canary 指定的前N行)，其余的行按 --wave-size 分批
+// 未指定时所有行为一批
+pub fn split(servers: &[&'static Server], rollout: &Rollout) -> Vec<Vec<&'static Server>> {
+    let mut waves = Vec::new();
+    let mut rest: Vec<&'static Server> = servers.to_vec();
+
+    let tagged: Vec<&'static Server> = rest.iter().copied().filter(|s| is_canary(s)).collect();
+    let canary = if !tagged.is_empty() {
+        tagged
+    } else {
+        rest.iter().copied().take(rollout.canary.unwrap_or(0)).collect()
+    };
+    if !canary.is_empty() {
+        rest.retain(|s| !canary.iter().any(|c| c.rid == s.rid));
+        waves.push(canary);
+    }
+
+    let size = match rollout.size {
+        Some(WaveSize::Rows(n)) => n,
+        Some(WaveSize::Percent(p)) => (servers.len() * p).div_ceil(100).max(1),
+        None => rest.len().max(1),
+    };
+    for chunk in rest.chunks(size) {
+        waves.push(chunk.to_vec());
+    }
+    waves
+}
+
+// 本批升级后重新启动的任务是否仍在运行，未运行的行记为失败
+// started 为各行已发出启动命令的 (角色, 目录)
+fn check_running(wave: &[&'static Server], started: &HashMap<usize, Vec<(usize, String)>>, summary: &Summary) {
+    for s in summary.passed().into_iter().filter(|s| wave.iter().any(|w| w.rid == s.rid)) {
+        let ssh = match ssh::Client::new(s) {
+            Ok(ssh) => ssh,
+            Err(e) => {
+                summary.fail(s, e);
+                continue;
+            }
+        };
+        for (role, dbps_home) in started.get(&s.rid).into_iter().flatten() {
+            if !ssh.check_valid_ps(*role, dbps_home) {
+                summary.fail(s, Error::Health(format!("{} is not running after the wave", dbps_home)));
+                break;
+            }
+        }
+    }
+}
+
+// 询问是否继续下一批
+fn confirm(next: usize, count: usize) -> bool {
+    for i in 0..3 {
+        let mut input = String::new();
+        println!("Do you want to continue with wave {}/{}? [y|n] ", next, count);
+        io::stdin().read_line(&mut input).unwrap();
+        if input.starts_with("y") {
+            return true;
+        }
+        if i == 2 || input.starts_with("n") {
+            return false;
+        }
+    }
+    false
+}
+
+// 每批完成后(包括最后一批)：等待 --wave-soak 秒，检查任务是否运行，失败率超过 --max-wave-failure 时停止
+// 不是最后一批且 --wave-pause 时由操作员确认后继续，返回是否继续下一批
+// 未分批且未指定 --wave-soak 时不检查
+pub async fn settle(wave: &[&'static Server], started: &HashMap<usize, Vec<(usize, String)>>, index: usize, count: usize, rollout: &Rollout, summary: &Summary) -> bool {
+    if rollout.soak > 0 && !config::is_dry_run() {
+        info!("Wave {}/{} completed, soaking for {}s", index, count, rollout.soak);
+        tokio::time::sleep(Duration::from_secs(rollout.soak)).await;
+    }
+    if count > 1 || rollout.soak > 0 {
+        check_running(wave, started, summary);
+    }
+
+    // 只有一批时不输出批次信息
+    let failed = summary.failed_count(wave);
+    let exceeded = failed * 100 > rollout.max_failure * wave.len();
+    if count > 1 && exceeded {
+        warn!("Wave {}/{} failure rate {}% ({}/{}) is above {}%{}", index, count, failed * 100 / wave.len(), failed, wave.len(), rollout.max_failure,
+            if index < count { ", rollout stopped" } else { "" });
+    } else if count > 1 {
+        info!("Wave {}/{} completed, {}/{} rows failed", index, count, failed, wave.len());
+    }
+
+    if exceeded || index == count || summary.aborted() {
+        return false;
+    }
+    if rollout.pause && !config::is_dry_run() && !confirm(index + 1, count) {
+        warn!("Rollout stopped by the operator after wave {}/{}", index, count);
+        return false;
+    }
+    true
+}
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, fs::{self, File}, io::{self, Error}, process::exit};
+use std::{collections::{BTreeMap, HashMap}, env, fs::{self, File}, io::{self, Error}, path::Path, process::exit};
 
 use lazy_static::lazy_static;
 use log::error;
@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 use calamine::{open_workbook, Reader, Xlsx};
 
-use crate::db::{self, DBInfo};
+use crate::{cmd::{health::HealthCheck, prune::Retention, rollback::Selection, wave::{Rollout, WaveSize}}, db::{self, DBInfo}};
 
 lazy_static! {
     pub static ref METADATA: Metadata = get_metadata().unwrap();
@@ -27,12 +27,55 @@ pub const ROLE_DS: usize = 0;
 pub const ROLE_DT: usize = 10;
 pub const ROLE_JDDM: usize = 11;
 
+pub fn get_role_name(role: usize) -> &'static str {
+    match role {
+        ROLE_DS => "ds",
+        ROLE_DT => "dt",
+        _ => "jddm",
+    }
+}
+
+pub const ROLE_NAMES: &[&str] = &["ds", "dt", "jddm"];
+
+fn get_role(name: &str) -> usize {
+    match name {
+        "ds" => ROLE_DS,
+        "dt" => ROLE_DT,
+        _ => ROLE_JDDM,
+    }
+}
+
+// SSH认证方式
+pub const AUTH_PASSWORD: &str = "password";
+pub const AUTH_PUBLICKEY: &str = "publickey";
+pub const AUTH_AGENT: &str = "agent";
+pub const AUTH_KEYBOARD_INTERACTIVE: &str = "keyboard-interactive";
+pub const AUTH_METHODS: &[&str] = &[AUTH_PASSWORD, AUTH_PUBLICKEY, AUTH_AGENT, AUTH_KEYBOARD_INTERACTIVE];
+
+// 主机密钥检查：strict 必须已在 known_hosts 中，accept-new 自动添加新主机，warn 只输出警告
+pub const HOST_KEY_STRICT: &str = "strict";
+pub const HOST_KEY_ACCEPT_NEW: &str = "accept-new";
+pub const HOST_KEY_WARN: &str = "warn";
+pub const HOST_KEY_CHECKS: &[&str] = &[HOST_KEY_STRICT, HOST_KEY_ACCEPT_NEW, HOST_KEY_WARN];
+
+// 停止方式：script 停止脚本 -> SIGTERM -> SIGKILL，term SIGTERM -> SIGKILL，kill 直接 SIGKILL
+pub const STOP_SCRIPT: &str = "script";
+pub const STOP_TERM: &str = "term";
+pub const STOP_KILL: &str = "kill";
+pub const STOP_STRATEGIES: &[&str] = &[STOP_SCRIPT, STOP_TERM, STOP_KILL];
+
+// 已安装版本不在清单允许范围内时的处理方式
+pub const VERSION_CHECK_REFUSE: &str = "refuse";
+pub const VERSION_CHECK_WARN: &str = "warn";
+pub const VERSION_CHECK_OFF: &str = "off";
+pub const VERSION_CHECKS: &[&str] = &[VERSION_CHECK_REFUSE, VERSION_CHECK_WARN, VERSION_CHECK_OFF];
+
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct GlobalConfig {
     pub servers: Vec<Server>
 }
 
-// 主机名	端口	协议	用户名	密码	基础目录	服务名
+// 主机名	端口	协议	用户名	密码	基础目录	服务名	源库类型	目标端类型	认证方式	私钥文件	私钥密码	跳板机	标签
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Server {
     pub rid: usize,
@@ -45,6 +88,11 @@ pub struct Server {
     pub service_name: String, // 服务名
     pub src_type: Option<String>,  // 源端数据库类型
     pub dst_type: Option<String>,  // 目标端类型
+    pub auth_method: Option<String>, // 认证方式: password, publickey, agent, keyboard-interactive
+    pub private_key: Option<String>, // 私钥文件
+    pub passphrase: Option<String>, // 私钥密码
+    pub jump_host: Option<String>, // 跳板机: [user@]host[:port]
+    pub tags: Option<String>, // 标签，逗号分隔，如：canary
 }
 
 impl Server {
@@ -89,9 +137,58 @@ pub struct Metadata {
 // 需要更新的包
 #[derive(Deserialize, Serialize)]
 pub struct Manifest {
+    #[serde(skip)]
+    pub key: String, // manifest.json 中的键，如：ORACLE_19.3.0.0.0.Linux.x86_64
     pub package: String, 
     pub dir: String,
     pub file: Vec<String>, // 需升级的文件
+    #[serde(default)]
+    pub process: Vec<String>, // 启动后需运行的进程，如：bin/pmon
+    #[serde(default)]
+    pub build: Option<String>, // 升级后的版本，如：5.2.1.20240601
+    #[serde(default)]
+    pub from: Vec<String>, // 允许升级的已安装版本，支持 * 结尾，如：5.2.*
+    #[serde(default)]
+    pub version_command: Option<String>, // 在 $DBPS_HOME 下执行，输出中读取已安装版本，默认按角色读取：ds/dt 为 bin/pmon -v，jddm 为 jar 包的版本
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>, // 包的sha256，由 monica sign 写入
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, String>, // 解压后各文件的sha256，如：bin/pmon -> <sha256>
+}
+
+// 目标行、角色：--rows --host --service --role --exclude
+#[derive(Debug, Default)]
+pub struct Target {
+    pub rows: Vec<(usize, usize)>,
+    pub hosts: Vec<String>,
+    pub services: Vec<String>,
+    pub roles: Vec<usize>,
+    pub exclude: Vec<String>, // 行号范围、主机名或服务名
+}
+
+impl Target {
+
+    // 未指定任何条件
+    pub fn is_all(&self) -> bool {
+        self.rows.is_empty() && self.hosts.is_empty() && self.services.is_empty() && self.roles.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn contains(&self, s: &Server) -> bool {
+        let in_rows = |rows: &[(usize, usize)]| rows.iter().any(|(start, end)| s.rid >= *start && s.rid <= *end);
+        let excluded = self.exclude.iter().any(|x| match parse_rows(x) {
+            Ok(range) => in_rows(&[range]),
+            Err(_) => *x == s.hostname || *x == s.service_name,
+        });
+        (self.rows.is_empty() || in_rows(&self.rows))
+            && (self.hosts.is_empty() || self.hosts.contains(&s.hostname))
+            && (self.services.is_empty() || self.services.contains(&s.service_name))
+            && !excluded
+    }
+
+    pub fn contains_role(&self, role: usize) -> bool {
+        self.roles.is_empty() || self.roles.contains(&role)
+    }
+
 }
 
 // 通用参数
@@ -125,10 +222,58 @@ pub struct ComArgument {
     #[structopt(short, long, default_value="2")]
     pub xlsx_start_with: usize,
 
+    /// Batch ID for backupsets, checkpoints and the local inventory, default derived from the input rows and the manifest.
+    #[structopt(long, parse(try_from_str=parse_batch_id))]
+    pub batch_id: Option<String>,
+
+    /// SSH authentication method: password, publickey, agent, keyboard-interactive. Overridden by column J.
+    #[structopt(long, possible_values = AUTH_METHODS)]
+    pub auth_method: Option<String>,
+
+    /// SSH private key file. Overridden by column K.
+    #[structopt(long)]
+    pub private_key: Option<String>,
+
+    /// SSH private key passphrase. Overridden by column L.
+    #[structopt(long)]
+    pub passphrase: Option<String>,
+
+    /// SSH jump host [user@]host[:port], authenticated like the target. Overridden by column M.
+    #[structopt(long)]
+    pub jump_host: Option<String>,
+
+    /// SSH host key checking: strict, accept-new, warn
+    #[structopt(long, possible_values = HOST_KEY_CHECKS, default_value = HOST_KEY_ACCEPT_NEW)]
+    pub host_key_check: String,
+
+    /// known_hosts file, default <Current dir>/<datadir>/known_hosts
+    #[structopt(long)]
+    pub known_hosts: Option<String>,
+
     /// read the latest log location on database.
     #[structopt(short="l", long)]
     pub current_log_position: bool,
 
+    /// Only these rows, e.g. 3-7,9
+    #[structopt(long, use_delimiter = true, parse(try_from_str=parse_rows))]
+    pub rows: Vec<(usize, usize)>,
+
+    /// Only rows on these hosts (column A).
+    #[structopt(long, use_delimiter = true)]
+    pub host: Vec<String>,
+
+    /// Only rows of these services (column G).
+    #[structopt(long, use_delimiter = true)]
+    pub service: Vec<String>,
+
+    /// Only these roles, e.g. ds,jddm
+    #[structopt(long, use_delimiter = true, possible_values = ROLE_NAMES)]
+    pub role: Vec<String>,
+
+    /// Skip these rows, hosts or services, e.g. 5,192.168.6.12,s3
+    #[structopt(long, use_delimiter = true)]
+    pub exclude: Vec<String>,
+
 }
 
 // 通用参数
@@ -162,51 +307,117 @@ pub struct PreCheckArgument {
     #[structopt(short, long, default_value="2")]
     pub xlsx_start_with: usize,
 
+    /// Batch ID for backupsets, checkpoints and the local inventory, default derived from the input rows and the manifest.
+    #[structopt(long, parse(try_from_str=parse_batch_id))]
+    pub batch_id: Option<String>,
+
+    /// SSH authentication method: password, publickey, agent, keyboard-interactive. Overridden by column J.
+    #[structopt(long, possible_values = AUTH_METHODS)]
+    pub auth_method: Option<String>,
+
+    /// SSH private key file. Overridden by column K.
+    #[structopt(long)]
+    pub private_key: Option<String>,
+
+    /// SSH private key passphrase. Overridden by column L.
+    #[structopt(long)]
+    pub passphrase: Option<String>,
+
+    /// SSH jump host [user@]host[:port], authenticated like the target. Overridden by column M.
+    #[structopt(long)]
+    pub jump_host: Option<String>,
+
+    /// SSH host key checking: strict, accept-new, warn
+    #[structopt(long, possible_values = HOST_KEY_CHECKS, default_value = HOST_KEY_ACCEPT_NEW)]
+    pub host_key_check: String,
+
+    /// known_hosts file, default <Current dir>/<datadir>/known_hosts
+    #[structopt(long)]
+    pub known_hosts: Option<String>,
+
+    /// Vault file for ${VAULT:NAME} passwords, default <Current dir>/<datadir>/vault.json
+    #[structopt(long)]
+    pub vault: Option<String>,
+
+    /// Ed25519 public key file, the manifest must be signed by this key (<manifest-file>.sig) and list the sha256 of every package and file.
+    #[structopt(long)]
+    pub trusted_key: Option<String>,
+
+    /// Installed build not listed in the manifest "from": refuse, warn, off
+    #[structopt(long, possible_values = VERSION_CHECKS, default_value = VERSION_CHECK_REFUSE)]
+    pub version_check: String,
+
+    /// Stop starting new rows after the first failed row (default).
+    #[structopt(long, conflicts_with = "continue-on-error")]
+    pub fail_fast: bool,
+
+    /// Keep processing the other rows when a row fails, failed rows are listed in the summary.
+    #[structopt(long)]
+    pub continue_on_error: bool,
+
+    /// Only these rows, e.g. 3-7,9
+    #[structopt(long, use_delimiter = true, parse(try_from_str=parse_rows))]
+    pub rows: Vec<(usize, usize)>,
+
+    /// Only rows on these hosts (column A).
+    #[structopt(long, use_delimiter = true)]
+    pub host: Vec<String>,
+
+    /// Only rows of these services (column G).
+    #[structopt(long, use_delimiter = true)]
+    pub service: Vec<String>,
+
+    /// Only these roles, e.g. ds,jddm
+    #[structopt(long, use_delimiter = true, possible_values = ROLE_NAMES)]
+    pub role: Vec<String>,
+
+    /// Skip these rows, hosts or services, e.g. 5,192.168.6.12,s3
+    #[structopt(long, use_delimiter = true)]
+    pub exclude: Vec<String>,
+
 }
 
-// 补丁升级专用参数
+// 固定主机密钥参数
 #[derive(Debug, StructOpt)]
-pub struct PatchArgument {
+pub struct TrustArgument {
 
-    ////////////////////////////////////////////////////////////////////////////
-    // #[structopt(subcommand)]
-    // pub com: ComArgument,
-    #[structopt(short, long)]
-    pub debug: bool,
+    #[structopt(flatten)]
+    pub common: PreCheckArgument,
 
-    /// DataXone install dir
-    #[structopt(short = "D", long, default_value = "/data/dataxone")]
-    pub basedir: String,
+    /// Replace a pinned host key that has changed, the old and new fingerprints are logged.
+    #[structopt(long)]
+    pub replace: bool,
 
-    /// <Current dir>/.monica
-    #[structopt(long, default_value = ".monica")]
-    pub datadir: String,
+}
 
-    /// User input file
-    #[structopt(short, long, parse(try_from_str=parse_file_path))]
-    pub input_file: String,
-    
-    /// Manifest file, Read only.
-    #[structopt(short, long, parse(try_from_str=parse_file_path))]
-    pub manifest_file: String,
+// 清理备份集参数
+#[derive(Debug, StructOpt)]
+pub struct PruneArgument {
 
-    /// Parallel workers
-    #[structopt(short, long, default_value="1")]
-    pub worker_threads: usize,
+    #[structopt(flatten)]
+    pub common: PreCheckArgument,
 
-    /// User input file read start with number. 
-    #[structopt(short, long, default_value="2")]
-    pub xlsx_start_with: usize,
-    ////////////////////////////////////////////////////////////////////////////
+    /// Keep the newest N backupsets in each DBPS_HOME, at least 1.
+    #[structopt(long, parse(try_from_str=parse_keep))]
+    pub keep: Option<usize>,
 
-    #[structopt(short = "c", long, default_value = "16384")]
-    pub chunk_size: usize,
+    /// Only delete backupsets older than D days.
+    #[structopt(long)]
+    pub older_than: Option<u64>,
 
-    #[structopt(short, long)]
-    pub quiet: bool,
+    /// Only delete the recycle bin and orphaned temporary backupsets.
+    #[structopt(long)]
+    pub recyclebin_only: bool,
 
-    #[structopt(short, long = "--skip-check")]
-    pub skip_check: bool,
+    /// List what would be deleted without deleting it.
+    #[structopt(long)]
+    pub dry_run: bool,
+
+}
+
+// 平台数据库参数
+#[derive(Debug, StructOpt)]
+pub struct DbArgument {
 
     /// DataXone platform database host
     #[structopt(short = "h", long)]
@@ -220,10 +431,107 @@ pub struct PatchArgument {
     #[structopt(short = "u", long, default_value = "dataxone")]
     pub mysql_username: String,
 
-    /// DataXone platform database user password
+    /// DataXone platform database user password, or a reference: ${ENV:NAME}, ${VAULT:NAME}, ${PROMPT}
     #[structopt( short = "p", long)]
     pub mysql_password: String,
 
+}
+
+// 停止任务的方式
+#[derive(Debug, StructOpt)]
+pub struct StopArgument {
+
+    /// How to stop ds processes: script, term, kill
+    #[structopt(long, possible_values = STOP_STRATEGIES, default_value = STOP_SCRIPT)]
+    pub ds_stop: String,
+
+    /// How to stop dt processes: script, term, kill
+    #[structopt(long, possible_values = STOP_STRATEGIES, default_value = STOP_SCRIPT)]
+    pub dt_stop: String,
+
+    /// How to stop jddm processes: script, term, kill
+    #[structopt(long, possible_values = STOP_STRATEGIES, default_value = STOP_SCRIPT)]
+    pub jddm_stop: String,
+
+    /// Seconds to wait after the stop script and after SIGTERM before the next stage.
+    #[structopt(long, default_value = "30")]
+    pub stop_grace: u64,
+
+}
+
+// 启动后的检查参数
+#[derive(Debug, StructOpt)]
+pub struct HealthArgument {
+
+    /// Seconds to wait for restarted services to come back up, 0 (default) to skip the health check.
+    #[structopt(long, default_value = "0")]
+    pub health_timeout: u64,
+
+    /// Log file under DBPS_HOME to watch after startup, e.g. log/pmon.log
+    #[structopt(long)]
+    pub health_log: Option<String>,
+
+    /// Pattern in the health log that means the service started.
+    #[structopt(long, requires = "health-log")]
+    pub health_pattern: Option<String>,
+
+    /// Pattern in the health log that means the service failed to start.
+    #[structopt(long, requires = "health-log")]
+    pub health_fail_pattern: Option<String>,
+
+}
+
+// 补丁升级专用参数
+#[derive(Debug, StructOpt)]
+pub struct PatchArgument {
+
+    #[structopt(flatten)]
+    pub common: PreCheckArgument,
+
+    #[structopt(short = "c", long, default_value = "16384")]
+    pub chunk_size: usize,
+
+    /// Upload rate limit shared by all hosts in bytes per second, e.g. 512K, 10M
+    #[structopt(long, parse(try_from_str=parse_bandwidth))]
+    pub max_bandwidth: Option<u64>,
+
+    /// Upload rate limit for each host in bytes per second, e.g. 512K, 10M
+    #[structopt(long, parse(try_from_str=parse_bandwidth))]
+    pub max_host_bandwidth: Option<u64>,
+
+    /// Maximum number of files uploaded at the same time, 0 for no limit.
+    #[structopt(long, default_value = "0")]
+    pub max_transfers: usize,
+
+    #[structopt(short, long)]
+    pub quiet: bool,
+
+    /// Patch the first N rows as a canary wave, rows tagged canary in column N take precedence.
+    #[structopt(long)]
+    pub canary: Option<usize>,
+
+    /// Rows in each wave after the canary, a number or a percentage of all rows, e.g. 10 or 25%
+    #[structopt(long, parse(try_from_str=parse_wave_size))]
+    pub wave_size: Option<WaveSize>,
+
+    /// Seconds to wait after each wave before checking that its services are running.
+    #[structopt(long, default_value = "0")]
+    pub wave_soak: u64,
+
+    /// Ask for confirmation before starting the next wave.
+    #[structopt(long)]
+    pub wave_pause: bool,
+
+    /// Stop the rollout when the failure rate of a wave is above this percentage.
+    #[structopt(long, default_value = "0")]
+    pub max_wave_failure: usize,
+
+    #[structopt(short, long = "--skip-check")]
+    pub skip_check: bool,
+
+    #[structopt(flatten)]
+    pub db: DbArgument,
+
     /// Skip backup and backup again, Skip appied patch and apply patch again.
     #[structopt(short, long)]
     pub force: bool,
@@ -232,15 +540,151 @@ pub struct PatchArgument {
     #[structopt(short="l", long)]
     pub current_log_position: bool,
 
+    /// Print the remote plan for each row without changing any host.
+    #[structopt(long)]
+    pub dry_run: bool,
+
+    #[structopt(flatten)]
+    pub health: HealthArgument,
+
+    /// Restore the row from its backupset when the health check fails after patch.
+    #[structopt(long)]
+    pub rollback_on_health_failure: bool,
+
+    #[structopt(flatten)]
+    pub stop: StopArgument,
+
+}
+
+// 备份专用参数，备份时始终从平台数据库读取位点
+#[derive(Debug, StructOpt)]
+pub struct BackupArgument {
+
+    #[structopt(flatten)]
+    pub common: PreCheckArgument,
+
+    #[structopt(flatten)]
+    pub db: DbArgument,
+
+    /// Skip backup and backup again.
+    #[structopt(short, long)]
+    pub force: bool,
+
+    /// Print the remote plan for each row without changing any host.
+    #[structopt(long)]
+    pub dry_run: bool,
+
+}
+
+// 回退专用参数
+#[derive(Debug, StructOpt)]
+pub struct RollbackArgument {
+
+    #[structopt(flatten)]
+    pub common: PreCheckArgument,
+
+    /// BackupSet ID or xlsx checksum from inventory/backupset.index, instead of the menu.
+    #[structopt(long, conflicts_with = "latest")]
+    pub backupset: Option<String>,
+
+    /// Use the latest BackupSet in inventory/backupset.index, instead of the menu.
+    #[structopt(long)]
+    pub latest: bool,
+
+    /// Do not ask for confirmation.
+    #[structopt(short = "y", long)]
+    pub yes: bool,
+
+    #[structopt(flatten)]
+    pub db: DbArgument,
+
+    /// Read the latest log location from DataXone database.
+    #[structopt(short="l", long)]
+    pub current_log_position: bool,
+
+    /// Print the remote plan for each row without changing any host.
+    #[structopt(long)]
+    pub dry_run: bool,
+
+    #[structopt(flatten)]
+    pub health: HealthArgument,
+
+    #[structopt(flatten)]
+    pub stop: StopArgument,
+
 }
 
 #[derive(Debug, StructOpt)]
 pub enum Command {
     Patch(PatchArgument),
-    Rollback(PatchArgument),
+    Rollback(RollbackArgument),
     Precheck(PreCheckArgument),
     Lsinventory(ComArgument),
-    Backup(PatchArgument),
+    Backup(BackupArgument),
+    /// Collect and pin the SSH host key of every row in the known_hosts file.
+    Trust(TrustArgument),
+    /// Show the patch level recorded in every ds/dt/jddm home and flag homes that drift from the manifest.
+    Status(PreCheckArgument),
+    /// Compare the remote manifest files with the local package and the backupset, report each file as patched, original or unknown.
+    Verify(PreCheckArgument),
+    /// Delete old backupsets, the recycle bin and orphaned temporary backupsets in every DBPS_HOME by a retention policy.
+    Prune(PruneArgument),
+    /// Manage the encrypted vault referenced by ${VAULT:NAME} passwords.
+    Vault(VaultArgument),
+    /// Record the sha256 of every package and file in the manifest and sign it with an ed25519 key.
+    Sign(SignArgument),
+}
+
+// 密码库参数，不指定 --set、--remove 时列出已保存的名称
+#[derive(Debug, StructOpt)]
+pub struct VaultArgument {
+
+    #[structopt(short, long)]
+    pub debug: bool,
+
+    /// <Current dir>/.monica
+    #[structopt(long, default_value = ".monica")]
+    pub datadir: String,
+
+    /// Vault file, default <Current dir>/<datadir>/vault.json
+    #[structopt(long)]
+    pub vault: Option<String>,
+
+    /// Store a secret under this name, the value is prompted.
+    #[structopt(long, conflicts_with = "remove")]
+    pub set: Option<String>,
+
+    /// Remove the secret with this name.
+    #[structopt(long)]
+    pub remove: Option<String>,
+}
+
+// 签名参数：解压各个包，将包及文件的sha256写入清单，再生成 <manifest-file>.sig
+#[derive(Debug, StructOpt)]
+pub struct SignArgument {
+
+    #[structopt(short, long)]
+    pub debug: bool,
+
+    /// DataXone install dir
+    #[structopt(short = "D", long, default_value = "/data/dataxone")]
+    pub basedir: String,
+
+    /// <Current dir>/.monica
+    #[structopt(long, default_value = ".monica")]
+    pub datadir: String,
+
+    /// Manifest file, updated with the sha256 of every package and file.
+    #[structopt(short, long, parse(try_from_str=parse_file_path))]
+    pub manifest_file: String,
+
+    /// Ed25519 private key file, the public key is <key>.pub
+    #[structopt(short, long)]
+    pub key: String,
+
+    /// Generate a new key pair before signing.
+    #[structopt(long)]
+    pub generate: bool,
 }
 
 #[derive(Debug, StructOpt)]
@@ -259,46 +703,108 @@ fn parse_file_path(p: &str) -> Result<String, io::Error> {
     }
 }
 
+// 批次编号：用于目录及文件名，只能包含字母、数字、. _ -
+fn parse_batch_id(p: &str) -> Result<String, String> {
+    if !p.is_empty() && p.len() <= 64 && p.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) && !p.starts_with('.') {
+        Ok(String::from(p))
+    } else {
+        Err(format!("invalid batch id: {}", p))
+    }
+}
+
+// 每批的行数：<数字> 或 <数字>%
+fn parse_wave_size(p: &str) -> Result<WaveSize, String> {
+    let (num, percent) = match p.strip_suffix('%') {
+        Some(n) => (n, true),
+        None => (p, false),
+    };
+    match num.trim().parse::<usize>() {
+        Ok(n) if n > 0 && percent && n <= 100 => Ok(WaveSize::Percent(n)),
+        Ok(n) if n > 0 && !percent => Ok(WaveSize::Rows(n)),
+        _ => Err(format!("invalid wave size: {}", p)),
+    }
+}
+
+// 行号范围：<行号> 或 <起始行号>-<结束行号>
+fn parse_rows(p: &str) -> Result<(usize, usize), String> {
+    let (start, end) = p.split_once('-').unwrap_or((p, p));
+    match (start.trim().parse::<usize>(), end.trim().parse::<usize>()) {
+        (Ok(start), Ok(end)) if start <= end => Ok((start, end)),
+        _ => Err(format!("invalid rows: {}", p)),
+    }
+}
+
+// 保留的备份集个数，至少保留1个
+fn parse_keep(p: &str) -> Result<usize, String> {
+    match p.trim().parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid keep: {}, at least 1", p)),
+    }
+}
+
+// 带宽：<数字>[K|M|G]，单位为字节/秒
+fn parse_bandwidth(p: &str) -> Result<u64, String> {
+    let (num, unit) = match p.to_uppercase().chars().last() {
+        Some('K') => (&p[..p.len() - 1], 1024),
+        Some('M') => (&p[..p.len() - 1], 1024 * 1024),
+        Some('G') => (&p[..p.len() - 1], 1024 * 1024 * 1024),
+        _ => (p, 1),
+    };
+    match num.trim().parse::<u64>() {
+        Ok(n) if n > 0 => n.checked_mul(unit).ok_or(format!("bandwidth too large: {}", p)),
+        _ => Err(format!("invalid bandwidth: {}", p)),
+    }
+}
+
 pub fn get_input_file() -> String {
     match Opt::from_args().command {
-        Command::Patch(a) | Command::Backup(a) | Command::Rollback(a) => {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => {
             a.input_file
         },
-        Command::Precheck(a) => {
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => {
             a.input_file
         },
         Command::Lsinventory(a) => {
             a.input_file
         },
+        Command::Vault(_) | Command::Sign(_) => String::new(),
     }
 }
 
 pub fn get_manifest_file() -> String {
     match Opt::from_args().command {
-        Command::Patch(a) | Command::Backup(a) | Command::Rollback(a) => {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => {
             a.manifest_file
         },
-        Command::Precheck(a) => {
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => {
             a.manifest_file
         },
         Command::Lsinventory(a) => {
             a.manifest_file
         },
+        Command::Sign(a) => {
+            a.manifest_file
+        },
+        Command::Vault(_) => String::new(),
     }
 }
 
 pub fn get_basedir() -> String {
     
     match Opt::from_args().command {
-        Command::Patch(a) | Command::Backup(a) | Command::Rollback(a) => {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => {
             a.basedir
         },
-        Command::Precheck(a) => {
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => {
             a.basedir
         },
         Command::Lsinventory(a)  => {
             a.basedir
         },
+        Command::Sign(a) => {
+            a.basedir
+        },
+        Command::Vault(_) => String::new(),
     }
 
 }
@@ -306,52 +812,175 @@ pub fn get_basedir() -> String {
 
 pub fn get_datadir() -> String {
     match Opt::from_args().command {
-        Command::Patch(a) | Command::Backup(a) | Command::Rollback(a) => {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => {
             a.datadir
         },
-        Command::Precheck(a) => {
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => {
             a.datadir
         },
         Command::Lsinventory(a) => {
             a.datadir
         },
+        Command::Vault(a) => {
+            a.datadir
+        },
+        Command::Sign(a) => {
+            a.datadir
+        },
     }
 }
 
 pub fn get_debug() -> bool {
     match Opt::from_args().command {
-        Command::Patch(a) | Command::Backup(a) | Command::Rollback(a) => {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => {
             a.debug
         },
-        Command::Precheck(a) => {
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => {
             a.debug
         },
         Command::Lsinventory(a) => {
             a.debug
         },
+        Command::Vault(a) => {
+            a.debug
+        },
+        Command::Sign(a) => {
+            a.debug
+        },
     }
 }
 
 pub fn get_xlsx_start_with() -> usize {
     match Opt::from_args().command {
-        Command::Patch(a) | Command::Backup(a) | Command::Rollback(a) => {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => {
             a.xlsx_start_with
         },
-        Command::Precheck(a) => {
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => {
             a.xlsx_start_with
         },
         Command::Lsinventory(a) => {
             a.xlsx_start_with
         },
+        Command::Vault(_) | Command::Sign(_) => 0,
+    }
+}
+
+pub fn get_auth_method() -> Option<String> {
+    match Opt::from_args().command {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => {
+            a.auth_method
+        },
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => {
+            a.auth_method
+        },
+        Command::Lsinventory(a) => a.auth_method,
+        Command::Vault(_) | Command::Sign(_) => None,
+    }
+}
+
+pub fn get_private_key() -> Option<String> {
+    match Opt::from_args().command {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => {
+            a.private_key
+        },
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => {
+            a.private_key
+        },
+        Command::Lsinventory(a) => a.private_key,
+        Command::Vault(_) | Command::Sign(_) => None,
+    }
+}
+
+pub fn get_passphrase() -> Option<String> {
+    match Opt::from_args().command {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => {
+            a.passphrase
+        },
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => {
+            a.passphrase
+        },
+        Command::Lsinventory(a) => a.passphrase,
+        Command::Vault(_) | Command::Sign(_) => None,
+    }
+}
+
+pub fn get_jump_host() -> Option<String> {
+    match Opt::from_args().command {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => {
+            a.jump_host
+        },
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => {
+            a.jump_host
+        },
+        Command::Lsinventory(a) => a.jump_host,
+        Command::Vault(_) | Command::Sign(_) => None,
+    }
+}
+
+pub fn get_vault_file() -> String {
+    let vault = match Opt::from_args().command {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => a.vault,
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => a.vault,
+        Command::Vault(a) => a.vault,
+        Command::Lsinventory(_) | Command::Sign(_) => None,
+    };
+    vault.unwrap_or_else(|| format!("{}/{}/vault.json", env::current_dir().unwrap().display(), get_datadir()))
+}
+
+pub fn get_batch_id() -> Option<String> {
+    match Opt::from_args().command {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => a.batch_id,
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => a.batch_id,
+        Command::Lsinventory(a) => a.batch_id,
+        Command::Vault(_) | Command::Sign(_) => None,
+    }
+}
+
+pub fn get_trusted_key() -> Option<String> {
+    match Opt::from_args().command {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => a.trusted_key,
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => a.trusted_key,
+        Command::Lsinventory(_) | Command::Vault(_) | Command::Sign(_) => None,
+    }
+}
+
+pub fn get_version_check() -> String {
+    match Opt::from_args().command {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => a.version_check,
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => a.version_check,
+        Command::Lsinventory(_) | Command::Vault(_) | Command::Sign(_) => String::from(VERSION_CHECK_OFF),
+    }
+}
+
+pub fn get_host_key_check() -> String {
+    match Opt::from_args().command {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => {
+            a.host_key_check
+        },
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => {
+            a.host_key_check
+        },
+        Command::Lsinventory(a) => a.host_key_check,
+        Command::Vault(_) | Command::Sign(_) => String::from(HOST_KEY_ACCEPT_NEW),
     }
 }
 
+pub fn get_known_hosts_file() -> String {
+    let known_hosts = match Opt::from_args().command {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => a.known_hosts,
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => a.known_hosts,
+        Command::Lsinventory(a) => a.known_hosts,
+        Command::Vault(_) | Command::Sign(_) => None,
+    };
+    known_hosts.unwrap_or_else(|| format!("{}/{}/known_hosts", env::current_dir().unwrap().display(), get_datadir()))
+}
+
 // pub fn get_worker_threads() -> usize {
 //     match Opt::from_args().command {
 //         Command::Patch(a) | Command::Rollback(a)  | Command::Backup(a)  => {
 //             a.worker_threads
 //         },
-//         Command::Precheck(a) => {
+//         Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => {
 //             a.worker_threads
 //         },
 //         _ => {
@@ -369,15 +998,164 @@ pub fn get_chunk_size() -> usize {
     }
 }
 
+// 全局限速、每台主机的限速，字节/秒
+pub fn get_max_bandwidth() -> (Option<u64>, Option<u64>) {
+    match Opt::from_args().command {
+        Command::Patch(a) => {
+            (a.max_bandwidth, a.max_host_bandwidth)
+        },
+        _ => (None, None),
+    }
+}
+
+pub fn get_max_transfers() -> usize {
+    match Opt::from_args().command {
+        Command::Patch(a) => {
+            a.max_transfers
+        },
+        _ => 0,
+    }
+}
+
 pub fn is_force() -> bool {
     match Opt::from_args().command {
-        Command::Patch(a) | Command::Backup(a) => {
-            a.force
+        Command::Patch(PatchArgument { force, .. }) | Command::Backup(BackupArgument { force, .. }) => {
+            force
         },
         _ => false,
     }
 }
 
+pub fn is_continue_on_error() -> bool {
+    match Opt::from_args().command {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => {
+            a.continue_on_error && !a.fail_fast
+        },
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => {
+            a.continue_on_error && !a.fail_fast
+        },
+        Command::Lsinventory(_) | Command::Vault(_) | Command::Sign(_) => false,
+    }
+}
+
+// 分批升级参数
+pub fn get_rollout() -> Rollout {
+    match Opt::from_args().command {
+        Command::Patch(a) => {
+            Rollout {
+                canary: a.canary,
+                size: a.wave_size,
+                soak: a.wave_soak,
+                pause: a.wave_pause,
+                max_failure: a.max_wave_failure,
+            }
+        },
+        _ => Rollout::default(),
+    }
+}
+
+// 目标行、角色，未指定时为所有
+pub fn get_target() -> Target {
+    let (rows, hosts, services, roles, exclude) = match Opt::from_args().command {
+        Command::Patch(PatchArgument { common: a, .. }) | Command::Backup(BackupArgument { common: a, .. }) | Command::Rollback(RollbackArgument { common: a, .. }) => (a.rows, a.host, a.service, a.role, a.exclude),
+        Command::Precheck(a) | Command::Trust(TrustArgument { common: a, .. }) | Command::Status(a) | Command::Verify(a) | Command::Prune(PruneArgument { common: a, .. }) => (a.rows, a.host, a.service, a.role, a.exclude),
+        Command::Lsinventory(a) => (a.rows, a.host, a.service, a.role, a.exclude),
+        Command::Vault(_) | Command::Sign(_) => return Target::default(),
+    };
+    Target { rows, hosts, services, roles: roles.iter().map(|r| get_role(r)).collect(), exclude }
+}
+
+// 角色是否在 --role 中
+pub fn is_target_role(role: usize) -> bool {
+    get_target().contains_role(role)
+}
+
+// 非交互回退参数
+pub fn get_rollback_selection() -> Selection {
+    match Opt::from_args().command {
+        Command::Rollback(a) => {
+            Selection {
+                backupset: a.backupset,
+                latest: a.latest,
+                yes: a.yes,
+            }
+        },
+        _ => Selection::default(),
+    }
+}
+
+// 备份集保留策略
+pub fn get_retention() -> Retention {
+    match Opt::from_args().command {
+        Command::Prune(a) => {
+            Retention {
+                keep: a.keep,
+                older_than: a.older_than,
+                recyclebin_only: a.recyclebin_only,
+            }
+        },
+        _ => Retention::default(),
+    }
+}
+
+// 启动后的检查，--health-timeout 0 时不检查
+pub fn get_health_check() -> Option<HealthCheck> {
+    match Opt::from_args().command {
+        Command::Patch(PatchArgument { health: a, rollback_on_health_failure: rollback, .. }) if a.health_timeout > 0 => {
+            Some(HealthCheck {
+                timeout: a.health_timeout,
+                log_file: a.health_log,
+                pattern: a.health_pattern,
+                fail_pattern: a.health_fail_pattern,
+                rollback,
+            })
+        },
+        Command::Rollback(RollbackArgument { health: a, .. }) if a.health_timeout > 0 => {
+            Some(HealthCheck {
+                timeout: a.health_timeout,
+                log_file: a.health_log,
+                pattern: a.health_pattern,
+                fail_pattern: a.health_fail_pattern,
+                rollback: false,
+            })
+        },
+        _ => None,
+    }
+}
+
+// 角色的停止方式，及每一步的等待时间
+pub fn get_stop_strategy(role: usize) -> (String, u64) {
+    match Opt::from_args().command {
+        Command::Patch(PatchArgument { stop: a, .. }) | Command::Rollback(RollbackArgument { stop: a, .. }) => {
+            let strategy = match role {
+                ROLE_DS => a.ds_stop,
+                ROLE_DT => a.dt_stop,
+                _ => a.jddm_stop,
+            };
+            (strategy, a.stop_grace)
+        },
+        _ => (String::from(STOP_KILL), 0),
+    }
+}
+
+// 主机密钥已变化时是否替换
+pub fn is_replace_host_key() -> bool {
+    match Opt::from_args().command {
+        Command::Trust(a) => a.replace,
+        _ => false,
+    }
+}
+
+pub fn is_dry_run() -> bool {
+    match Opt::from_args().command {
+        Command::Patch(PatchArgument { dry_run, .. }) | Command::Backup(BackupArgument { dry_run, .. }) | Command::Rollback(RollbackArgument { dry_run, .. }) => {
+            dry_run
+        },
+        Command::Prune(a) => a.dry_run,
+        _ => false,
+    }
+}
+
 pub fn current_log_position() -> bool {
     match Opt::from_args().command {
         Command::Patch(a) => {
@@ -396,7 +1174,7 @@ pub fn current_log_position() -> bool {
 pub fn get_db_info() -> Option<DBInfo> {
 
     match Opt::from_args().command {
-        Command::Patch(a) | Command::Backup(a) | Command::Rollback(a) => {
+        Command::Patch(PatchArgument { db: a, .. }) | Command::Backup(BackupArgument { db: a, .. }) | Command::Rollback(RollbackArgument { db: a, .. }) => {
             let dbi = db::DBInfo{
                 db_host: a.mysql_host,
                 db_port: a.mysql_port,
@@ -418,11 +1196,31 @@ pub fn get_local_inventory_dir() -> String {
 }
 
 // read config file
+// 根据文件扩展名选择清单格式：csv、yaml/yml、toml，其他按xlsx读取
 fn get_config() -> Option<GlobalConfig> {
+    let input_file = get_input_file();
+    let extension = Path::new(&input_file).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let servers = match extension.as_str() {
+        "csv" => read_csv_config(&input_file),
+        "yaml" | "yml" => read_yaml_config(&input_file),
+        "toml" => read_toml_config(&input_file),
+        _ => read_xlsx_config(&input_file),
+    };
+
     let mut config = GlobalConfig::default();
-    let mut workbook: Xlsx<_>  = open_workbook(get_input_file()).expect("Cannot open file");
-    config.servers = Vec::new();
     let mut data: HashMap<String, usize> = HashMap::new();
+    for s in servers {
+        check_server(&s, &mut data);
+        config.servers.push(s);
+    }
+
+    Some(config)
+}
+
+// 读取xlsx，列位置固定
+fn read_xlsx_config(input_file: &str) -> Vec<Server> {
+    let mut servers = Vec::new();
+    let mut workbook: Xlsx<_>  = open_workbook(input_file).expect("Cannot open file");
     let xlsx_start_with = get_xlsx_start_with();
 
     for sheet in workbook.sheet_names() {
@@ -431,7 +1229,7 @@ fn get_config() -> Option<GlobalConfig> {
             let rid = rindex + 1;
             if rid < xlsx_start_with {
                 // 排除表头
-                println!("Open {}, Sheet {}, start with {}, skip Line {}", get_input_file(), sheet.to_string(), xlsx_start_with, rid);
+                println!("Open {}, Sheet {}, start with {}, skip Line {}", input_file, sheet, xlsx_start_with, rid);
                 continue;
             }
             let mut s = Server::default();
@@ -446,6 +1244,9 @@ fn get_config() -> Option<GlobalConfig> {
                 // cell6:服务名
                 // cell7:源库类型
                 // cell8:目标端类型
+                // cell9:认证方式
+                // cell10:私钥文件
+                // cell11:私钥密码
                 match index {
                     0 => {
                         if cell.to_string().is_empty() {
@@ -504,6 +1305,31 @@ fn get_config() -> Option<GlobalConfig> {
                     } else {
                         Some(cell.to_string().to_uppercase())
                     },
+                    9 => s.auth_method = if cell.to_string().is_empty() {
+                        None
+                    } else {
+                        Some(cell.to_string().to_lowercase())
+                    },
+                    10 => s.private_key = if cell.to_string().is_empty() {
+                        None
+                    } else {
+                        Some(cell.to_string())
+                    },
+                    11 => s.passphrase = if cell.to_string().is_empty() {
+                        None
+                    } else {
+                        Some(cell.to_string())
+                    },
+                    12 => s.jump_host = if cell.to_string().is_empty() {
+                        None
+                    } else {
+                        Some(cell.to_string())
+                    },
+                    13 => s.tags = if cell.to_string().is_empty() {
+                        None
+                    } else {
+                        Some(cell.to_string())
+                    },
                     _ => {
                         error!("Data check failed, invalid index {} on row {}", index, rid);
                         exit(-1);
@@ -513,80 +1339,230 @@ fn get_config() -> Option<GlobalConfig> {
                 
             }
 
-            match &s.src_type {
-                Some(src_type) => {
-                    if src_type == "ORACLE" {
+            // 行编号
+            s.rid = rid;
+            servers.push(s);
+        }
+    }
 
-                    } else if !METADATA.ds.contains_key(src_type) {
-                        error!("Data check failed, invalid src_type {} on row {}", src_type, rid);
-                        exit(-1);
-                    }
-                },
-                None => {}
-            }
-            
-            match &s.dst_type {
-                Some(dst_type) => {
-                    if dst_type == "ORACLE" {
+    servers
+}
 
-                    } else if !METADATA.dt.contains_key(dst_type) {
-                        error!("Data check failed, invalid dst_type {} on row {}", dst_type, rid);
-                        exit(-1);
-                    }
-                },
-                None => {
-                    if s.src_type.is_none() {
-                        // 非法输出
-                        error!("Data check failed, src_type and dst_type Cannot be empty");
-                        abnormal_exit_precheck("Column H and column I cannot be empty");
-                    }
-                }
+// 读取csv，首行为字段名
+fn read_csv_config(input_file: &str) -> Vec<Server> {
+    let mut reader = csv::Reader::from_path(input_file).expect("Cannot open file");
+    let headers = reader.headers().expect("Cannot read csv header").clone();
+    let mut servers = Vec::new();
+    for (rindex, record) in reader.records().enumerate() {
+        // 首行为表头，数据行从第2行开始
+        let rid = rindex + 2;
+        let record = match record {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Data check failed, invalid csv record on row {}, cause: {}", rid, e);
+                exit(-1);
             }
+        };
+        let fields = headers.iter().map(|h| h.trim().to_string()).zip(record.iter().map(|v| v.trim().to_string())).collect();
+        servers.push(server_from_fields(rid, fields));
+    }
+    servers
+}
 
-            // 检查重复数据
-            let key = s.to_hash();
-
-            let v = match data.get(&key) {
-                Some(v) => {
-                    Some(v)
+// 读取yaml，格式：
+// servers:
+//   - hostname: 192.168.1.10
+//     port: 22
+//     ...
+fn read_yaml_config(input_file: &str) -> Vec<Server> {
+    let contents = fs::read_to_string(input_file).expect("Cannot open file");
+    let value: serde_yaml::Value = match serde_yaml::from_str(&contents) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Data check failed, invalid yaml file {}, cause: {}", input_file, e);
+            exit(-1);
+        }
+    };
+    let rows = match value.get("servers").and_then(|v| v.as_sequence()) {
+        Some(rows) => rows.clone(),
+        None => {
+            error!("Data check failed, the key `servers` must be a list");
+            exit(-1);
+        }
+    };
+
+    let mut servers = Vec::new();
+    for (rindex, row) in rows.iter().enumerate() {
+        let rid = rindex + 1;
+        let mut fields = Vec::new();
+        for (k, v) in row.as_mapping().into_iter().flatten() {
+            let key = k.as_str().unwrap_or_default().to_string();
+            let value = match v {
+                serde_yaml::Value::Null => String::new(),
+                serde_yaml::Value::String(v) => v.clone(),
+                serde_yaml::Value::Number(v) => v.to_string(),
+                serde_yaml::Value::Bool(v) => v.to_string(),
+                _ => {
+                    error!("Data check failed, invalid value of {} on row {}", key, rid);
+                    exit(-1);
                 }
-                None => {
-                    match data.get(&s.to_hash_with_src()) {
-                        Some(v) => {
-                            Some(v)
-                        },
-                        None => {
-                            match data.get(&s.to_hash_with_dst()) {
-                                Some(v) => {
-                                    Some(v)
-                                },
-                                None => None
-                            }
-                        }
-                    }
+            };
+            fields.push((key, value));
+        }
+        servers.push(server_from_fields(rid, fields));
+    }
+    servers
+}
+
+// 读取toml，格式：
+// [[servers]]
+// hostname = "192.168.1.10"
+// port = 22
+// ...
+fn read_toml_config(input_file: &str) -> Vec<Server> {
+    let contents = fs::read_to_string(input_file).expect("Cannot open file");
+    let value: toml::Table = match toml::from_str(&contents) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Data check failed, invalid toml file {}, cause: {}", input_file, e);
+            exit(-1);
+        }
+    };
+    let rows = match value.get("servers").and_then(|v| v.as_array()) {
+        Some(rows) => rows.clone(),
+        None => {
+            error!("Data check failed, the key `servers` must be an array of tables");
+            exit(-1);
+        }
+    };
+
+    let mut servers = Vec::new();
+    for (rindex, row) in rows.iter().enumerate() {
+        let rid = rindex + 1;
+        let mut fields = Vec::new();
+        for (key, v) in row.as_table().into_iter().flatten() {
+            let value = match v {
+                toml::Value::String(v) => v.clone(),
+                toml::Value::Integer(v) => v.to_string(),
+                toml::Value::Float(v) => v.to_string(),
+                toml::Value::Boolean(v) => v.to_string(),
+                _ => {
+                    error!("Data check failed, invalid value of {} on row {}", key, rid);
+                    exit(-1);
                 }
             };
+            fields.push((key.clone(), value));
+        }
+        servers.push(server_from_fields(rid, fields));
+    }
+    servers
+}
 
-            if v.is_some() {
-                error!("Data check failed");
-                // The third and fourth rows conflict
-                abnormal_exit_precheck(&format!("The data in the {} and {} rows conflict", rid, v.unwrap()));
-                break;
+// 按字段名映射到 Server，字段名与 Server 的字段一致
+fn server_from_fields(rid: usize, fields: Vec<(String, String)>) -> Server {
+    let mut s = Server::default();
+    for (key, value) in fields {
+        let value = if value.is_empty() { None } else { Some(value) };
+        match key.as_str() {
+            "hostname" => s.hostname = value.unwrap_or_default(),
+            "port" => s.port = value.unwrap_or_default(),
+            "protocol" => s.protocol = value.unwrap_or_default(),
+            "username" => s.username = value.unwrap_or_default(),
+            "password" => s.password = value,
+            "service_base_path" => s.service_base_path = value.unwrap_or_default(),
+            "service_name" => s.service_name = value.unwrap_or_default(),
+            "src_type" => s.src_type = value.map(|v| v.to_uppercase()),
+            "dst_type" => s.dst_type = value.map(|v| v.to_uppercase()),
+            "auth_method" => s.auth_method = value.map(|v| v.to_lowercase()),
+            "private_key" => s.private_key = value,
+            "passphrase" => s.passphrase = value,
+            "jump_host" => s.jump_host = value,
+            "tags" => s.tags = value,
+            _ => {
+                error!("Data check failed, invalid field {} on row {}", key, rid);
+                exit(-1);
             }
+        }
+    }
 
-            data.insert(key.clone(), rid);
-            if s.src_type.is_some() && s.dst_type.is_some() {
-                data.insert(s.to_hash_with_src(), rid);
-                data.insert(s.to_hash_with_dst(), rid);
-            }
+    for (field, value) in [("hostname", &s.hostname), ("port", &s.port), ("protocol", &s.protocol), ("username", &s.username), 
+            ("service_base_path", &s.service_base_path), ("service_name", &s.service_name)] {
+        if value.is_empty() {
+            abnormal_exit_field_empty(rid, field);
+        }
+    }
 
-            // 行编号
-            s.rid = rid;
-            config.servers.push(s);
+    // 行编号
+    s.rid = rid;
+    s
+}
+
+// 检查数据有效性及重复数据，所有格式通用
+fn check_server(s: &Server, data: &mut HashMap<String, usize>) {
+    let rid = s.rid;
+
+    if let Some(src_type) = &s.src_type {
+        if src_type == "ORACLE" {
+
+        } else if !METADATA.ds.contains_key(src_type) {
+            error!("Data check failed, invalid src_type {} on row {}", src_type, rid);
+            exit(-1);
         }
     }
     
-    Some(config)
+    match &s.dst_type {
+        Some(dst_type) => {
+            if dst_type == "ORACLE" {
+
+            } else if !METADATA.dt.contains_key(dst_type) {
+                error!("Data check failed, invalid dst_type {} on row {}", dst_type, rid);
+                exit(-1);
+            }
+        },
+        None => {
+            if s.src_type.is_none() {
+                // 非法输出
+                error!("Data check failed, src_type and dst_type Cannot be empty");
+                abnormal_exit_precheck("Column H and column I cannot be empty");
+            }
+        }
+    }
+
+    if let Some(auth_method) = &s.auth_method {
+        if !AUTH_METHODS.contains(&auth_method.as_str()) {
+            error!("Data check failed, invalid auth_method {} on row {}", auth_method, rid);
+            exit(-1);
+        }
+    }
+
+    // 检查重复数据
+    let key = s.to_hash();
+
+    let v = match data.get(&key) {
+        Some(v) => {
+            Some(v)
+        }
+        None => {
+            match data.get(&s.to_hash_with_src()) {
+                Some(v) => {
+                    Some(v)
+                },
+                None => data.get(&s.to_hash_with_dst())
+            }
+        }
+    };
+
+    if let Some(v) = v {
+        error!("Data check failed");
+        // The third and fourth rows conflict
+        abnormal_exit_precheck(&format!("The data in the {} and {} rows conflict", rid, v));
+    }
+
+    data.insert(key.clone(), rid);
+    if s.src_type.is_some() && s.dst_type.is_some() {
+        data.insert(s.to_hash_with_src(), rid);
+        data.insert(s.to_hash_with_dst(), rid);
+    }
 }
 
 
@@ -594,7 +1570,10 @@ fn get_config() -> Option<GlobalConfig> {
 fn get_metadata() -> Option<Metadata> {
 
     let json = fs::read_to_string(get_manifest_file()).unwrap();
-    let meta: Metadata = serde_json::from_str(&json).unwrap();
+    let mut meta: Metadata = serde_json::from_str(&json).unwrap();
+    for (key, m) in meta.ds.iter_mut().chain(meta.dt.iter_mut()) {
+        m.key = key.clone();
+    }
 
     Some(meta)
 }
@@ -677,7 +1656,17 @@ pub fn abnormal_exit_precheck(cause: &str){
 pub fn abnormal_exit_data_empty(rid: usize, index: usize){
     error!("Data check failed: Data empty");
     println!("PreChecks failed:");
-    println!("  CAUSE: {}", format!("Row {} column {} cannot be empty", rid, char::from(index as u8 +65)));
+    println!("  CAUSE: Row {} column {} cannot be empty", rid, char::from(index as u8 +65));
+    println!("  ACTION: Contact DSG Support Services or refer to the software manual.");
+    println!("Bye.");
+    exit(-1);
+}
+
+
+pub fn abnormal_exit_field_empty(rid: usize, field: &str){
+    error!("Data check failed: Data empty");
+    println!("PreChecks failed:");
+    println!("  CAUSE: Row {} field {} cannot be empty", rid, field);
     println!("  ACTION: Contact DSG Support Services or refer to the software manual.");
     println!("Bye.");
     exit(-1);
@@ -698,4 +1687,190 @@ pub fn abnormal_exit_patch(cause: &str){
     println!("  ACTION: Contact DSG Support Services or refer to the software manual.");
     println!("Bye.");
     exit(-1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 写入临时的清单文件，返回文件路径
+    fn write_inventory(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!("monica-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn read_inventory(name: &str, contents: &str, read: fn(&str) -> Vec<Server>) -> Vec<Server> {
+        let path = write_inventory(name, contents);
+        let servers = read(&path);
+        fs::remove_file(path).unwrap();
+        servers
+    }
+
+    const CSV_INVENTORY: &str = "\
+hostname,port,protocol,username,password,service_base_path,service_name,src_type,dst_type,auth_method,private_key,passphrase,jump_host,tags
+192.168.6.12,22,SSH2,dsg,secret,/data/dataxone/sync,s1,oracle,,PublicKey,~/.ssh/id_ed25519,,ops@bastion:2222,canary
+192.168.6.13,22,SSH2,dsg,,/data/dataxone/sync,s2,,kafka,,,,,
+";
+
+    const YAML_INVENTORY: &str = "\
+servers:
+  - hostname: 192.168.6.12
+    port: 22
+    protocol: SSH2
+    username: dsg
+    password: secret
+    service_base_path: /data/dataxone/sync
+    service_name: s1
+    src_type: oracle
+    auth_method: PublicKey
+    private_key: ~/.ssh/id_ed25519
+    jump_host: ops@bastion:2222
+    tags: canary
+  - hostname: 192.168.6.13
+    port: 22
+    protocol: SSH2
+    username: dsg
+    password:
+    service_base_path: /data/dataxone/sync
+    service_name: s2
+    dst_type: kafka
+";
+
+    const TOML_INVENTORY: &str = "\
+[[servers]]
+hostname = \"192.168.6.12\"
+port = 22
+protocol = \"SSH2\"
+username = \"dsg\"
+password = \"secret\"
+service_base_path = \"/data/dataxone/sync\"
+service_name = \"s1\"
+src_type = \"oracle\"
+auth_method = \"PublicKey\"
+private_key = \"~/.ssh/id_ed25519\"
+jump_host = \"ops@bastion:2222\"
+tags = \"canary\"
+
+[[servers]]
+hostname = \"192.168.6.13\"
+port = 22
+protocol = \"SSH2\"
+username = \"dsg\"
+service_base_path = \"/data/dataxone/sync\"
+service_name = \"s2\"
+dst_type = \"kafka\"
+";
+
+    #[test]
+    fn inventory_formats_read_the_same_rows() {
+        let csv = read_inventory("inventory.csv", CSV_INVENTORY, read_csv_config);
+        let yaml = read_inventory("inventory.yaml", YAML_INVENTORY, read_yaml_config);
+        let toml = read_inventory("inventory.toml", TOML_INVENTORY, read_toml_config);
+
+        for servers in [&csv, &yaml, &toml] {
+            assert_eq!(servers.len(), 2);
+            let s = &servers[0];
+            assert_eq!(s.hostname, "192.168.6.12");
+            assert_eq!(s.port, "22");
+            assert_eq!(s.password.as_deref(), Some("secret"));
+            assert_eq!(s.src_type.as_deref(), Some("ORACLE"));
+            assert_eq!(s.dst_type, None);
+            assert_eq!(s.auth_method.as_deref(), Some("publickey"));
+            assert_eq!(s.jump_host.as_deref(), Some("ops@bastion:2222"));
+            assert_eq!(s.tags.as_deref(), Some("canary"));
+            let s = &servers[1];
+            assert_eq!(s.password, None);
+            assert_eq!(s.src_type, None);
+            assert_eq!(s.dst_type.as_deref(), Some("KAFKA"));
+            assert_eq!(s.auth_method, None);
+        }
+        assert_eq!(csv[1].to_hash(), yaml[1].to_hash());
+        assert_eq!(yaml[0].to_hash(), toml[0].to_hash());
+    }
+
+    #[test]
+    fn inventory_row_numbers() {
+        // csv 首行为表头，数据行从第2行开始；yaml、toml 从1开始
+        let csv = read_inventory("rows.csv", CSV_INVENTORY, read_csv_config);
+        let yaml = read_inventory("rows.yaml", YAML_INVENTORY, read_yaml_config);
+        let toml = read_inventory("rows.toml", TOML_INVENTORY, read_toml_config);
+        assert_eq!(csv.iter().map(|s| s.rid).collect::<Vec<usize>>(), vec![2, 3]);
+        assert_eq!(yaml.iter().map(|s| s.rid).collect::<Vec<usize>>(), vec![1, 2]);
+        assert_eq!(toml.iter().map(|s| s.rid).collect::<Vec<usize>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn bandwidth_units() {
+        assert_eq!(parse_bandwidth("512"), Ok(512));
+        assert_eq!(parse_bandwidth("10k"), Ok(10 * 1024));
+        assert_eq!(parse_bandwidth("10M"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_bandwidth("2g"), Ok(2 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn bandwidth_rejects_invalid_values() {
+        for p in ["", "0", "0M", "M", "-1", "1.5M", "10T"] {
+            assert!(parse_bandwidth(p).is_err(), "{}", p);
+        }
+        assert!(parse_bandwidth(&format!("{}G", u64::MAX / 1024)).is_err());
+    }
+
+    fn server(rid: usize, hostname: &str, service_name: &str) -> Server {
+        Server { rid, hostname: hostname.to_string(), service_name: service_name.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn rows_ranges() {
+        assert_eq!(parse_rows("3"), Ok((3, 3)));
+        assert_eq!(parse_rows("3-7"), Ok((3, 7)));
+        assert_eq!(parse_rows(" 3 - 7 "), Ok((3, 7)));
+        for p in ["", "7-3", "a", "3-", "-7", "3-7-9"] {
+            assert!(parse_rows(p).is_err(), "{}", p);
+        }
+    }
+
+    #[test]
+    fn target_without_filters_contains_all() {
+        let target = Target::default();
+        assert!(target.is_all());
+        assert!(target.contains(&server(2, "192.168.6.12", "s1")));
+        assert!(target.contains_role(ROLE_DS) && target.contains_role(ROLE_DT) && target.contains_role(ROLE_JDDM));
+    }
+
+    #[test]
+    fn target_filters_are_combined() {
+        let target = Target {
+            rows: vec![(2, 4), (9, 9)],
+            hosts: vec![String::from("192.168.6.12")],
+            services: vec![String::from("s1"), String::from("s2")],
+            ..Default::default()
+        };
+        assert!(!target.is_all());
+        assert!(target.contains(&server(3, "192.168.6.12", "s2")));
+        assert!(target.contains(&server(9, "192.168.6.12", "s1")));
+        assert!(!target.contains(&server(5, "192.168.6.12", "s1")));
+        assert!(!target.contains(&server(3, "192.168.6.13", "s1")));
+        assert!(!target.contains(&server(3, "192.168.6.12", "s3")));
+    }
+
+    #[test]
+    fn target_exclude_rows_hosts_and_services() {
+        let target = Target {
+            exclude: vec![String::from("5-6"), String::from("192.168.6.13"), String::from("s3")],
+            ..Default::default()
+        };
+        assert!(target.contains(&server(2, "192.168.6.12", "s1")));
+        assert!(!target.contains(&server(6, "192.168.6.12", "s1")));
+        assert!(!target.contains(&server(2, "192.168.6.13", "s1")));
+        assert!(!target.contains(&server(2, "192.168.6.12", "s3")));
+    }
+
+    #[test]
+    fn target_roles() {
+        let target = Target { roles: ["ds", "jddm"].iter().map(|r| get_role(r)).collect(), ..Default::default() };
+        assert!(target.contains_role(ROLE_DS));
+        assert!(!target.contains_role(ROLE_DT));
+        assert!(target.contains_role(ROLE_JDDM));
+    }
 }
\ No newline at end of file
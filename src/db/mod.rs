@@ -1,9 +1,9 @@
-use std::{process::exit, time::Duration};
+use std::time::Duration;
 
 use log::error;
 use sqlx::{prelude::FromRow, MySql, Pool};
 
-use crate::config::Server;
+use crate::{config::Server, error::{Error, Result}, vault};
 
 
 #[derive(Debug, Default)]
@@ -16,12 +16,12 @@ pub struct DBInfo {
 }
 
 #[derive(Debug, FromRow)]
-pub struct YRBA {
+pub struct Yrba {
     lscn: Option<String>,
     ucmt_scn: Option<String>,
 }
 
-pub const DB_NAME: &'static str = "dataxone_pmon";
+pub const DB_NAME: &str = "dataxone_pmon";
 
 #[derive(Debug, Clone)]
 pub struct Client {
@@ -30,14 +30,16 @@ pub struct Client {
 
 impl Client {
 
-    pub async fn new(db_info: &DBInfo) -> Self {
+    pub async fn new(db_info: &DBInfo) -> Result<Self> {
 
         let port: u16 = db_info.db_port.parse().unwrap();
+        // 连接时解析 ${ENV:NAME}、${VAULT:NAME}、${PROMPT} 等密码引用
+        let password = vault::resolve(&db_info.db_password).map_err(Error::Database)?;
         let options = sqlx::mysql::MySqlConnectOptions::new()
             .host(&db_info.db_host)
-            .port(port as u16)
+            .port(port)
             .username(&db_info.db_username)
-            .password(&db_info.db_password)
+            .password(&password)
             .ssl_mode(sqlx::mysql::MySqlSslMode::Disabled)
             .timezone(Some(String::from("+08:00")))
             .database(&DB_NAME);
@@ -48,14 +50,13 @@ impl Client {
             .max_lifetime(None)
             .acquire_timeout(Duration::from_secs(10800))
             .idle_timeout(Duration::from_secs(10800))
-            .connect_with(options).await;
-        
-        
-        Client{pool: result.unwrap()}
+            .connect_with(options).await?;
+
+        Ok(Client{pool: result})
 
     }
 
-    pub async fn query_log_pos(&self, s: &Server) -> Option<String> {
+    pub async fn query_log_pos(&self, s: &Server) -> Result<Option<String>> {
         let sql = format!("select LSCN as lscn, UCMT_SCN as ucmt_scn from {}.yrba where qnm = ?", DB_NAME);
 
         // thread 'monica' panicked at src\db\mod.rs:54:102:
@@ -63,18 +64,18 @@ impl Client {
         // 1、cause: error returned from database: 1159 (08S01): Got timeout reading communication packets
         // 2、Database data fetch failed, cause: pool timed out while waiting for an open connection
         // 连接超时，需保持长连接
-        let rows = match sqlx::query_as::<_, YRBA>(&sql).bind(&s.service_name).fetch_all(&self.pool).await {
+        let rows = match sqlx::query_as::<_, Yrba>(&sql).bind(&s.service_name).fetch_all(&self.pool).await {
             Ok(r) => r,
             Err(e) => {
                 error!("xlsx:Line: {:<2} Database data fetch failed, cause: {}", s.rid, e);
-                exit(-1);
+                return Err(e.into());
             }
         };
 
         if rows.len() == 0 {
-            None
+            Ok(None)
         } else {
-            let row: &YRBA = rows.iter().next().unwrap();
+            let row: &Yrba = rows.first().unwrap();
             let mut yrba = match &row.lscn {
                 Some(value) => format!("{},", value),
                 None => String::from(",")
@@ -82,14 +83,14 @@ impl Client {
     
             yrba = match &row.ucmt_scn {
                 Some(value) => format!("{}{}", yrba, value),
-                None => format!("{}", yrba),
+                None => yrba,
             };
 
             if yrba == "," {
-                return None;
+                return Ok(None);
             }
     
-            Some(yrba)
+            Ok(Some(yrba))
         }
 
     }
@@ -0,0 +1,59 @@
+use std::{fmt, io};
+
+// 工作线程中的错误逐层返回，由调用方决定继续处理其余的行还是终止
+#[derive(Debug)]
+pub enum Error {
+    // SSH 连接或认证失败
+    Connect(String),
+    // 远端或本地的目录、文件不存在
+    NotFound(String),
+    // 版本读取失败，无法匹配清单
+    Version(String),
+    // 已安装版本不在清单允许的范围内
+    Build(String),
+    // 数据库访问失败
+    Database(String),
+    // 远端命令执行失败
+    Remote(String),
+    // 文件校验失败
+    Verify(String),
+    // 启动后检查失败
+    Health(String),
+    // 本地文件读写失败
+    Io(io::Error),
+    // --fail-fast：之前的行已失败，当前行未执行
+    Aborted,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connect(host) => write!(f, "Session create failed: {}", host),
+            Error::NotFound(path) => write!(f, "No such file or directory: {}", path),
+            Error::Version(input) => write!(f, "Oracle version read failed: {}", input),
+            Error::Build(cause) => write!(f, "Build mismatch: {}", cause),
+            Error::Database(cause) => write!(f, "Database data fetch failed, cause: {}", cause),
+            Error::Remote(cause) => write!(f, "{}", cause),
+            Error::Verify(cause) => write!(f, "{}", cause),
+            Error::Health(cause) => write!(f, "Health check failed, cause: {}", cause),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Aborted => write!(f, "Not started, an earlier row failed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        Error::Database(e.to_string())
+    }
+}
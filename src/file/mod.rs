@@ -1,22 +1,22 @@
-use std::{fs::{self, File, OpenOptions}, io::{Read, Write}, path::{Path, PathBuf}, process::exit};
+use std::{fs::{self, File, OpenOptions}, io::{Read, Write}, path::{Path, PathBuf}};
 
 use chrono::Local;
 use flate2::read::GzDecoder;
 use log::{error, info};
 use tar::Archive;
 
-use crate::config::{self, get_local_inventory_dir, Server, GLOBAL_CONFIG};
+use crate::{config::{self, get_local_inventory_dir, Server, GLOBAL_CONFIG}, error::{Error, Result}};
 
 
 // 调用系统命令解压文件
-pub fn extract_compressed_files(base: &str, tgz: &str, dst: &str, rid: usize) {
+pub fn extract_compressed_files(base: &str, tgz: &str, dst: &str, rid: usize) -> Result<()> {
     
     let path = Path::new(base).join(tgz);
     info!("xlsx:Line: {:<2} Extracting file {}", rid, path.to_string_lossy().to_string());
     if !path.exists() {
-        abnormal_exit_not_found();
+        return Err(Error::NotFound(path.to_string_lossy().to_string()));
     }
-    let file = File::open(path.clone()).unwrap();
+    let file = File::open(path.clone())?;
     let tar: GzDecoder<File> = GzDecoder::new(file);
     let mut archive = Archive::new(tar);
     archive.set_overwrite(true);
@@ -29,6 +29,7 @@ pub fn extract_compressed_files(base: &str, tgz: &str, dst: &str, rid: usize) {
             // abnormal_exit_precheck(&e.to_string());
         }
     }
+    Ok(())
 }
 
 pub fn path_join(s: &str, path: &str) -> String {
@@ -43,22 +44,13 @@ pub fn path_join(s: &str, path: &str) -> String {
 
 // 计算sha256sum
 pub fn sha256sum(file: PathBuf) -> String {
-    let file_sha256 = sha256::try_digest(file).unwrap();
-    file_sha256
+    sha256::try_digest(file).unwrap()
 }
 
 pub fn get_filesize(file: &PathBuf) -> u64{
     std::fs::metadata(file).map(|metadata| metadata.len()).unwrap_or(0)
 }
 
-pub fn abnormal_exit_not_found(){
-    println!("PreChecks failed:");
-    println!("  CAUSE: No such file or directory");
-    println!("  ACTION: Contact DSG Support Services or refer to the software manual.");
-    println!("Bye.");
-    exit(-1);
-}
-
 // 将备份断点信息写入缓存
 // pub fn write_backup_ckp(xlsx_checksum: &str, rid: usize){
 //     // 写入断点信息到本地缓存目录
@@ -119,7 +111,7 @@ pub fn write_local_inventory(xlsx_checksum: &str){
     // 写入backupset.index文件
     let index_file = format!("{}/backupset.index", dir);
     // 格式：142cc5bcbe240c17bebba1d9cfa6721a062554c3a1eeaac580b4204d1e37c325:日期
-    let s = format!("{}:{}:{}:{}\n", &xlsx_checksum[0..12], xlsx_checksum, GLOBAL_CONFIG.servers.len(), Local::now().format("%Y-%m-%d %H:%M:%S"));
+    let s = format!("{}:{}:{}:{}\n", crate::cmd::batch::short_id(xlsx_checksum), xlsx_checksum, GLOBAL_CONFIG.servers.len(), Local::now().format("%Y-%m-%d %H:%M:%S"));
 
     match OpenOptions::new().append(true).write(true).create(true).open(&index_file) {
         Ok(mut f) => {
@@ -281,6 +273,19 @@ pub fn file_checkpoint(s: &Server, role: usize, xlsx_checksum: &str, local_file:
 }
 
 // 获取本地的清单索引文件
+// 删除文件上传的断点信息，远端文件恢复后需重新上传
+pub fn remove_file_checkpoint(s: &Server, role: usize, xlsx_checksum: &str){
+
+    // .monica/inventory/<checksum>/2-0.file.ckp
+    let local_ckp_file = format!("{}/{}/{}-{}.file.ckp", get_local_inventory_dir(), xlsx_checksum, s.rid, role);
+    if Path::new(&local_ckp_file).exists() {
+        if let Err(e) = fs::remove_file(&local_ckp_file) {
+            error!("File {} remove failed, cause: {}", &local_ckp_file, e);
+        }
+    }
+
+}
+
 pub fn read_local_inventory_index() -> String {
 
     // .monica/inventory
@@ -305,7 +310,7 @@ pub fn clean_local_inventory(checksum: &str, s: &str){
     let dir = get_local_inventory_dir();
     match fs::remove_dir_all(&format!("{}/{}", dir, checksum)) {
         Ok(_) => {
-            info!("Rollback: remove local inventory dir {}/{}", dir, checksum);
+            info!("Remove local inventory dir {}/{}", dir, checksum);
 
             // 从文件中删除对应的行
             let index_file = format!("{}/backupset.index", dir);
@@ -322,14 +327,14 @@ pub fn clean_local_inventory(checksum: &str, s: &str){
                 Ok(mut f) => {
                     f.write_all(new_lines.join("\n").as_bytes()).unwrap();
                     f.flush().unwrap();
-                    info!("Rollback: flush local inventory index {}", index_file);
+                    info!("Flush local inventory index {}", index_file);
                 },
                 Err(e) => {
                     error!("File {} open failed, cause: {}", &index_file, e);
                 }
             }
         },
-        Err(e) => {error!("Rollback: remove local inventory dir failed, cause: {}", e)}
+        Err(e) => {error!("Remove local inventory dir failed, cause: {}", e)}
     }
 
 }
\ No newline at end of file
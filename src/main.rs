@@ -2,19 +2,24 @@
 use std::{env, fs, io, path::Path, process::exit};
 use chrono::Local;
 use config::{get_debug, Command};
-use log::LevelFilter;
+use log::{error, info, LevelFilter};
 use log4rs::{append::{console::ConsoleAppender, file::FileAppender}, config::{Appender, Root}, encode::pattern::PatternEncoder, Config};
-use cmd::{backup::handle_command_backup, lsinventory::handle_command_lsinventory, precheck::handle_command_precheck, rollback::handle_command_rollback};
+use cmd::{backup::handle_command_backup, lsinventory::handle_command_lsinventory, precheck::handle_command_precheck, prune::handle_command_prune, rollback::{abnormal_exit_rollback, handle_command_rollback}, status::handle_command_status, summary::Summary, trust::handle_command_trust, verify::handle_command_verify};
 use structopt::StructOpt;
-use crate::config::{get_basedir, get_datadir, get_input_file, get_manifest_file};
+use crate::config::{get_basedir, get_datadir, get_input_file, get_manifest_file, Server};
 
 mod config;
 mod db;
 mod ssh;
 mod file;
 mod cmd;
+mod error;
+mod report;
+mod ps;
+mod vault;
+mod bundle;
 
-fn print_title(log_file: &str){
+fn print_title(log_file: &str, report_file: &str){
     // :: /data/dataxone/
     let basedir = get_basedir();
     // :: .monica
@@ -31,6 +36,8 @@ fn print_title(log_file: &str){
 
     // 创建日志目录
     fs::create_dir_all(format!("{}/{}/logs", log_file_output, &datadir)).unwrap();
+    // 创建报告目录
+    fs::create_dir_all(format!("{}/{}/reports", log_file_output, &datadir)).unwrap();
 
     println!("\n{} {}{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), key);
     println!("{} version {}", env!("CARGO_PKG_DESCRIPTION"), env!("CARGO_PKG_VERSION"));
@@ -41,6 +48,9 @@ fn print_title(log_file: &str){
     println!("Manifest file     : {}", get_manifest_file());
     println!("Input file        : {}", get_input_file());
     println!("Log file location : {}", log_file);
+    if !report_file.is_empty() {
+        println!("Report file       : {}", report_file);
+    }
     println!("\n--------------------------------------------------------------------------------\n");
     println!("Local Machine Information::");
     println!("Platform description: {} {}", env::consts::OS, env::consts::ARCH);
@@ -49,6 +59,61 @@ fn print_title(log_file: &str){
 }
 
 
+// 待处理的行：按 --rows --host --service --exclude 过滤，未指定时为所有行
+fn target_servers() -> Vec<&'static Server> {
+    let target = config::get_target();
+    for (start, end) in target.rows.iter() {
+        if let Some(rid) = (*start..=*end).find(|rid| !config::GLOBAL_CONFIG.servers.iter().any(|s| s.rid == *rid)) {
+            error!("Data check failed, row {} not in {}", rid, config::get_input_file());
+            exit(-1);
+        }
+    }
+
+    let servers: Vec<&'static Server> = config::GLOBAL_CONFIG.servers.iter().filter(|s| target.contains(s)).collect();
+    if !target.is_all() {
+        if servers.is_empty() {
+            error!("Data check failed, no rows match --rows, --host, --service, --exclude");
+            exit(-1);
+        }
+        let rows: Vec<String> = servers.iter().map(|s| s.rid.to_string()).collect();
+        let roles: Vec<&str> = target.roles.iter().map(|r| config::get_role_name(*r)).collect();
+        info!("Target rows: {}, roles: {}", rows.join(","), if roles.is_empty() { String::from("all") } else { roles.join(",") });
+    }
+    servers
+}
+
+// 检查当前阶段的执行结果，返回执行成功的行
+// --fail-fast：有行失败时写入报告后终止；--continue-on-error：后续阶段只处理成功的行
+fn check_summary(phase: &str, result: error::Result<Summary>, failed: &mut bool, log_prefix: &str, report_file: &str) -> Vec<&'static Server> {
+    let cause = match &result {
+        Ok(summary) if summary.is_ok() => None,
+        Ok(_) if config::is_continue_on_error() => {
+            *failed = true;
+            None
+        },
+        Ok(summary) => summary.cause(),
+        Err(e) => Some(e.to_string()),
+    };
+
+    if let Some(cause) = cause {
+        if !report_file.is_empty() {
+            report::write(log_prefix, report_file);
+        }
+        match phase {
+            "Backup" => config::abnormal_exit_backup(&cause),
+            "Patch" => config::abnormal_exit_patch(&cause),
+            "Rollback" => abnormal_exit_rollback(&cause),
+            _ => config::abnormal_exit_precheck(&cause),
+        }
+    }
+
+    match result {
+        Ok(summary) => summary.passed(),
+        Err(_) => Vec::new(),
+    }
+}
+
+
 #[tokio::main]
 async fn main() -> Result<(), sqlx::Error>{
     let opt: config::Opt = config::Opt::from_args();
@@ -68,6 +133,24 @@ async fn main() -> Result<(), sqlx::Error>{
         },
         Command::Backup(_) => {
             "backup"
+        },
+        Command::Trust(_) => {
+            "trust"
+        },
+        Command::Vault(_) => {
+            "vault"
+        },
+        Command::Sign(_) => {
+            "sign"
+        },
+        Command::Status(_) => {
+            "status"
+        },
+        Command::Verify(_) => {
+            "verify"
+        },
+        Command::Prune(_) => {
+            "prune"
         }
     };
 
@@ -77,8 +160,15 @@ async fn main() -> Result<(), sqlx::Error>{
     let log_dir = format!("{}/{}/logs", log_file_output, &datadir);
     let log_file_name = format!("monica-{}-{}.log", log_prefix, Local::now().format("%Y-%m-%d_%H%M%S"));
     let log_file = format!("{}/{}", log_dir, log_file_name);
+
+    // 运行报告，lsinventory、trust、vault、sign、status、verify、prune 不生成报告
+    report::init();
+    let report_file = match opt.command {
+        Command::Lsinventory(_) | Command::Trust(_) | Command::Vault(_) | Command::Sign(_) | Command::Status(_) | Command::Verify(_) | Command::Prune(_) => String::new(),
+        _ => format!("{}/{}/reports/monica-{}-{}.json", log_file_output, &datadir, log_prefix, Local::now().format("%Y-%m-%d_%H%M%S")),
+    };
     
-    print_title(&log_file);
+    print_title(&log_file, &report_file);
 
     let mut level = LevelFilter::Info;
     if get_debug() {
@@ -102,28 +192,49 @@ async fn main() -> Result<(), sqlx::Error>{
         .unwrap();
     let _ = log4rs::init_config(config).unwrap();
 
+    // 本地清单由输入文件的sha256迁移到批次编号
+    if matches!(opt.command, Command::Patch(_) | Command::Backup(_) | Command::Rollback(_) | Command::Lsinventory(_) | Command::Verify(_) | Command::Prune(_)) {
+        match cmd::batch::check_batch_id() {
+            Ok(id) => info!("Batch ID: {}", id),
+            Err(e) => {
+                error!("Batch ID derive failed, cause: {}", e);
+                exit(-1);
+            }
+        }
+        cmd::batch::migrate_local_inventory();
+    }
+
+    // --continue-on-error 时，是否有失败的行
+    let mut failed = false;
     match opt.command {
         Command::Precheck(a)=> {
             // 预检查功能
             println!("User request: precheck\n");
             
             // 提前检查xlsx是否有效
-            let _ = config::GLOBAL_CONFIG.servers;
+            let servers = target_servers();
 
-            handle_command_precheck(a.worker_threads).await;
+            let result = handle_command_precheck(a.worker_threads, &servers).await;
+            check_summary("PreChecks", result, &mut failed, log_prefix, &report_file);
         },
         Command::Patch(a) => {
 
             println!("User request: patch\n");
+            let mut servers = target_servers();
             if !a.skip_check {
-                handle_command_precheck(a.worker_threads).await;
+                let result = handle_command_precheck(a.common.worker_threads, &servers).await;
+                servers = check_summary("PreChecks", result, &mut failed, log_prefix, &report_file);
+            } else if let Err(e) = bundle::verify_manifest() {
+                // 跳过预检查时，仍需校验清单签名
+                config::abnormal_exit_precheck(&e.to_string());
             }
 
             // 备份
-            handle_command_backup(a.worker_threads).await;
+            let result = handle_command_backup(a.common.worker_threads, &servers).await;
+            servers = check_summary("Backup", result, &mut failed, log_prefix, &report_file);
     
             // 跳过提示，直接升级
-            if !a.quiet {
+            if !a.quiet && !a.dry_run {
                 for i in 0..3 {
                     let mut input = String::new();
 
@@ -140,17 +251,16 @@ async fn main() -> Result<(), sqlx::Error>{
                 }
             }
 
-            // 提前检查xlsx是否有效
-            let _ = config::GLOBAL_CONFIG.servers;
-    
             // 升级操作
-            cmd::apply::handle_command_xpatch(a.worker_threads).await;
+            let result = cmd::apply::handle_command_xpatch(a.common.worker_threads, &servers).await;
+            check_summary("Patch", result, &mut failed, log_prefix, &report_file);
 
         },
         Command::Rollback(a) => {
             println!("User request: rollback\n");
             // 回退
-            handle_command_rollback(a.worker_threads).await;
+            let result = handle_command_rollback(a.common.worker_threads, &target_servers()).await;
+            check_summary("Rollback", result, &mut failed, log_prefix, &report_file);
         },
         Command::Lsinventory(a) => {
             // 列出远端目录
@@ -162,11 +272,58 @@ async fn main() -> Result<(), sqlx::Error>{
             println!("User request: backup\n");
 
             // 提前检查xlsx是否有效
-            let _ = config::GLOBAL_CONFIG.servers;
-            handle_command_backup(a.worker_threads).await;
+            let servers = target_servers();
+            let result = handle_command_backup(a.common.worker_threads, &servers).await;
+            check_summary("Backup", result, &mut failed, log_prefix, &report_file);
+        },
+        Command::Trust(_) => {
+            // 固定主机密钥
+            println!("User request: trust\n");
+            failed = !handle_command_trust(&target_servers());
+        },
+        Command::Vault(a) => {
+            // 管理密码库
+            println!("User request: vault\n");
+            if let Err(e) = vault::handle_command_vault(a.set, a.remove) {
+                error!("Vault failed, cause: {}", e);
+                failed = true;
+            }
+        },
+        Command::Sign(a) => {
+            // 对清单签名
+            println!("User request: sign\n");
+            if let Err(e) = bundle::handle_command_sign(&a.key, a.generate) {
+                error!("Sign failed, cause: {}", e);
+                failed = true;
+            }
+        },
+        Command::Status(_) => {
+            // 查看补丁级别
+            println!("User request: status\n");
+            failed = !handle_command_status(&target_servers());
+        },
+        Command::Verify(_) => {
+            // 检查远端文件是否被手工替换
+            println!("User request: verify\n");
+            failed = !handle_command_verify(&target_servers());
+        },
+        Command::Prune(_) => {
+            // 清理远端备份集
+            println!("User request: prune\n");
+            failed = !handle_command_prune(&target_servers());
         }
     }
 
+    if !report_file.is_empty() {
+        report::write(log_prefix, &report_file);
+    }
+
+    if failed {
+        println!("{} completed with failed lines, see the summary above", env!("CARGO_PKG_NAME"));
+        println!();
+        exit(-1);
+    }
+
     println!("{} completed", env!("CARGO_PKG_NAME"));
     println!("");
 
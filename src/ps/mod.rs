@@ -0,0 +1,207 @@
+use std::fmt;
+
+use crate::config::ROLE_JDDM;
+
+// 参数分隔符：/proc/<pid>/cmdline 中的 \0 替换为 \x1f 后输出
+const ARG_SEPARATOR: char = '\u{1f}';
+
+// 程序文件被替换后 /proc/<pid>/exe 指向的路径带有该后缀
+const DELETED_SUFFIX: &str = " (deleted)";
+
+// 远端进程
+#[derive(Debug, Clone, PartialEq)]
+pub struct Process {
+    pub pid: u32,
+    pub cwd: String,
+    pub exe: String, // 程序的完整路径，相对路径按 cwd 解析
+    pub args: Vec<String>,
+}
+
+impl Process {
+
+    // java 系统属性 -D<key>=<value>
+    pub fn property(&self, key: &str) -> Option<&str> {
+        let prefix = format!("-D{}=", key);
+        self.args.iter().find_map(|a| a.strip_prefix(&prefix))
+    }
+
+    // 参数中是否包含指定名称的文件，如：-jar lib/xxx.jar，-cp lib/a.jar:lib/b.jar
+    pub fn has_file(&self, name: &str) -> bool {
+        self.args.iter()
+            .flat_map(|a| a.split(':'))
+            .any(|f| f.rsplit('/').next() == Some(name))
+    }
+
+}
+
+impl fmt::Display for Process {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.pid, self.args.join(" "))
+    }
+}
+
+// 读取命令行中包含 needle，或工作目录、程序路径位于 needle 下的进程
+// 每行输出：<pid>\t<cwd>\t<exe>\t<cmdline>，只做粗略过滤，精确匹配由 select 完成
+pub fn list_command(needle: &str) -> String {
+    format!("n='{}'; m=\" $(grep -alF -- \"$n\" /proc/[0-9]*/cmdline 2>/dev/null | tr '\\n' ' ') \"; \
+        for p in /proc/[0-9]*; do c=$(readlink $p/cwd 2>/dev/null); x=$(readlink $p/exe 2>/dev/null); \
+        case \"$m\" in *\" $p/cmdline \"*) ;; *) case \"$c/ $x\" in \"$n\"*|*\" $n\"*) ;; *) continue;; esac;; esac; \
+        printf '%s\\t%s\\t%s\\t' \"${{p#/proc/}}\" \"$c\" \"$x\"; tr '\\000' '\\037' 2>/dev/null < $p/cmdline; echo; done",
+        needle.replace('\'', "'\\''"))
+}
+
+// 解析 list_command 的输出，已退出的进程(cmdline 为空)不返回
+pub fn parse(stdout: &str) -> Vec<Process> {
+    stdout.lines().filter_map(parse_line).collect()
+}
+
+// 程序路径优先取 /proc/<pid>/exe，无权限读取时按 argv[0] 解析
+pub fn parse_line(line: &str) -> Option<Process> {
+    let mut fields = line.splitn(4, '\t');
+    let pid = fields.next()?.trim().parse().ok()?;
+    let cwd = fields.next()?.to_string();
+    let exe = fields.next()?.trim_end_matches(DELETED_SUFFIX).to_string();
+    let args: Vec<String> = fields.next()?
+        .trim_end_matches(ARG_SEPARATOR)
+        .split(ARG_SEPARATOR)
+        .map(String::from)
+        .collect();
+    let argv0 = args.first().filter(|a| !a.is_empty())?;
+    let exe = if exe.is_empty() { resolve(&cwd, argv0) } else { normalize(&exe) };
+    Some(Process { pid, cwd, exe, args })
+}
+
+// 程序路径：不含 / 时为 PATH 中的命令，原样返回；相对路径按 cwd 解析
+pub fn resolve(cwd: &str, argv0: &str) -> String {
+    if argv0.starts_with('/') {
+        normalize(argv0)
+    } else if argv0.contains('/') && !cwd.is_empty() {
+        normalize(&format!("{}/{}", cwd, argv0))
+    } else {
+        argv0.to_string()
+    }
+}
+
+// 去掉路径中的 .、.. 及重复的 /
+pub fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for p in path.split('/') {
+        match p {
+            "" | "." => {},
+            ".." => { parts.pop(); },
+            _ => parts.push(p),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+// 任务的进程：ds/dt 为 $DBPS_HOME/bin 下的程序，jddm 为 -DPath=$DBPS_HOME 的 java 进程
+pub fn select(processes: Vec<Process>, role: usize, dbps_home: &str) -> Vec<Process> {
+    let home = normalize(dbps_home);
+    let bin = format!("{}/bin/", home);
+    processes.into_iter()
+        .filter(|p| match role {
+            ROLE_JDDM => p.property("Path").map(normalize).as_deref() == Some(home.as_str()),
+            _ => p.exe.starts_with(&bin),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ROLE_DS, ROLE_DT};
+
+    // 取自 list_command 的输出：<pid>\t<cwd>\t<exe>\t<cmdline>，参数以 \x1f 分隔，exe 为空表示无权限读取
+    const FIXTURE: &str = "\
+1201\t/home/ops\t/usr/bin/tail\ttail\x1f-f\x1f/opt/dbps/ds1/log/pmon.log\x1f
+1202\t/home/ops\t\tvim\x1f/opt/dbps/ds1/bin/pmon.conf\x1f
+1203\t/opt/dbps/ds1\t\t./bin/pmon\x1f-c\x1fconf/pmon.conf\x1f
+1204\t/\t\t/opt/dbps/ds1/bin/../bin/dbps_capture\x1f/opt/dbps/ds1\x1f
+1205\t/opt/dbps/ds10\t\t./bin/pmon\x1f/opt/dbps/ds1\x1f
+1206\t/opt/dbps/ds1\t\t./tools/pmon\x1f
+1207\t/opt/dbps/ds1/bin\t/opt/dbps/ds1/bin/dbps_apply (deleted)\tdbps_apply\x1f-d\x1f
+1301\t/opt/dbps/jddm1\t/usr/lib/jvm/bin/java\t/usr/bin/java\x1f-DPath=/opt/dbps/jddm1/\x1f-DPid=JDDM_M\x1f-jar\x1flib/jddm.jar\x1f
+1302\t/opt/dbps/jddm1\t\t/usr/bin/java\x1f-DPath=/opt/dbps/jddm1\x1f-DPid=JDDM_1\x1f-cp\x1flib/a.jar:lib/b.jar\x1fcom.dsg.Main\x1ftopic=t1\x1f
+1303\t/opt/dbps/jddm10\t\t/usr/bin/java\x1f-DPath=/opt/dbps/jddm10\x1f-DPid=JDDM_1\x1f-jar\x1flib/jddm.jar\x1f
+1304\t/home/ops\t\t/usr/bin/java\x1f-Dlog.dir=/opt/dbps/jddm1/log\x1f-jar\x1f/opt/dbps/jddm1/lib/jddm.jar\x1f
+1305\t/\t\t\x1f
+";
+
+    fn pids(role: usize, dbps_home: &str) -> Vec<u32> {
+        select(parse(FIXTURE), role, dbps_home).iter().map(|p| p.pid).collect()
+    }
+
+    #[test]
+    fn parse_skips_exited_processes() {
+        let processes = parse(FIXTURE);
+        assert_eq!(processes.len(), 11);
+        assert_eq!(processes[2].exe, "/opt/dbps/ds1/bin/pmon");
+        assert_eq!(processes[3].exe, "/opt/dbps/ds1/bin/dbps_capture");
+        assert_eq!(processes[0].exe, "/usr/bin/tail");
+        assert_eq!(processes[6].exe, "/opt/dbps/ds1/bin/dbps_apply");
+    }
+
+    #[test]
+    fn unrelated_process_with_home_in_args_is_not_selected() {
+        for role in [ROLE_DS, ROLE_DT, ROLE_JDDM] {
+            let selected = pids(role, "/opt/dbps/ds1");
+            assert!(!selected.contains(&1201) && !selected.contains(&1202), "role {}: {:?}", role, selected);
+        }
+        assert!(!pids(ROLE_JDDM, "/opt/dbps/jddm1").contains(&1304));
+    }
+
+    #[test]
+    fn jddm_is_matched_by_path_property() {
+        assert_eq!(pids(ROLE_JDDM, "/opt/dbps/jddm1"), vec![1301, 1302]);
+        assert_eq!(pids(ROLE_JDDM, "/opt/dbps/jddm1/"), vec![1301, 1302]);
+        assert_eq!(pids(ROLE_JDDM, "/opt/dbps/jddm10"), vec![1303]);
+        assert!(pids(ROLE_JDDM, "/opt/dbps/ds1").is_empty());
+    }
+
+    #[test]
+    fn ds_dt_are_matched_by_bin_executable() {
+        assert_eq!(pids(ROLE_DS, "/opt/dbps/ds1"), vec![1203, 1204, 1207]);
+        assert_eq!(pids(ROLE_DT, "/opt/dbps/ds1/"), vec![1203, 1204, 1207]);
+        assert_eq!(pids(ROLE_DS, "/opt/dbps/ds10"), vec![1205]);
+        assert!(pids(ROLE_DS, "/opt/dbps/jddm1").is_empty());
+    }
+
+    // 在本机执行 list_command：./bin/pmon 的命令行中不含 $DBPS_HOME，只能通过 cwd/exe 找到
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn list_command_output_is_parsed() {
+        use std::{fs, process::Command, thread, time::Duration};
+
+        let home = std::env::temp_dir().join(format!("monica-ps-{}", std::process::id()));
+        let home_str = home.to_string_lossy().to_string();
+        fs::create_dir_all(home.join("bin")).unwrap();
+        fs::copy("/bin/sleep", home.join("bin/pmon")).unwrap();
+
+        let mut pmon = Command::new("./bin/pmon").arg("30").current_dir(&home).spawn().unwrap();
+        let mut jddm = Command::new("sh").args(["-c", "sleep 30", "sh", &format!("-DPath={}", home_str)]).spawn().unwrap();
+        thread::sleep(Duration::from_millis(200));
+
+        let output = Command::new("sh").args(["-c", &list_command(&home_str)]).output().unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        let ds: Vec<u32> = select(parse(&stdout), ROLE_DS, &home_str).iter().map(|p| p.pid).collect();
+        let jd: Vec<u32> = select(parse(&stdout), ROLE_JDDM, &home_str).iter().map(|p| p.pid).collect();
+
+        for child in [&mut pmon, &mut jddm] {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        let _ = fs::remove_dir_all(&home);
+
+        assert_eq!(ds, vec![pmon.id()], "{}", stdout);
+        assert_eq!(jd, vec![jddm.id()], "{}", stdout);
+    }
+
+    #[test]
+    fn jddm_start_arguments() {
+        let processes = select(parse(FIXTURE), ROLE_JDDM, "/opt/dbps/jddm1");
+        let p = processes.iter().find(|p| p.property("Pid") == Some("JDDM_1")).unwrap();
+        assert_eq!(p.args.last().map(String::as_str), Some("topic=t1"));
+        assert!(p.has_file("b.jar"));
+    }
+}
@@ -0,0 +1,204 @@
+use std::{fs, path::Path, sync::Mutex};
+
+use chrono::{DateTime, Local};
+use lazy_static::lazy_static;
+use log::{error, info};
+use serde::Serialize;
+
+use crate::{config::{self, get_role_name, Manifest, Server}, error::Result, file, ssh};
+
+lazy_static! {
+    static ref ENTRIES: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+    static ref STARTED: DateTime<Local> = Local::now();
+}
+
+pub const STATUS_SUCCESS: &str = "success";
+pub const STATUS_SKIPPED: &str = "skipped";
+pub const STATUS_FAILED: &str = "failed";
+pub const STATUS_PLANNED: &str = "planned";
+
+// 运行报告：每行、每个角色(ds/dt/jddm)一条记录
+#[derive(Serialize)]
+pub struct Report {
+    pub command: String,
+    pub version: String,
+    pub input_file: String,
+    pub manifest_file: String,
+    pub start_time: String,
+    pub end_time: String,
+    pub total: usize,
+    pub success: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FileEntry {
+    pub file: String,
+    pub local_sha256: Option<String>,
+    pub remote_sha256: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub phase: String,
+    pub rid: usize,
+    pub hostname: String,
+    pub service_name: String,
+    pub role: String,
+    pub dbps_home: Option<String>,
+    pub manifest: Option<String>,
+    pub installed_build: Option<String>, // 升级前已安装的版本
+    pub backupset: Option<String>,
+    pub files: Vec<FileEntry>,
+    pub started_before: Option<bool>, // 停止前是否运行
+    pub started_after_stop: Option<bool>, // 停止后是否仍在运行
+    pub startup_issued: Option<bool>, // 是否已发出启动命令
+    pub yrba_before: Option<String>,
+    pub yrba_after: Option<String>,
+    pub healthy: Option<bool>, // 启动后检查是否通过
+    pub restored: Option<bool>, // 失败后是否已从备份集恢复
+    pub start_time: String,
+    pub end_time: String,
+    pub duration_ms: i64,
+    pub status: Option<String>,
+    pub message: Option<String>,
+    #[serde(skip)]
+    start: DateTime<Local>,
+}
+
+impl Entry {
+
+    pub fn new(phase: &str, s: &Server, role: usize) -> Self {
+        let start = Local::now();
+        Entry {
+            phase: phase.to_string(),
+            rid: s.rid,
+            hostname: s.hostname.clone(),
+            service_name: s.service_name.clone(),
+            role: get_role_name(role).to_string(),
+            dbps_home: None,
+            manifest: None,
+            installed_build: None,
+            backupset: None,
+            files: Vec::new(),
+            started_before: None,
+            started_after_stop: None,
+            startup_issued: None,
+            yrba_before: None,
+            yrba_after: None,
+            healthy: None,
+            restored: None,
+            start_time: start.format("%Y-%m-%d %H:%M:%S").to_string(),
+            end_time: String::new(),
+            duration_ms: 0,
+            status: None,
+            message: None,
+            start,
+        }
+    }
+
+    // 角色适用于当前行，未明确成功或跳过的记录为失败
+    pub fn begin(&mut self) {
+        self.status = Some(String::from(STATUS_FAILED));
+    }
+
+    pub fn set_status(&mut self, status: &str, message: &str) {
+        self.status = Some(status.to_string());
+        if !message.is_empty() {
+            self.message = Some(message.to_string());
+        }
+    }
+
+    pub fn fail(&mut self, message: &str) {
+        self.set_status(STATUS_FAILED, message);
+    }
+
+    pub fn set_manifest(&mut self, manifest: Option<&Manifest>) {
+        self.manifest = manifest.map(|m| m.key.clone());
+    }
+
+    // 记录清单中的文件，及本地、远端的sha256sum
+    pub fn record_files(&mut self, ssh: &ssh::Client, dbps_home: &str, manifest: &Manifest, with_local: bool) {
+        let remote_sums = ssh.sha256sum_files(dbps_home, &manifest.file);
+        self.files = manifest.file.iter().map(|f| {
+            let local_file = Path::new(&config::get_basedir()).join(&manifest.dir).join(f);
+            FileEntry {
+                file: file::path_join(dbps_home, f),
+                local_sha256: if with_local && local_file.exists() { Some(file::sha256sum(local_file)) } else { None },
+                remote_sha256: remote_sums.get(f).cloned(),
+            }
+        }).collect();
+    }
+
+    // 已发出启动命令的目录，返回 (角色, 目录)
+    pub fn started_home(&self) -> Option<(usize, String)> {
+        if self.startup_issued != Some(true) {
+            return None;
+        }
+        let role = match self.role.as_str() {
+            "ds" => config::ROLE_DS,
+            "dt" => config::ROLE_DT,
+            _ => config::ROLE_JDDM,
+        };
+        self.dbps_home.clone().map(|home| (role, home))
+    }
+
+    // 记录sha256sum文件中的文件，格式：<sha256sum>  <file>
+    pub fn record_sha256sum_file(&mut self, dbps_home: &str, contents: &str) {
+        self.files = contents.lines()
+            .filter_map(|l| l.split_once(' '))
+            .map(|(sum, f)| FileEntry {
+                file: file::path_join(dbps_home, f.trim_start_matches([' ', '*'])),
+                local_sha256: None,
+                remote_sha256: Some(sum.to_string()),
+            }).collect();
+    }
+
+}
+
+// 记录结束，角色不适用于当前行的记录将被丢弃
+pub fn push(mut e: Entry, result: &Result<()>) {
+    if e.status.is_none() {
+        return;
+    }
+    if let Err(cause) = result {
+        if e.status.as_deref() != Some(STATUS_FAILED) || e.message.is_none() {
+            e.fail(&cause.to_string());
+        }
+    }
+    let end = Local::now();
+    e.end_time = end.format("%Y-%m-%d %H:%M:%S").to_string();
+    e.duration_ms = (end - e.start).num_milliseconds();
+    ENTRIES.lock().unwrap().push(e);
+}
+
+pub fn init() {
+    lazy_static::initialize(&STARTED);
+}
+
+// 写入报告文件
+pub fn write(command: &str, report_file: &str) {
+    let entries: Vec<Entry> = ENTRIES.lock().unwrap().drain(..).collect();
+    let count = |status: &str| entries.iter().filter(|e| e.status.as_deref() == Some(status)).count();
+    let report = Report {
+        command: command.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        input_file: config::get_input_file(),
+        manifest_file: config::get_manifest_file(),
+        start_time: STARTED.format("%Y-%m-%d %H:%M:%S").to_string(),
+        end_time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        total: entries.len(),
+        success: count(STATUS_SUCCESS),
+        skipped: count(STATUS_SKIPPED),
+        failed: count(STATUS_FAILED),
+        entries,
+    };
+
+    let contents = serde_json::to_string_pretty(&report).unwrap();
+    match fs::write(report_file, contents) {
+        Ok(_) => info!("Report written to {}", report_file),
+        Err(e) => error!("File {} write failed, cause: {}", report_file, e),
+    }
+}
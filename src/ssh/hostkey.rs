@@ -0,0 +1,143 @@
+use std::{fs, path::Path, sync::Mutex};
+
+use base64::{engine::general_purpose::{STANDARD, STANDARD_NO_PAD}, Engine};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use ssh2::{CheckResult, HashType, HostKeyType, KnownHostFileKind, KnownHosts, Session};
+
+use crate::config::{self, HOST_KEY_ACCEPT_NEW, HOST_KEY_STRICT, HOST_KEY_WARN};
+
+// known_hosts 文件读写锁，多行同时添加新主机时避免互相覆盖
+static KNOWN_HOSTS_LOCK: Mutex<()> = Mutex::new(());
+
+// 固定主机密钥的结果
+pub const TRUST_ADDED: &str = "added";
+pub const TRUST_UNCHANGED: &str = "unchanged";
+pub const TRUST_REPLACED: &str = "replaced";
+
+// known_hosts 中的主机名，非22端口为 [host]:port
+fn host_name(hostname: &str, port: u16) -> String {
+    if port == 22 {
+        hostname.to_string()
+    } else {
+        format!("[{}]:{}", hostname, port)
+    }
+}
+
+fn key_type_name(key_type: HostKeyType) -> &'static str {
+    match key_type {
+        HostKeyType::Rsa => "ssh-rsa",
+        HostKeyType::Dss => "ssh-dss",
+        HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+        HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+        HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+        HostKeyType::Ed255219 => "ssh-ed25519",
+        HostKeyType::Unknown => "unknown",
+    }
+}
+
+// 主机密钥指纹，格式与 ssh-keygen -l 一致：SHA256:<base64>
+pub fn fingerprint(sess: &Session) -> String {
+    match sess.host_key_hash(HashType::Sha256) {
+        Some(hash) => format!("SHA256:{}", STANDARD_NO_PAD.encode(hash)),
+        None => String::from("<NONE>"),
+    }
+}
+
+// known_hosts 中该主机已固定的密钥指纹
+fn pinned_fingerprints(known_hosts: &KnownHosts, name: &str) -> Result<Vec<String>, String> {
+    let hosts = known_hosts.hosts().map_err(|e| e.to_string())?;
+    Ok(hosts.iter()
+        .filter(|h| h.name() == Some(name))
+        .map(|h| match STANDARD.decode(h.key()) {
+            Ok(key) => format!("SHA256:{}", STANDARD_NO_PAD.encode(Sha256::digest(key))),
+            Err(_) => String::from("<INVALID>"),
+        })
+        .collect())
+}
+
+fn read_known_hosts(sess: &Session) -> Result<(KnownHosts, String), String> {
+    let file = config::get_known_hosts_file();
+    let mut known_hosts = sess.known_hosts().map_err(|e| e.to_string())?;
+    if Path::new(&file).exists() {
+        known_hosts.read_file(Path::new(&file), KnownHostFileKind::OpenSSH).map_err(|e| format!("{}: {}", file, e))?;
+    }
+    Ok((known_hosts, file))
+}
+
+fn write_known_hosts(known_hosts: &KnownHosts, file: &str) -> Result<(), String> {
+    if let Some(dir) = Path::new(file).parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    known_hosts.write_file(Path::new(file), KnownHostFileKind::OpenSSH).map_err(|e| format!("{}: {}", file, e))
+}
+
+// 握手后、认证前检查主机密钥，按 --host-key-check 处理未知或不一致的密钥
+pub fn verify(sess: &Session, rid: usize, hostname: &str, port: &str) -> Result<(), String> {
+    let mode = config::get_host_key_check();
+    let port: u16 = port.parse().map_err(|_| format!("invalid port {}", port))?;
+    let (key, key_type) = sess.host_key().ok_or("no host key")?;
+    let fp = fingerprint(sess);
+
+    let _lock = KNOWN_HOSTS_LOCK.lock().unwrap();
+    let (mut known_hosts, file) = read_known_hosts(sess)?;
+    match known_hosts.check_port(hostname, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => {
+            let cause = format!("Host key for {}:{} has changed, {} {}, see {}", hostname, port, key_type_name(key_type), fp, file);
+            if mode == HOST_KEY_WARN {
+                warn!("xlsx:Line: {:<2} {}", rid, cause);
+                return Ok(());
+            }
+            Err(cause)
+        },
+        CheckResult::Failure => Err(format!("Host key check for {}:{} failed, see {}", hostname, port, file)),
+        CheckResult::NotFound => {
+            match mode.as_str() {
+                HOST_KEY_STRICT => Err(format!("Host key for {}:{} not found in {}, {} {}, run `monica trust` first", hostname, port, file, key_type_name(key_type), fp)),
+                HOST_KEY_ACCEPT_NEW => {
+                    known_hosts.add(&host_name(hostname, port), key, "", key_type.into()).map_err(|e| e.to_string())?;
+                    write_known_hosts(&known_hosts, &file)?;
+                    info!("xlsx:Line: {:<2} Permanently added {}:{} ({} {}) to {}", rid, hostname, port, key_type_name(key_type), fp, file);
+                    Ok(())
+                },
+                _ => {
+                    warn!("xlsx:Line: {:<2} Host key for {}:{} not found in {}, {} {}", rid, hostname, port, file, key_type_name(key_type), fp);
+                    Ok(())
+                },
+            }
+        },
+    }
+}
+
+// 固定主机密钥：密钥已变化时，指定 --replace 才替换 known_hosts 中该主机已有的记录，返回 (密钥类型, 指纹, 结果)
+pub fn pin(sess: &Session, rid: usize, hostname: &str, port: &str) -> Result<(String, String, &'static str), String> {
+    let port: u16 = port.parse().map_err(|_| format!("invalid port {}", port))?;
+    let (key, key_type) = sess.host_key().ok_or("no host key")?;
+    let fp = fingerprint(sess);
+    let name = host_name(hostname, port);
+
+    let _lock = KNOWN_HOSTS_LOCK.lock().unwrap();
+    let (mut known_hosts, file) = read_known_hosts(sess)?;
+    let status = match known_hosts.check_port(hostname, port, key) {
+        CheckResult::Match => return Ok((key_type_name(key_type).to_string(), fp, TRUST_UNCHANGED)),
+        CheckResult::Mismatch => {
+            let pinned = pinned_fingerprints(&known_hosts, &name)?.join(", ");
+            if !config::is_replace_host_key() {
+                return Err(format!("Host key for {}:{} has changed, pinned {}, offered {} {}, rerun with --replace to replace it", hostname, port, pinned, key_type_name(key_type), fp));
+            }
+            for host in known_hosts.hosts().map_err(|e| e.to_string())? {
+                if host.name() == Some(name.as_str()) {
+                    known_hosts.remove(&host).map_err(|e| e.to_string())?;
+                }
+            }
+            warn!("xlsx:Line: {:<2} Host key for {}:{} replaced, pinned {}, now {} {}", rid, hostname, port, pinned, key_type_name(key_type), fp);
+            TRUST_REPLACED
+        },
+        CheckResult::NotFound => TRUST_ADDED,
+        CheckResult::Failure => return Err(format!("Host key check for {}:{} failed, see {}", hostname, port, file)),
+    };
+    known_hosts.add(&name, key, "", key_type.into()).map_err(|e| e.to_string())?;
+    write_known_hosts(&known_hosts, &file)?;
+    Ok((key_type_name(key_type).to_string(), fp, status))
+}
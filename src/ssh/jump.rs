@@ -0,0 +1,177 @@
+use std::{io::{self, Read, Write}, net::{SocketAddr, TcpListener, TcpStream}, thread, time::Duration};
+
+use log::{error, info, warn};
+use polling::{Event, Events, Poller};
+use ssh2::{BlockDirections, Channel, Session};
+
+use crate::config::Server;
+
+use super::{hostkey, userauth};
+
+// 转发时等待可读写的最长时间，超时后重新读取，libssh2 的缓冲区中可能还有数据
+const FORWARD_POLL_MILLIS: u64 = 1000;
+const LOCAL: usize = 0;
+const REMOTE: usize = 1;
+
+// 跳板机：[user@]host[:port]，未指定用户时使用目标主机的用户，未指定端口时为22
+// 认证方式、密码、私钥与目标主机相同
+fn jump_server(s: &Server, jump_host: &str) -> Server {
+    let (username, address) = match jump_host.split_once('@') {
+        Some((u, a)) => (u.to_string(), a),
+        None => (s.username.clone(), jump_host),
+    };
+    let (hostname, port) = match address.rsplit_once(':') {
+        Some((h, p)) => (h.to_string(), p.to_string()),
+        None => (address.to_string(), String::from("22")),
+    };
+    let mut j = s.clone();
+    j.hostname = hostname;
+    j.port = port;
+    j.username = username;
+    j.jump_host = None;
+    j
+}
+
+// 通过跳板机连接目标主机：认证跳板机后打开 direct-tcpip 通道，由本地端口转发到通道
+// 返回连接到本地端口的 TcpStream，用于目标主机的 SSH 会话
+pub fn connect(s: &Server, jump_host: &str) -> Result<TcpStream, String> {
+    let j = jump_server(s, jump_host);
+    let tcp = TcpStream::connect(format!("{}:{}", j.hostname, j.port)).map_err(|e| format!("jump host {}:{}, {}", j.hostname, j.port, e))?;
+    let remote = tcp.try_clone().map_err(|e| e.to_string())?;
+    let mut sess = Session::new().map_err(|e| e.to_string())?;
+    sess.set_tcp_stream(tcp);
+    sess.handshake().map_err(|e| format!("jump host {}:{} handshake failed, {}", j.hostname, j.port, e))?;
+    hostkey::verify(&sess, s.rid, &j.hostname, &j.port).map_err(|e| format!("jump host {}:{} host key verification failed, {}", j.hostname, j.port, e))?;
+    userauth(&sess, &j).map_err(|e| format!("jump host {}:{} auth failed, {}", j.hostname, j.port, e))?;
+
+    let port: u16 = s.port.parse().map_err(|_| format!("invalid port {}", s.port))?;
+    let channel = sess.channel_direct_tcpip(&s.hostname, port, None).map_err(|e| format!("jump host {}:{} tunnel to {}:{} failed, {}", j.hostname, j.port, s.hostname, s.port, e))?;
+    info!("xlsx:Line: {:<2} Tunnel to {}:{} through jump host {}:{}", s.rid, s.hostname, s.port, j.hostname, j.port);
+
+    let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+    let addr = listener.local_addr().map_err(|e| e.to_string())?;
+    let local = TcpStream::connect(addr).map_err(|e| e.to_string())?;
+    let peer = local.local_addr().map_err(|e| e.to_string())?;
+    let rid = s.rid;
+    thread::spawn(move || forward(rid, listener, peer, sess, channel, remote));
+    Ok(local)
+}
+
+// 只接受本进程发起的连接，其他进程连接到本地端口时直接关闭
+fn accept(rid: usize, listener: &TcpListener, peer: SocketAddr) -> io::Result<TcpStream> {
+    loop {
+        let (stream, addr) = listener.accept()?;
+        if addr == peer {
+            return Ok(stream);
+        }
+        warn!("xlsx:Line: {:<2} Tunnel rejected connection from {}", rid, addr);
+    }
+}
+
+// 等待本地连接、跳板机连接可读写
+struct Poll {
+    poller: Poller,
+    events: Events,
+}
+
+impl Poll {
+
+    fn new(local: &TcpStream, remote: &TcpStream) -> io::Result<Poll> {
+        let poller = Poller::new()?;
+        unsafe {
+            poller.add(local, Event::none(LOCAL))?;
+            poller.add(remote, Event::none(REMOTE))?;
+        }
+        Ok(Poll { poller, events: Events::new() })
+    }
+
+    // 本地连接按 interest 等待，跳板机连接等待可读，libssh2 阻塞在发送时同时等待可写
+    fn wait(&mut self, sess: &Session, local: &TcpStream, remote: &TcpStream, interest: Event) -> io::Result<()> {
+        let outbound = matches!(sess.block_directions(), BlockDirections::Outbound | BlockDirections::Both);
+        self.poller.modify(local, interest)?;
+        self.poller.modify(remote, Event::new(REMOTE, true, outbound))?;
+        self.events.clear();
+        self.poller.wait(&mut self.events, Some(Duration::from_millis(FORWARD_POLL_MILLIS)))?;
+        Ok(())
+    }
+
+}
+
+// 在本地连接与通道之间双向转发，任一方关闭后结束
+// remote 为跳板机连接的副本，只用于等待可读写，读写由 libssh2 完成
+fn forward(rid: usize, listener: TcpListener, peer: SocketAddr, sess: Session, mut channel: Channel, remote: TcpStream) {
+    let local = match accept(rid, &listener, peer) {
+        Ok(local) => local,
+        Err(e) => {
+            error!("xlsx:Line: {:<2} Tunnel accept failed, cause: {}", rid, e);
+            return;
+        }
+    };
+    drop(listener);
+    if let Err(e) = local.set_nonblocking(true) {
+        error!("xlsx:Line: {:<2} Tunnel set nonblocking failed, cause: {}", rid, e);
+        return;
+    }
+    sess.set_blocking(false);
+    let mut poll = match Poll::new(&local, &remote) {
+        Ok(poll) => poll,
+        Err(e) => {
+            error!("xlsx:Line: {:<2} Tunnel poll failed, cause: {}", rid, e);
+            return;
+        }
+    };
+
+    let mut buf = vec![0u8; 32 * 1024];
+    loop {
+        let mut idle = true;
+
+        match (&local).read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                idle = false;
+                if write_all(&mut channel, &buf[..n], || poll.wait(&sess, &local, &remote, Event::none(LOCAL))).is_err() {
+                    break;
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+            Err(_) => break,
+        }
+
+        match channel.read(&mut buf) {
+            Ok(0) => {
+                if channel.eof() {
+                    break;
+                }
+            },
+            Ok(n) => {
+                idle = false;
+                if write_all(&mut &local, &buf[..n], || poll.wait(&sess, &local, &remote, Event::writable(LOCAL))).is_err() {
+                    break;
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+            Err(_) => break,
+        }
+
+        // 两个方向都没有数据时，等待任一方可读
+        if idle && poll.wait(&sess, &local, &remote, Event::readable(LOCAL)).is_err() {
+            break;
+        }
+    }
+    let _ = channel.close();
+    let _ = poll.poller.delete(&local);
+    let _ = poll.poller.delete(&remote);
+}
+
+// 非阻塞写入，缓冲区满时等待可写
+fn write_all<W: Write>(w: &mut W, mut data: &[u8], mut wait: impl FnMut() -> io::Result<()>) -> io::Result<()> {
+    while !data.is_empty() {
+        match w.write(data) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => wait()?,
+            Err(e) => return Err(e),
+        }
+    }
+    w.flush().or_else(|e| if e.kind() == io::ErrorKind::WouldBlock { Ok(()) } else { Err(e) })
+}
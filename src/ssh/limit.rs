@@ -0,0 +1,102 @@
+use std::{collections::HashMap, sync::{Arc, Condvar, Mutex}, thread, time::{Duration, Instant}};
+
+use lazy_static::lazy_static;
+
+use crate::config;
+
+lazy_static! {
+    // --max-bandwidth：所有主机共用
+    static ref GLOBAL_LIMITER: Option<Arc<RateLimiter>> = config::get_max_bandwidth().0.map(|rate| Arc::new(RateLimiter::new(rate)));
+    // --max-host-bandwidth：每台主机一个
+    static ref HOST_LIMITERS: Mutex<HashMap<String, Arc<RateLimiter>>> = Mutex::new(HashMap::new());
+    // --max-transfers：同时上传的文件数
+    static ref TRANSFER_SLOTS: Slots = Slots { max: config::get_max_transfers(), used: Mutex::new(0), cv: Condvar::new() };
+}
+
+// 令牌桶限速：每秒补充 rate 字节，最多积累 1 秒，不足时欠账并等待
+pub struct RateLimiter {
+    rate: f64,
+    state: Mutex<(Instant, f64)>,
+}
+
+impl RateLimiter {
+
+    fn new(rate: u64) -> Self {
+        RateLimiter { rate: rate as f64, state: Mutex::new((Instant::now(), rate as f64)) }
+    }
+
+    fn acquire(&self, n: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let tokens = (state.1 + now.duration_since(state.0).as_secs_f64() * self.rate).min(self.rate) - n as f64;
+            *state = (now, tokens);
+            if tokens < 0.0 { -tokens / self.rate } else { 0.0 }
+        };
+        if wait > 0.0 {
+            thread::sleep(Duration::from_secs_f64(wait));
+        }
+    }
+
+}
+
+// 上传限速：先经过全局限速，再经过主机限速
+pub struct Throttle {
+    host: Option<Arc<RateLimiter>>,
+}
+
+impl Throttle {
+
+    pub fn new(host: &str) -> Self {
+        let host = config::get_max_bandwidth().1.map(|rate| {
+            HOST_LIMITERS.lock().unwrap()
+                .entry(host.to_string())
+                .or_insert_with(|| Arc::new(RateLimiter::new(rate)))
+                .clone()
+        });
+        Throttle { host }
+    }
+
+    // 每发送一个块前调用
+    pub fn acquire(&self, n: usize) {
+        if let Some(limiter) = GLOBAL_LIMITER.as_ref() {
+            limiter.acquire(n);
+        }
+        if let Some(limiter) = &self.host {
+            limiter.acquire(n);
+        }
+    }
+
+}
+
+struct Slots {
+    max: usize,
+    used: Mutex<usize>,
+    cv: Condvar,
+}
+
+// 上传名额，释放时唤醒等待的上传
+pub struct Slot;
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if TRANSFER_SLOTS.max == 0 {
+            return;
+        }
+        *TRANSFER_SLOTS.used.lock().unwrap() -= 1;
+        TRANSFER_SLOTS.cv.notify_one();
+    }
+}
+
+// 获取上传名额，达到 --max-transfers 时等待其他上传完成
+pub fn acquire_slot() -> Slot {
+    if TRANSFER_SLOTS.max == 0 {
+        return Slot;
+    }
+    let mut used = TRANSFER_SLOTS.used.lock().unwrap();
+    while *used >= TRANSFER_SLOTS.max {
+        used = TRANSFER_SLOTS.cv.wait(used).unwrap();
+    }
+    *used += 1;
+    Slot
+}
@@ -1,36 +1,79 @@
 
-use std::{fs::File, net::TcpStream, path::{Path, PathBuf}, process::exit, time::Duration};
+use std::{collections::HashMap, fs::File, io::SeekFrom, net::TcpStream, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::Duration};
 
 use chrono::Local;
+use lazy_static::lazy_static;
 use log::{debug, info, error};
-use ssh2::Session;
+use ssh2::{KeyboardInteractivePrompt, OpenFlags, OpenType, Prompt, Session};
 use std::io::prelude::*;
 
-use crate::{cmd::JDDM_START_WITH_FILE, config::{self, get_chunk_size, get_yrba_file_name, Server, BACKUPUP_DIR, BACKUPUP_FILE_PREFIX, BACKUPUP_RECYCLE_BIN_DIR, BACKUPUP_SHA256SUM_FILENAME, BACKUPUP_TMP_DIR}, file::{self, get_filesize, path_join}};
+use crate::{cmd::JDDM_START_WITH_FILE, error::Error, config::{self, get_chunk_size, get_yrba_file_name, Manifest, Server, ROLE_JDDM, AUTH_AGENT, AUTH_KEYBOARD_INTERACTIVE, AUTH_METHODS, AUTH_PASSWORD, AUTH_PUBLICKEY, BACKUPUP_DIR, BACKUPUP_FILE_PREFIX, BACKUPUP_RECYCLE_BIN_DIR, BACKUPUP_SHA256SUM_FILENAME, BACKUPUP_TMP_DIR, YRBA_FILENAME}, file::{self, get_filesize, path_join}, ps::{self, Process}, vault};
 
-// const SSH_KEEPALIVE_INTERVAL: usize = 5;
+mod hostkey;
+mod jump;
+mod limit;
+mod pool;
+
+lazy_static! {
+    // 远端缓存的上传锁，key: <user>@<host>:<port>/<sha256sum>
+    static ref CACHE_LOCKS: Mutex<HashMap<String, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+// 远端缓存目录，相对于 $HOME
+pub const REMOTE_CACHE_DIR: &str = ".monica/cache";
+
+// 清单未指定 version_command 时读取 ds/dt 已安装版本的命令
+const PMON_VERSION_COMMAND: &str = "bin/pmon -v";
+// ORACLE 类型没有 pmon 时读取 xagentd，去掉其中的 oracle 版本
+const XAGENTD_VERSION_COMMAND: &str = "bin/xagentd -v | grep -v 'for oracle version'";
+
+const SSH_KEEPALIVE_INTERVAL: usize = 5;
 const SSH_TOTAL_RETRY_COUNT: usize = 10;
 
 #[derive(Clone)]
 pub struct Client {
     s: Server,
-    sess: Session,
+    sess: Arc<Mutex<Session>>, // 连接池中的会话，断开后重新连接并替换
     host: String,
     rid: usize
 }
 
 impl Client {
 
-    pub fn new(s: &Server) -> Self {
-        let sess = connect_ssh(s).unwrap();
+    pub fn new(s: &Server) -> Result<Self, Error> {
+        let sess = match pool::get(s) {
+            Some(sess) => sess,
+            None => return Err(Error::Connect(format!("{}:{}", s.hostname, s.port))),
+        };
 
-        let mut _s = Server::default();
-        _s.hostname = s.hostname.clone();
-        _s.port = s.port.clone();
-        _s.username = s.username.clone();
-        _s.password = s.password.clone();
+        let _s = Server {
+            hostname: s.hostname.clone(),
+            port: s.port.clone(),
+            username: s.username.clone(),
+            password: s.password.clone(),
+            auth_method: s.auth_method.clone(),
+            private_key: s.private_key.clone(),
+            passphrase: s.passphrase.clone(),
+            jump_host: s.jump_host.clone(),
+            ..Default::default()
+        };
+
+        Ok(Client{ s: _s, sess: Arc::new(Mutex::new(sess)), host: s.hostname.clone(), rid: s.rid})
+    }
+
+    fn sess(&self) -> Session {
+        self.sess.lock().unwrap().clone()
+    }
 
-        Client{ s: _s, sess: sess, host: s.hostname.clone(), rid: s.rid}
+    // 重新连接，并替换连接池中的会话
+    fn reconnect(&self) -> bool {
+        match pool::get(&self.s) {
+            Some(sess) => {
+                *self.sess.lock().unwrap() = sess;
+                true
+            },
+            None => false,
+        }
     }
 
     // pub fn set_flag(&mut self, flag: String){
@@ -40,15 +83,37 @@ impl Client {
     pub fn exec_cmd_with_status(&self, command: &str) -> (i32, String, String) {
 
         debug!("xlsx:Line: {:<2} Host: {}, Exec_ssh_cmd: `{}`", self.rid, self.host, command);
-        let mut channel = self.sess.channel_session().unwrap();
-        channel.exec(command).unwrap();
-        let mut stdout = String::new();
-        channel.read_to_string(&mut stdout).unwrap();
-        let mut stderr: String = String::new();
-        channel.stderr().read_to_string(&mut stderr).unwrap();
-
-        channel.wait_close().unwrap();
-        let status = channel.exit_status().unwrap();
+        // 会话已断开时重新连接一次
+        let mut channel = match self.sess().channel_session() {
+            Ok(channel) => channel,
+            Err(e) => {
+                error!("xlsx:Line: {:<2} Host: {}, Channel open failed, cause: {}, reconnect", self.rid, self.host, e);
+                let channel = if self.reconnect() { self.sess().channel_session() } else { Err(e) };
+                match channel {
+                    Ok(channel) => channel,
+                    Err(e) => return (-1, String::new(), e.to_string()),
+                }
+            }
+        };
+        // 执行、读取或关闭通道失败时与打开通道失败一样返回 -1
+        let result = (|| -> Result<(i32, String, String), String> {
+            channel.exec(command).map_err(|e| e.to_string())?;
+            let mut stdout = String::new();
+            channel.read_to_string(&mut stdout).map_err(|e| e.to_string())?;
+            let mut stderr: String = String::new();
+            channel.stderr().read_to_string(&mut stderr).map_err(|e| e.to_string())?;
+
+            channel.wait_close().map_err(|e| e.to_string())?;
+            let status = channel.exit_status().map_err(|e| e.to_string())?;
+            Ok((status, stdout, stderr))
+        })();
+        let (status, stdout, stderr) = match result {
+            Ok(output) => output,
+            Err(cause) => {
+                error!("xlsx:Line: {:<2} Host: {}, Exec_ssh_cmd failed, cause: {}", self.rid, self.host, cause);
+                return (-1, String::new(), cause);
+            }
+        };
         debug!("xlsx:Line: {:<2} Host: {}, Exec_ssh_cmd: status={}, stdout={}, stderr={}", self.rid, self.host, status, stdout.replace("\n", "\\n"), stderr.replace("\n", "\\n"));
         (status, stdout, stderr)
     }
@@ -140,6 +205,35 @@ impl Client {
         stdout.trim_end_matches("\n") == "1"
     }
 
+    // 升级失败时，通过临时备份集恢复远端文件，备份集保持不变
+    // 临时备份集不存在时（已生成正式备份集），使用正式备份集
+    pub fn exec_restore_backupset(&self, base: &str, xlsx_checksum: &str) -> bool {
+
+        let backupset_file_name = format!("{}-{}.tar", BACKUPUP_FILE_PREFIX, xlsx_checksum);
+
+        let mut cmd = format!("cd {} && ", base);
+        cmd = format!("{} export file_name=\"{}/{}\" && ", cmd, BACKUPUP_TMP_DIR, backupset_file_name);
+        cmd = format!("{} if [ ! -e $file_name ]; then export file_name=\"{}/{}\"; fi && ", cmd, BACKUPUP_DIR, backupset_file_name);
+        cmd = format!("{} export LANG=en_US.utf8 && ", cmd);
+        cmd = format!("{} tar -xf $file_name && ", cmd);
+        cmd = format!("{} export file_count=$(cat bin/{} | wc -l) && ", cmd, BACKUPUP_SHA256SUM_FILENAME);
+        cmd = format!("{} sha256sum -c bin/{} | grep ': OK' | wc -l | awk -v c=$file_count '{{print $0==c}}'", cmd, BACKUPUP_SHA256SUM_FILENAME);
+        let stdout = self.exec_cmd(&cmd);
+        // 返回0，则代表有文件的sha256sum不一致；返回1，全部sha256sum通过
+        stdout.trim_end_matches("\n") == "1"
+    }
+
+    // 读取备份集中的文件内容，不解压
+    pub fn read_backupset_file(&self, base: &str, xlsx_checksum: &str, file_name: &str) -> Option<String> {
+        let backupset_file = format!("{}/{}-{}.tar", BACKUPUP_DIR, BACKUPUP_FILE_PREFIX, xlsx_checksum);
+        let (status, stdout, _) = self.exec_cmd_with_status(&format!("cd {} && tar -xOf {} {}", base, backupset_file, file_name));
+        let s = stdout.trim_end_matches("\n");
+        if status != 0 || s.is_empty() {
+            return None;
+        }
+        Some(s.to_string())
+    }
+
     // 列出远端备份集
     // cat $DBPS_HOME/monica.backupset/monica.backupset.index 
     pub fn list_remote_backupset(&self, dbps_home: &str) -> Vec<String> {
@@ -167,9 +261,23 @@ impl Client {
     }
 
     // 检查进程是否存在
-    pub fn check_valid_ps(&self, dir_prefix: &str) -> bool {
-        let stdout = self.exec_cmd(&format!("ps -ef --cols 10240 | grep \"{}\" | egrep -v 'grep' | wc -l", dir_prefix));
-        stdout != "0\n"
+    pub fn check_valid_ps(&self, role: usize, dbps_home: &str) -> bool {
+        !self.list_ps(role, dbps_home).is_empty()
+    }
+
+    // 任务的进程，按程序的完整路径精确匹配
+    pub fn list_ps(&self, role: usize, dbps_home: &str) -> Vec<Process> {
+        let stdout = self.exec_cmd(&ps::list_command(dbps_home));
+        ps::select(ps::parse(&stdout), role, dbps_home)
+    }
+
+    // 计算远端文件的sha256sum，返回 <相对路径, sha256sum>，不存在的文件不返回
+    pub fn sha256sum_files(&self, dbps_home: &str, files: &[String]) -> HashMap<String, String> {
+        let stdout = self.exec_cmd(&format!("cd {} && sha256sum {} 2>/dev/null", dbps_home, files.join(" ")));
+        stdout.lines()
+            .filter_map(|l| l.split_once(' '))
+            .map(|(sum, f)| (f.trim_start_matches([' ', '*']).to_string(), sum.to_string()))
+            .collect()
     }
 
     // 获取正在运行的jddm参数
@@ -179,19 +287,47 @@ impl Client {
     // }
 
     // 获取正在运行的jddm参数
-    pub fn write_jddm_starts_with(&self, dir_prefix: &str) -> bool {
-        let (status,_,_) = self.exec_cmd_with_status(&format!("ps -ef --cols 10240 | grep \"DPath={} \" | grep DPid=JDDM_ | grep -v grep | awk '{{print $NF}}' | head -1 > {}/{}", dir_prefix, dir_prefix, JDDM_START_WITH_FILE));
+    // 启动参数为 DPid=JDDM_* 进程的最后一个参数
+    pub fn write_jddm_starts_with(&self, dbps_home: &str) -> bool {
+        let starts_with = self.list_ps(ROLE_JDDM, dbps_home).into_iter()
+            .find(|p| p.property("Pid").is_some_and(|v| v.starts_with("JDDM_")))
+            .and_then(|p| p.args.last().cloned());
+        let starts_with = match starts_with {
+            Some(s) => s,
+            None => return false,
+        };
+        let (status,_,_) = self.exec_cmd_with_status(&format!("printf '%s\\n' '{}' > {}/{}", starts_with.replace('\'', "'\\''"), dbps_home, JDDM_START_WITH_FILE));
         status == 0
     }
 
-    // 检查进程是否存在
-    pub fn kill_ps(&self, dir_prefix: &str) -> (bool, bool) {
-        let starting = self.check_valid_ps(dir_prefix);
+    // 向任务的进程发送信号，如：TERM
+    pub fn signal_ps(&self, role: usize, dbps_home: &str, signal: &str) {
+        let pids: Vec<String> = self.list_ps(role, dbps_home).iter().map(|p| p.pid.to_string()).collect();
+        if pids.is_empty() {
+            return;
+        }
+        self.exec_cmd(&format!("kill -{} {}", signal, pids.join(" ")));
+    }
+
+    // 等待任务的进程退出，超时后返回 false
+    pub fn wait_ps_exit(&self, role: usize, dbps_home: &str, timeout: u64) -> bool {
+        for _ in 0..timeout {
+            if !self.check_valid_ps(role, dbps_home) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        }
+        !self.check_valid_ps(role, dbps_home)
+    }
+
+    // 强制停止任务的进程(SIGKILL)，返回 (停止前是否运行, 停止后是否仍在运行)
+    pub fn kill_ps(&self, role: usize, dbps_home: &str) -> (bool, bool) {
+        let starting = self.check_valid_ps(role, dbps_home);
         if !starting {
             return (starting, false);
         }
-        self.exec_cmd(&format!("ps -ef --cols 10240 | grep \"{}\" | egrep -v 'grep' | awk '{{print $2}}' | xargs kill -9", dir_prefix));
-        let starting2 = self.check_valid_ps(dir_prefix);
+        self.signal_ps(role, dbps_home, "9");
+        let starting2 = self.check_valid_ps(role, dbps_home);
         (starting, starting2)
     }
 
@@ -204,7 +340,7 @@ impl Client {
         let stdout = self.exec_cmd(&format!("ls {} | egrep \"^({}){}\"", base, dir_prefix, &s.service_name));
         let dir = stdout.trim_end_matches("\n");
         debug!("xlsx:Line: {:<2} Host: {}, Exec_ssh_cmd: {}", self.rid, self.host, dir);
-        if dir == "" {
+        if dir.is_empty() {
             None
         } else {
             Some(path_join(&base, dir))
@@ -213,19 +349,16 @@ impl Client {
     }
 
     pub fn ds_dbps_home(&self, s: &Server) -> Option<String> {
-        let s = self.dbps_home(s, &"ds_");
-        s
+        self.dbps_home(s, "ds_")
     }
 
     pub fn dt_dbps_home(&self, s: &Server) -> Option<String> {
-        if s.dst_type.is_none() {
-            return None;
-        }
+        let dst_type = s.dst_type.as_ref()?;
 
         // /data/dataxone/sync/<service_name>
         let base = path_join(&s.service_base_path, &s.service_name);
 
-        let cmd = if s.dst_type.clone().unwrap() == "KAFKA" {
+        let cmd = if dst_type == "KAFKA" {
             // oracle到kafka: dy_<service_name>
             // polardb到kafka: dt_<service_name>_y
             format!("ls {} | egrep \"^(dy)_{}|dt_{}_y\"", base, &s.service_name, &s.service_name)
@@ -238,7 +371,7 @@ impl Client {
         let stdout = self.exec_cmd(&cmd);
         let dir = stdout.trim_end_matches("\n");
         debug!("xlsx:Line: {:<2} Host: {}, Exec_ssh_cmd: {}", self.rid, self.host, dir);
-        if dir == "" {
+        if dir.is_empty() {
             return None
         }
 
@@ -247,8 +380,7 @@ impl Client {
     }
 
     pub fn jddm_home(&self, s: &Server) -> Option<String> {
-        let s = self.dbps_home(s, &"dt_");
-        s
+        self.dbps_home(s, "dt_")
     }
 
     pub fn is_file(&self, remote_file: &str) -> bool {
@@ -286,6 +418,15 @@ impl Client {
         }
     }
 
+    // 已安装的版本：在 $DBPS_HOME 下执行清单中的 version_command，未指定时按角色、类型读取
+    // 从输出中取第一个形如 5.2.1.20240601 的版本号
+    pub fn get_build_version(&self, role: usize, sd_type: &str, dbps_home: &str, manifest: &Manifest) -> Option<String> {
+        let command = manifest.version_command.clone().unwrap_or_else(|| version_command(role, sd_type, manifest));
+        let (_, stdout, _) = self.exec_cmd_with_status(&format!("cd {} && {{ {}; }} 2>&1", dbps_home, command));
+        debug!("xlsx:Line: {:<2} Host: {}, Exec_ssh_cmd: {}, {}", self.rid, self.host, command, stdout.trim_end());
+        parse_build_version(&stdout)
+    }
+
     // 将位点信息写入到备份文件中
     // 写入 $DBPS_HOME/bin/monica.yrba.dat
     pub fn write_log_pos(&self, dbps_home: &str, yrba: &str) -> Result<bool, String> {
@@ -314,6 +455,16 @@ impl Client {
     }
 
 
+    // 读取当前位点文件 $DBPS_HOME/rmp/yrba.dat
+    pub fn get_yrba(&self, dbps_home: &str) -> Option<String> {
+        let (status, stdout, _) = self.exec_cmd_with_status(&format!("cat {}/rmp/{}", dbps_home, YRBA_FILENAME));
+        let s = stdout.trim_end_matches("\n");
+        if status != 0 || s.is_empty() {
+            return None;
+        }
+        Some(s.to_string())
+    }
+
     // 向远程服务器发送文件
     pub fn scp_send(&mut self, file: PathBuf, rfile: PathBuf, current: usize, counter: usize) -> bool {
         let remote_file = rfile.to_string_lossy().to_string();
@@ -335,7 +486,7 @@ impl Client {
 
         let mut ch;
         loop {
-            match self.sess.scp_send(&remote_tmp_file, 0o755, file_size, None) {
+            match self.sess().scp_send(&remote_tmp_file, 0o755, file_size, None) {
                 Ok(c) => {
                     ch = c;
                     break;
@@ -380,9 +531,12 @@ impl Client {
 
         // 当前的时钟
         let mut clock = Local::now().timestamp();
+        // --max-bandwidth、--max-host-bandwidth 限速
+        let throttle = limit::Throttle::new(&self.host);
         // 16KB
         // 1.5M => 1M
         for chunk in buf.chunks(chunk_size) {
+            throttle.acquire(chunk.len());
             // called `Result::unwrap()` on an `Err` value: Custom { kind: Other, error: "Unable to send channel data" }
             // 网络不稳定会导致报错：error: "Unable to send channel data"
             match ch.write_all(chunk) {
@@ -455,9 +609,7 @@ impl Client {
 
         let mut try_count = 0;
         while try_count < total_try_count {
-            let _sess = connect_ssh(&self.s);
-            if _sess.is_some() {
-                self.sess = _sess.unwrap();
+            if self.reconnect() {
                 self.scp_send(file.clone(), rfile.clone(), current, counter);
                 break;
             }
@@ -468,9 +620,9 @@ impl Client {
             try_count += 1;
             if try_count == 10 {
                 // 超过重试次数
-                // 网络异常
-                println!("ERROR: Network not available, exceeding retry attempts, exit now.");
-                exit(-1);
+                // 网络异常，由文件校验标记当前行失败
+                error!("xlsx:Line: {:<2} Upload [{}/{}] file {} failed, Network not available, exceeding retry attempts.", self.rid, current, counter, local_file_name);
+                return false;
             }
         }
 
@@ -479,6 +631,148 @@ impl Client {
 
     }
 
+    // 同一主机上的多行共用远端缓存 $HOME/.monica/cache/<sha256sum>，每个文件只上传一次，再复制到各行的 DBPS_HOME
+    // 远端不支持SFTP时，直接上传到 DBPS_HOME
+    pub fn send_cached(&mut self, file: PathBuf, rfile: PathBuf, current: usize, counter: usize) -> bool {
+        if self.sess().sftp().is_err() {
+            return self.sftp_send(file, rfile, current, counter);
+        }
+
+        let checksum = file::sha256sum(file.clone());
+        let stdout = self.exec_cmd("echo $HOME");
+        let cache_dir = format!("{}/{}", stdout.trim_end_matches('\n'), REMOTE_CACHE_DIR);
+        let cache_file = format!("{}/{}", cache_dir, checksum);
+        let remote_file = rfile.to_string_lossy().to_string();
+
+        // 同一主机、同一文件同时只有一行上传，其他行等待后直接复制
+        let lock = cache_lock(&self.s, &checksum);
+        let _guard = lock.lock().unwrap();
+
+        let (status, _, _) = self.exec_cmd_with_status(&format!("test -f {}", cache_file));
+        if status == 0 {
+            info!("xlsx:Line: {:<2} Upload [{}/{}] {} found in host cache \"{}\"", self.rid, current, counter, file.file_name().unwrap().to_string_lossy(), cache_file);
+        } else {
+            self.exec_cmd(&format!("mkdir -p {}", cache_dir));
+            let _slot = limit::acquire_slot();
+            if !self.sftp_put(&file, &cache_file, &checksum, current, counter) {
+                return false;
+            }
+        }
+
+        // 复制到临时文件后再改名，避免程序正在使用/运行
+        let (_, _, stderr) = self.exec_cmd_with_status(&format!("cp {} {}.monica && chmod 755 {}.monica", cache_file, remote_file, remote_file));
+        if !stderr.is_empty() {
+            error!("xlsx:Line: {:<2} Host: {}, Copy \"{}\" from host cache failed, cause: {}", self.rid, self.host, remote_file, stderr);
+            return false;
+        }
+        self.write_sha256sum_to_file(file, rfile) & self.move_file(&format!("{}.monica", remote_file), &remote_file)
+    }
+
+    // 通过SFTP向远程服务器发送文件：从本地文件流式读取，写入远端临时文件 <file>.monica
+    // 网络中断后重新连接，按远端临时文件的大小从断点继续上传，完成后校验整个文件的sha256sum
+    // 远端不支持SFTP时，使用SCP上传
+    pub fn sftp_send(&mut self, file: PathBuf, rfile: PathBuf, current: usize, counter: usize) -> bool {
+        // 达到 --max-transfers 时等待其他上传完成
+        let _slot = limit::acquire_slot();
+
+        if let Err(e) = self.sess().sftp() {
+            error!("xlsx:Line: {:<2} SFTP not available, cause: {}, upload with SCP", self.rid, e);
+            return self.scp_send(file, rfile, current, counter);
+        }
+
+        let remote_file = rfile.to_string_lossy().to_string();
+        let checksum = file::sha256sum(file.clone());
+        // 写入sha256sum.txt文件
+        self.sftp_put(&file, &remote_file, &checksum, current, counter) && self.write_sha256sum_to_file(file, rfile)
+    }
+
+    // 上传到远端临时文件，校验通过后改名为 remote_file
+    fn sftp_put(&mut self, file: &PathBuf, remote_file: &str, local_checksum: &str, current: usize, counter: usize) -> bool {
+        let local_file_name = file.file_name().unwrap().to_string_lossy().to_string();
+        info!("xlsx:Line: {:<2} Upload [{}/{}] {} to \"{}\"", self.rid, current, counter, local_file_name, remote_file);
+
+        let remote_tmp_file = format!("{}.monica", remote_file);
+
+        // 断点续传的临时文件可能来自其他版本的文件，校验失败时从头重新上传一次
+        let mut restarted = false;
+        loop {
+            let mut try_count = 0;
+            let resumed = loop {
+                match self.sftp_upload(file, &remote_tmp_file, current, counter) {
+                    Ok(resumed) => break resumed,
+                    Err(e) => {
+                        try_count += 1;
+                        if try_count >= SSH_TOTAL_RETRY_COUNT {
+                            // 网络异常，由文件校验标记当前行失败
+                            error!("xlsx:Line: {:<2} Upload [{}/{}] file {} failed, cause: {}, exceeding retry attempts.", self.rid, current, counter, local_file_name, e);
+                            return false;
+                        }
+                        error!("xlsx:Line: {:<2} Upload [{}/{}] file {} interrupted, cause: {}, [{}/{}] resume after 3s.", self.rid, current, counter, local_file_name, e, try_count, SSH_TOTAL_RETRY_COUNT);
+                        std::thread::sleep(Duration::from_secs(3));
+                        self.reconnect();
+                    }
+                }
+            };
+
+            let stdout = self.exec_cmd(&format!("sha256sum {} | awk '{{print $1}}'", remote_tmp_file));
+            let remote_checksum = stdout.trim();
+            if remote_checksum == local_checksum {
+                break;
+            }
+            error!("xlsx:Line: {:<2} Upload [{}/{}] file {} SHA-256 mismatch, local: {}, remote: {}", self.rid, current, counter, local_file_name, local_checksum, remote_checksum);
+            self.exec_cmd(&format!("rm -f {}", remote_tmp_file));
+            if !resumed || restarted {
+                return false;
+            }
+            restarted = true;
+        }
+
+        self.move_file(&remote_tmp_file, remote_file)
+    }
+
+    // 上传到远端临时文件，返回是否从断点继续上传
+    fn sftp_upload(&self, file: &PathBuf, remote_tmp_file: &str, current: usize, counter: usize) -> Result<bool, String> {
+        let file_size = get_filesize(file);
+        let sftp = self.sess().sftp().map_err(|e| e.to_string())?;
+
+        // 远端临时文件的大小即为断点位置
+        let mut offset = match sftp.stat(Path::new(remote_tmp_file)) {
+            Ok(stat) => stat.size.unwrap_or(0),
+            Err(_) => 0,
+        };
+        if offset > file_size {
+            offset = 0;
+        }
+
+        let flags = if offset == 0 {
+            OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE
+        } else {
+            OpenFlags::WRITE
+        };
+        let mut remote = sftp.open_mode(Path::new(remote_tmp_file), flags, 0o755, OpenType::File).map_err(|e| e.to_string())?;
+        let mut f = File::open(file).map_err(|e| e.to_string())?;
+        if offset > 0 {
+            info!("xlsx:Line: {:<2} Upload [{}/{}] resume \"{}\" at {} bytes", self.rid, current, counter, remote_tmp_file, offset);
+            remote.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+            f.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+        }
+
+        let name = file.file_name().unwrap().to_string_lossy().to_string();
+        let mut progress = Progress::new(self.rid, current, counter, &name, file_size, offset);
+        let throttle = limit::Throttle::new(&self.host);
+        let mut buf = vec![0u8; get_chunk_size()];
+        loop {
+            let n = f.read(&mut buf).map_err(|e| e.to_string())?;
+            if n == 0 {
+                break;
+            }
+            throttle.acquire(n);
+            remote.write_all(&buf[..n]).map_err(|e| e.to_string())?;
+            progress.update(n);
+        }
+        Ok(offset > 0)
+    }
+
     // 计算本地文件的sha256sum，并写入远程目录文件
     // 写入 $DBPS_HOME/bin/
     fn write_sha256sum_to_file(&self, file: PathBuf, rfile: PathBuf) -> bool {
@@ -488,7 +782,7 @@ impl Client {
         // $DBPS_HOME/bin/monica.sha256sum.txt.tmp
         let remote_checksum_file = format!("{}/{}.tmp", remote_file_dir, BACKUPUP_SHA256SUM_FILENAME);
 
-        let (_, _, stderr) = self.exec_cmd_with_status(&format!("echo \"{} {}\" >> {}", local_file_checksum, rfile.to_string_lossy().to_string(), remote_checksum_file));
+        let (_, _, stderr) = self.exec_cmd_with_status(&format!("echo \"{} {}\" >> {}", local_file_checksum, rfile.to_string_lossy(), remote_checksum_file));
 
         if !stderr.is_empty() {
             error!("SHA-256sum file write failed, cause: {}", stderr);
@@ -519,7 +813,7 @@ impl Client {
 
         // $DBPS_HOME/bin/monica.sha256sum.txt.tmp
         let remote_checksum_file = format!("{}/bin/{}.tmp", base, BACKUPUP_SHA256SUM_FILENAME);
-        let mut cmd = format!("export LANG=en_US.utf8 && ");
+        let mut cmd = String::from("export LANG=en_US.utf8 && ");
         cmd = format!("{} export file_count=$(cat {} | wc -l) && ", cmd, remote_checksum_file);
         cmd = format!("{} sha256sum -c {} | grep ': OK' | wc -l | awk -v c=$file_count '{{print $0==c}}'", cmd, remote_checksum_file);
         let stdout = self.exec_cmd(&cmd);
@@ -531,6 +825,58 @@ impl Client {
 }
 
 
+// 上传进度：每秒输出一次速度及剩余时间
+struct Progress {
+    rid: usize,
+    current: usize,
+    counter: usize,
+    name: String,
+    file_size: u64,
+    bytes_send: usize, // 本次已发送字节数
+    total_bytes_send: u64, // 全部发送的字节数，含断点前已上传的部分
+    clock: i64,
+}
+
+impl Progress {
+
+    fn new(rid: usize, current: usize, counter: usize, name: &str, file_size: u64, offset: u64) -> Self {
+        Progress { rid, current, counter, name: name.to_string(), file_size, bytes_send: 0, total_bytes_send: offset, clock: Local::now().timestamp() }
+    }
+
+    fn update(&mut self, n: usize) {
+        self.bytes_send += n;
+        self.total_bytes_send += n as u64;
+
+        let file_kb = self.file_size as f64 / 1024.0;
+        let col_size = format!("{:.0}", file_kb).len();
+        let p = (self.total_bytes_send as f64 * 100.0 / self.file_size as f64).floor();
+        let total_send_kb = (self.total_bytes_send as f64 / 1024.0).ceil();
+
+        if self.total_bytes_send >= self.file_size {
+            let speed_kb: f64 = (self.bytes_send as f64 / 1024.0).ceil();
+            info!("xlsx:Line: {:<2} TX [{}/{}] {} {:>3.0}% {:col_size$.0}KiB / {:.0}KiB {:col_size$.0}KiB/s Done", self.rid, self.current, self.counter, self.name, p, total_send_kb, file_kb, speed_kb);
+            return;
+        }
+
+        let duration_sec = Local::now().timestamp() - self.clock;
+        if duration_sec > 0 {
+            let speed_kb = (self.bytes_send as f64 / 1024.0 / duration_sec as f64).ceil().max(1.0);
+            let eta = eta_format(((file_kb - total_send_kb) / speed_kb) as u64);
+            info!("xlsx:Line: {:<2} TX [{}/{}] {} {:>3.0}% {:col_size$.0}KiB / {:.0}KiB {:col_size$.0}KiB/s {} ETA", self.rid, self.current, self.counter, self.name, p, total_send_kb, file_kb, speed_kb, eta);
+            self.clock = Local::now().timestamp();
+            self.bytes_send = 0;
+        }
+    }
+
+}
+
+fn cache_lock(s: &Server, checksum: &str) -> Arc<Mutex<()>> {
+    CACHE_LOCKS.lock().unwrap()
+        .entry(format!("{}@{}:{}/{}", s.username, s.hostname, s.port, checksum))
+        .or_default()
+        .clone()
+}
+
 fn eta_format(secs: u64) -> String {
     let remaining_seconds = secs % 60;
     let minutes = (secs % 3600) / 60;
@@ -539,14 +885,54 @@ fn eta_format(secs: u64) -> String {
     s
 }
 
+// 读取已安装版本的命令
+// ds/dt：bin/pmon -v，ORACLE 类型没有 pmon 时为 bin/xagentd -v
+// jddm 没有 pmon：读取清单中 jar 包(没有时为 lib/*.jar)的 Implementation-Version，其次为 jar 包文件名中的版本，如：jddm-5.2.1.20240601.jar
+fn version_command(role: usize, sd_type: &str, manifest: &Manifest) -> String {
+    match role {
+        ROLE_JDDM => {
+            let jars: Vec<&str> = manifest.file.iter().map(String::as_str).filter(|f| f.ends_with(".jar")).collect();
+            let jars = if jars.is_empty() { String::from("lib/*.jar") } else { jars.join(" ") };
+            format!("for f in {}; do [ -f \"$f\" ] && unzip -p \"$f\" META-INF/MANIFEST.MF 2>/dev/null | grep -i '^Implementation-Version:'; done; \
+                for f in {}; do [ -f \"$f\" ] && basename \"$f\" .jar | tr '_-' '  '; done", jars, jars)
+        },
+        _ if sd_type == "ORACLE" => format!("if [ -x bin/pmon ]; then {}; else {}; fi", PMON_VERSION_COMMAND, XAGENTD_VERSION_COMMAND),
+        _ => PMON_VERSION_COMMAND.to_string(),
+    }
+}
+
+// 版本号：以数字开头且包含 . 的第一个单词，忽略前缀 v 及两侧的标点
+fn parse_build_version(output: &str) -> Option<String> {
+    output.split_whitespace()
+        .map(|w| w.trim_matches(|c: char| !c.is_ascii_alphanumeric()).trim_start_matches(['v', 'V']))
+        .find(|w| w.starts_with(|c: char| c.is_ascii_digit()) && w.contains('.'))
+        .map(String::from)
+}
+
 fn get_index_file() -> String {
     let index_file = format!("{}/{}", BACKUPUP_DIR, config::BACKUPUP_INDEX_FILENAME);
     index_file
 }
 
+// 新建连接，指定跳板机时通过跳板机转发
+fn open_tcp(s: &Server) -> Result<TcpStream, String> {
+    match s.jump_host.clone().or_else(config::get_jump_host) {
+        Some(jump_host) => jump::connect(s, &jump_host),
+        None => TcpStream::connect(format!("{}:{}", s.hostname, s.port)).map_err(|e| e.to_string()),
+    }
+}
+
+// 只握手不认证，将目标主机的主机密钥固定到 known_hosts，返回 (密钥类型, 指纹, 结果)
+pub fn trust_host(s: &Server) -> Result<(String, String, &'static str), String> {
+    let tcp = open_tcp(s)?;
+    let mut sess = Session::new().map_err(|e| e.to_string())?;
+    sess.set_tcp_stream(tcp);
+    sess.handshake().map_err(|e| format!("Server handshake failed, cause: {}", e))?;
+    hostkey::pin(&sess, s.rid, &s.hostname, &s.port)
+}
+
 fn connect_ssh(s: &Server) -> Option<Session> {
-    // 新建连接
-    let tcp = match TcpStream::connect(format!("{}:{}", s.hostname, s.port)) {
+    let tcp = match open_tcp(s) {
         Ok(tcp) => tcp,
         Err(e) => {
             // 无法链接到对应的端口
@@ -563,20 +949,125 @@ fn connect_ssh(s: &Server) -> Option<Session> {
             return None;
         }
     }
-    let pwd = &s.password.clone().unwrap();
-    match sess.userauth_password(&s.username, pwd) {
-        Ok(()) => {},
-        Err(e) => {
-            error!("xlsx:Line: {:<2} Host: {}:{}, Server auth failed, cause: {}", s.rid, s.hostname, s.port, e);
-            return None;
-        }
+    // 认证前检查主机密钥，避免将密码发送给冒充的主机
+    if let Err(e) = hostkey::verify(&sess, s.rid, &s.hostname, &s.port) {
+        error!("xlsx:Line: {:<2} Host: {}:{}, Host key verification failed, cause: {}", s.rid, s.hostname, s.port, e);
+        return None;
+    }
+    if let Err(e) = userauth(&sess, s) {
+        error!("xlsx:Line: {:<2} Host: {}:{}, Server auth failed, cause: {}", s.rid, s.hostname, s.port, e);
+        return None;
     }
     info!("xlsx:Line: {:<2} Connected to server {}:{}", s.rid, s.hostname, s.port);
     if let Err(e) = sess.set_banner("monica") {
         info!("xlsx:Line: {:<2} Update server {}:{} set_banner error, cause: {}", s.rid, s.hostname, s.port, e);
     }
-    // 
-    // sess.set_keepalive(true, SSH_KEEPALIVE_INTERVAL as u32);
+    // 由连接池定时发送 keepalive
+    sess.set_keepalive(false, SSH_KEEPALIVE_INTERVAL as u32);
     
     Some(sess)
-}
\ No newline at end of file
+}
+
+
+// 认证方式：行配置优先，其次为全局参数；都未指定时依次尝试所有可用的认证方式
+fn auth_method_list(s: &Server) -> Vec<&'static str> {
+    let method = s.auth_method.clone().or_else(config::get_auth_method);
+    if let Some(m) = method {
+        return AUTH_METHODS.iter().filter(|x| **x == m).copied().collect();
+    }
+
+    let mut methods = Vec::new();
+    if s.private_key.is_some() || config::get_private_key().is_some() {
+        methods.push(AUTH_PUBLICKEY);
+    }
+    methods.push(AUTH_AGENT);
+    if s.password.is_some() {
+        methods.push(AUTH_PASSWORD);
+        methods.push(AUTH_KEYBOARD_INTERACTIVE);
+    }
+    methods
+}
+
+// 依次尝试认证，全部失败时返回每种方式的失败原因
+fn userauth(sess: &Session, s: &Server) -> Result<(), String> {
+    // 服务端允许的认证方式，如：publickey,password,keyboard-interactive
+    let offered = sess.auth_methods(&s.username).map(String::from).unwrap_or_default();
+    if sess.authenticated() {
+        return Ok(());
+    }
+
+    let mut causes = Vec::new();
+    for method in auth_method_list(s) {
+        if !offered.is_empty() && !offered.split(',').any(|m| m == method) {
+            causes.push(format!("{}: not allowed by server", method));
+            continue;
+        }
+        let result = match method {
+            AUTH_PUBLICKEY => userauth_pubkey(sess, s),
+            AUTH_AGENT => userauth_agent(sess, &s.username),
+            AUTH_PASSWORD => match &s.password {
+                Some(pwd) => vault::resolve(pwd).and_then(|pwd| sess.userauth_password(&s.username, &pwd).map_err(|e| e.to_string())),
+                None => Err(String::from("password is empty")),
+            },
+            AUTH_KEYBOARD_INTERACTIVE => match &s.password {
+                Some(pwd) => vault::resolve(pwd).and_then(|pwd| sess.userauth_keyboard_interactive(&s.username, &mut PasswordPrompt(&pwd)).map_err(|e| e.to_string())),
+                None => Err(String::from("password is empty")),
+            },
+            _ => Err(String::from("unsupported")),
+        };
+        match result {
+            Ok(()) if sess.authenticated() => {
+                debug!("xlsx:Line: {:<2} Host: {}:{}, Authenticated with {}", s.rid, s.hostname, s.port, method);
+                return Ok(());
+            },
+            Ok(()) => causes.push(format!("{}: partial authentication", method)),
+            Err(e) => causes.push(format!("{}: {}", method, e)),
+        }
+    }
+
+    if causes.is_empty() {
+        causes.push(String::from("no authentication method available"));
+    }
+    Err(format!("all authentication methods failed [{}]", causes.join("; ")))
+}
+
+fn userauth_pubkey(sess: &Session, s: &Server) -> Result<(), String> {
+    let private_key = match s.private_key.clone().or_else(config::get_private_key) {
+        Some(k) => k,
+        None => return Err(String::from("private key file is empty")),
+    };
+    let passphrase = match s.passphrase.clone().or_else(config::get_passphrase) {
+        Some(p) => Some(vault::resolve(&p)?),
+        None => None,
+    };
+    sess.userauth_pubkey_file(&s.username, None, Path::new(&private_key), passphrase.as_deref())
+        .map_err(|e| format!("{}, {}", private_key, e))
+}
+
+// 依次尝试ssh-agent中的所有密钥
+fn userauth_agent(sess: &Session, username: &str) -> Result<(), String> {
+    let mut agent = sess.agent().map_err(|e| e.to_string())?;
+    agent.connect().map_err(|e| e.to_string())?;
+    agent.list_identities().map_err(|e| e.to_string())?;
+    let identities = agent.identities().map_err(|e| e.to_string())?;
+    if identities.is_empty() {
+        return Err(String::from("no identities found in the ssh agent"));
+    }
+    let mut cause = String::new();
+    for identity in identities.iter() {
+        match agent.userauth(username, identity) {
+            Ok(()) => return Ok(()),
+            Err(e) => cause = e.to_string(),
+        }
+    }
+    Err(cause)
+}
+
+// keyboard-interactive：所有提示均以密码作答
+struct PasswordPrompt<'a>(&'a str);
+
+impl KeyboardInteractivePrompt for PasswordPrompt<'_> {
+    fn prompt<'b>(&mut self, _username: &str, _instructions: &str, prompts: &[Prompt<'b>]) -> Vec<String> {
+        prompts.iter().map(|_| self.0.to_string()).collect()
+    }
+}
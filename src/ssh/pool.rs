@@ -0,0 +1,69 @@
+use std::{collections::HashMap, sync::{Arc, Mutex, Once}, thread, time::Duration};
+
+use lazy_static::lazy_static;
+use log::debug;
+use ssh2::Session;
+
+use crate::config::Server;
+
+use super::{connect_ssh, SSH_KEEPALIVE_INTERVAL};
+
+lazy_static! {
+    // 已建立的会话，key: <user>@<host>:<port>
+    static ref SESSIONS: Mutex<HashMap<String, Arc<Mutex<Option<Session>>>>> = Mutex::new(HashMap::new());
+}
+
+static KEEPALIVE: Once = Once::new();
+
+fn key(s: &Server) -> String {
+    format!("{}@{}:{}", s.username, s.hostname, s.port)
+}
+
+fn entry(s: &Server) -> Arc<Mutex<Option<Session>>> {
+    SESSIONS.lock().unwrap().entry(key(s)).or_default().clone()
+}
+
+// 获取会话：同一主机、端口、用户在 precheck、backup、patch 之间复用同一个会话，会话已断开时重新连接
+pub fn get(s: &Server) -> Option<Session> {
+    KEEPALIVE.call_once(|| {
+        thread::spawn(keepalive);
+    });
+
+    let entry = entry(s);
+    let mut sess = entry.lock().unwrap();
+    if let Some(current) = sess.as_ref() {
+        if alive(current) {
+            debug!("xlsx:Line: {:<2} Reuse session {}", s.rid, key(s));
+            return Some(current.clone());
+        }
+    }
+    *sess = connect_ssh(s);
+    sess.clone()
+}
+
+// 能打开通道的会话才能复用
+fn alive(sess: &Session) -> bool {
+    match sess.channel_session() {
+        Ok(mut channel) => {
+            let _ = channel.close();
+            true
+        },
+        Err(_) => false,
+    }
+}
+
+// 定时发送 keepalive，避免空闲的会话被服务端或防火墙断开
+fn keepalive() {
+    loop {
+        thread::sleep(Duration::from_secs(SSH_KEEPALIVE_INTERVAL as u64));
+        let entries: Vec<Arc<Mutex<Option<Session>>>> = SESSIONS.lock().unwrap().values().cloned().collect();
+        for entry in entries {
+            // 正在连接的会话跳过
+            if let Ok(sess) = entry.try_lock() {
+                if let Some(sess) = sess.as_ref() {
+                    let _ = sess.keepalive_send();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::{collections::BTreeMap, env, fs, io::{self, Write}, path::Path, sync::Mutex};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, ChaCha20Poly1305, Key, Nonce};
+use dialoguer::{theme::ColorfulTheme, Password};
+use lazy_static::lazy_static;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::config;
+
+// 未设置时提示输入密码库口令
+pub const VAULT_PASSPHRASE_ENV: &str = "MONICA_VAULT_PASSPHRASE";
+
+const VAULT_VERSION: u32 = 2;
+const VAULT_ITERATIONS: u32 = 600_000;
+// 文件中的迭代次数：过小时口令易被穷举，过大时打开密码库耗时过长
+const VAULT_MIN_ITERATIONS: u32 = 100_000;
+const VAULT_MAX_ITERATIONS: u32 = 10_000_000;
+
+lazy_static! {
+    // 已解密的密码库，首次使用时打开
+    static ref SECRETS: Mutex<Option<BTreeMap<String, String>>> = Mutex::new(None);
+    // ${PROMPT} 输入的密码，同一名称只提示一次
+    static ref PROMPTS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+}
+
+// 密码库文件：口令经 PBKDF2-HMAC-SHA256 派生密钥，内容以 ChaCha20-Poly1305 加密
+// version、iterations、salt 作为附加数据参与校验
+#[derive(Deserialize, Serialize)]
+struct VaultFile {
+    version: u32,
+    iterations: u32,
+    salt: String,
+    nonce: String,
+    data: String,
+}
+
+// 密码引用，连接时解析：
+// ${ENV:NAME}     环境变量
+// ${VAULT:NAME}   密码库
+// ${PROMPT}       提示输入，${PROMPT:NAME} 按名称分别提示
+// 其他值原样返回
+pub fn resolve(value: &str) -> Result<String, String> {
+    let reference = match value.strip_prefix("${").and_then(|v| v.strip_suffix('}')) {
+        Some(r) => r,
+        None => return Ok(value.to_string()),
+    };
+    let (kind, name) = match reference.split_once(':') {
+        Some((k, n)) => (k, n),
+        None => (reference, ""),
+    };
+    match kind {
+        "ENV" => env::var(name).map_err(|_| format!("environment variable {} not set", name)),
+        "VAULT" => {
+            let mut secrets = SECRETS.lock().unwrap();
+            if secrets.is_none() {
+                *secrets = Some(open(&config::get_vault_file(), &passphrase(false)?)?);
+            }
+            secrets.as_ref().unwrap().get(name).cloned().ok_or(format!("{} not found in vault {}", name, config::get_vault_file()))
+        },
+        "PROMPT" => {
+            let mut prompts = PROMPTS.lock().unwrap();
+            if let Some(v) = prompts.get(name) {
+                return Ok(v.clone());
+            }
+            let label = if name.is_empty() { String::from("Password") } else { format!("Password for {}", name) };
+            let v = prompt(&label, false)?;
+            prompts.insert(name.to_string(), v.clone());
+            Ok(v)
+        },
+        _ => Ok(value.to_string()),
+    }
+}
+
+fn prompt(label: &str, confirm: bool) -> Result<String, String> {
+    let theme = ColorfulTheme::default();
+    let mut p = Password::with_theme(&theme).with_prompt(label);
+    if confirm {
+        p = p.with_confirmation("Repeat", "Values do not match");
+    }
+    p.interact().map_err(|e| e.to_string())
+}
+
+// 密码库口令：优先读取环境变量 MONICA_VAULT_PASSPHRASE
+fn passphrase(confirm: bool) -> Result<String, String> {
+    match env::var(VAULT_PASSPHRASE_ENV) {
+        Ok(p) => Ok(p),
+        Err(_) => prompt("Vault passphrase", confirm),
+    }
+}
+
+fn cipher(passphrase: &str, salt: &[u8], iterations: u32) -> ChaCha20Poly1305 {
+    let mut key = Key::default();
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    ChaCha20Poly1305::new(&key)
+}
+
+fn associated_data(iterations: u32, salt: &[u8]) -> Vec<u8> {
+    let mut aad = Vec::new();
+    aad.extend_from_slice(&VAULT_VERSION.to_be_bytes());
+    aad.extend_from_slice(&iterations.to_be_bytes());
+    aad.extend_from_slice(salt);
+    aad
+}
+
+fn decode(field: &str, value: &str) -> Result<Vec<u8>, String> {
+    STANDARD.decode(value).map_err(|e| format!("invalid {}: {}", field, e))
+}
+
+// 打开密码库，文件不存在时返回空的密码库
+pub fn open(file: &str, passphrase: &str) -> Result<BTreeMap<String, String>, String> {
+    if !Path::new(file).exists() {
+        return Ok(BTreeMap::new());
+    }
+    let contents = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+    let v: VaultFile = serde_json::from_str(&contents).map_err(|e| format!("{}: {}", file, e))?;
+    if v.version != VAULT_VERSION {
+        return Err(format!("{}: unsupported vault version {}, recreate it with monica vault --set", file, v.version));
+    }
+    if !(VAULT_MIN_ITERATIONS..=VAULT_MAX_ITERATIONS).contains(&v.iterations) {
+        return Err(format!("{}: iterations {} out of range {}-{}", file, v.iterations, VAULT_MIN_ITERATIONS, VAULT_MAX_ITERATIONS));
+    }
+    let salt = decode("salt", &v.salt)?;
+    let nonce = decode("nonce", &v.nonce)?;
+    let data = decode("data", &v.data)?;
+    if nonce.len() != 12 {
+        return Err(format!("{}: invalid nonce", file));
+    }
+
+    let data = cipher(passphrase, &salt, v.iterations)
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: &data, aad: &associated_data(v.iterations, &salt) })
+        .map_err(|_| format!("{}: wrong passphrase or the file was modified", file))?;
+    serde_json::from_slice(&data).map_err(|e| format!("{}: {}", file, e))
+}
+
+// 保存密码库，每次保存使用新的 salt 及 nonce
+pub fn save(file: &str, passphrase: &str, secrets: &BTreeMap<String, String>) -> Result<(), String> {
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let plaintext = serde_json::to_vec(secrets).unwrap();
+    let data = cipher(passphrase, &salt, VAULT_ITERATIONS)
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: &plaintext, aad: &associated_data(VAULT_ITERATIONS, &salt) })
+        .map_err(|e| format!("{}: {}", file, e))?;
+
+    let v = VaultFile {
+        version: VAULT_VERSION,
+        iterations: VAULT_ITERATIONS,
+        salt: STANDARD.encode(salt),
+        nonce: STANDARD.encode(nonce),
+        data: STANDARD.encode(data),
+    };
+    if let Some(dir) = Path::new(file).parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    write_private(file, &serde_json::to_string_pretty(&v).unwrap()).map_err(|e| format!("{}: {}", file, e))
+}
+
+// 密码库文件只允许当前用户读写，已存在的文件也改为 0600
+#[cfg(unix)]
+fn write_private(file: &str, contents: &str) -> io::Result<()> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let mut f = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(file)?;
+    f.set_permissions(fs::Permissions::from_mode(0o600))?;
+    f.write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(file: &str, contents: &str) -> io::Result<()> {
+    fs::File::create(file)?.write_all(contents.as_bytes())
+}
+
+// monica vault：--set 保存，--remove 删除，否则列出已保存的名称
+pub fn handle_command_vault(set: Option<String>, remove: Option<String>) -> Result<(), String> {
+    let file = config::get_vault_file();
+    let new_vault = !Path::new(&file).exists();
+    let passphrase = passphrase(new_vault)?;
+    let mut secrets = open(&file, &passphrase)?;
+
+    if let Some(name) = set {
+        let value = prompt(&format!("Value for {}", name), true)?;
+        secrets.insert(name.clone(), value);
+        save(&file, &passphrase, &secrets)?;
+        println!("{} saved to {}", name, file);
+    } else if let Some(name) = remove {
+        if secrets.remove(&name).is_none() {
+            return Err(format!("{} not found in vault {}", name, file));
+        }
+        save(&file, &passphrase, &secrets)?;
+        println!("{} removed from {}", name, file);
+    } else {
+        println!("Vault file: {}", file);
+        for name in secrets.keys() {
+            println!("  {}", name);
+        }
+    }
+    Ok(())
+}